@@ -0,0 +1,105 @@
+use std::cell::{Cell, OnceCell};
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// limits for deciding one candidate in step3, `None` is unlimited\
+/// a candidate, which reaches a limit, is undecided and not treated as "does not fit"
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CandidateBudget {
+    pub max_duration: Option<Duration>,
+    /// orders and orientations tried by the heuristic solver
+    pub max_permutations: Option<u64>,
+    /// moves of `step3_check_permutation` towards (0, 0), for all orders together
+    pub max_compaction_steps: Option<u64>,
+}
+
+impl Display for CandidateBudget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let limit = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+        write!(f, "time {}, permutations {}, compaction_steps {}",
+               limit(self.max_duration.map(|d| format!("{}s", d.as_secs_f64()))),
+               limit(self.max_permutations.map(|p| p.to_string())),
+               limit(self.max_compaction_steps.map(|c| c.to_string())))
+    }
+}
+
+/// how much of the budget one candidate used, shared by the solver and the placement strategies\
+/// once a limit is reached, every further step is refused
+pub(crate) struct BudgetTracker {
+    budget: CandidateBudget,
+    start: Instant,
+    permutations: Cell<u64>,
+    compaction_steps: Cell<u64>,
+    exhausted: OnceCell<String>,
+}
+
+impl BudgetTracker {
+    pub(crate) fn new(budget: CandidateBudget) -> BudgetTracker {
+        BudgetTracker {
+            budget,
+            start: Instant::now(),
+            permutations: Cell::new(0),
+            compaction_steps: Cell::new(0),
+            exhausted: OnceCell::new(),
+        }
+    }
+
+    /// without any limits, for checking single orders by hand
+    pub(crate) fn unlimited() -> BudgetTracker {
+        BudgetTracker::new(CandidateBudget::default())
+    }
+
+    /// the limit, which was reached
+    pub(crate) fn exhausted(&self) -> Option<&str> {
+        self.exhausted.get().map(String::as_str)
+    }
+
+    fn exceed(&self, reason: String) -> bool {
+        let _ = self.exhausted.set(reason);
+        false
+    }
+
+    /// false if the time is up
+    pub(crate) fn within_time(&self) -> bool {
+        match self.budget.max_duration {
+            _ if self.exhausted.get().is_some() => false,
+            Some(max) if self.start.elapsed() > max => self.exceed(format!("time limit of {} s reached", max.as_secs_f64())),
+            _ => true,
+        }
+    }
+
+    /// count one more order to try, false if it is over the budget
+    pub(crate) fn permutation(&self) -> bool {
+        self.permutations.set(self.permutations.get() + 1);
+        match self.budget.max_permutations {
+            Some(max) if self.permutations.get() > max => self.exceed(format!("permutation limit of {max} reached")),
+            _ => self.within_time(),
+        }
+    }
+
+    /// count one more compaction move, false if it is over the budget
+    pub(crate) fn compaction_step(&self) -> bool {
+        self.compaction_steps.set(self.compaction_steps.get() + 1);
+        match self.budget.max_compaction_steps {
+            Some(max) if self.compaction_steps.get() > max => self.exceed(format!("compaction limit of {max} steps reached")),
+            _ => self.within_time(),
+        }
+    }
+}
+
+#[test]
+fn test_budget_tracker() {
+    let tracker = BudgetTracker::new(CandidateBudget { max_permutations: Some(2), ..Default::default() });
+    assert!(tracker.permutation() && tracker.permutation() && tracker.compaction_step());
+    assert!(!tracker.permutation());
+    // the first reached limit stays the reason and nothing is allowed anymore
+    assert!(!tracker.compaction_step() && !tracker.within_time());
+    assert_eq!(tracker.exhausted(), Some("permutation limit of 2 reached"));
+
+    let tracker = BudgetTracker::new(CandidateBudget { max_duration: Some(Duration::ZERO), max_compaction_steps: Some(5), ..Default::default() });
+    std::thread::sleep(Duration::from_millis(1));
+    assert!(!tracker.compaction_step());
+    assert_eq!(tracker.exhausted(), Some("time limit of 0 s reached"));
+    assert!(BudgetTracker::unlimited().permutation());
+    assert_eq!(CandidateBudget { max_permutations: Some(7), ..Default::default() }.to_string(), "time -, permutations 7, compaction_steps -");
+}
@@ -1,15 +1,73 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use itertools::Itertools;
 
-use crate::rect::{RecId, Rectangle};
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::rect::{Combination, PlacedRectangle, RecDimension, RecId, Rectangle};
+use crate::resolution::Resolution;
 
+/// id of the rectangles, which describe blocked zones inside the big rect
+pub(crate) const BLOCKED_ZONE_ID: RecId = 0;
+
+/// number of layers in the box
+pub(crate) const LAYER_COUNT: u8 = 3;
+
+/// what is already known about the place of a piece in the final solution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Pin {
+    /// 0 is the bottom layer
+    pub layer: Option<u8>,
+    /// upper left corner of the piece
+    pub position: Option<(RecDimension, RecDimension)>,
+    /// true if height and width are swapped compared to the configuration
+    pub rotated: Option<bool>,
+}
+
+/// which pieces share a layer
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LayerConstraint {
+    /// all of these pieces are in the same layer
+    Together(Vec<RecId>),
+    /// no two of these pieces are in the same layer
+    Apart(Vec<RecId>),
+    /// all of these pieces are in this layer, 0 is the bottom layer
+    InLayer(Vec<RecId>, u8),
+}
+
+impl LayerConstraint {
+    fn ids(&self) -> &Vec<RecId> {
+        match self {
+            LayerConstraint::Together(ids) | LayerConstraint::Apart(ids) | LayerConstraint::InLayer(ids, _) => ids,
+        }
+    }
+
+    /// check a single layer, layers are only known for pinned pieces
+    fn allows_layer(&self, combination: &Combination) -> bool {
+        let contained = self.ids().iter().filter(|id| combination.iter().any(|r| r.id == **id)).count();
+        match self {
+            LayerConstraint::Together(ids) => contained == 0 || contained == ids.len(),
+            LayerConstraint::Apart(_) => contained <= 1,
+            LayerConstraint::InLayer(..) => true,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct RectConfiguration {
     pub big_rect: Rectangle,
+    /// parts of the big rect, where no rectangle may be placed (inserts, chamfered corners, dividers...)
+    pub blocked_zones: Vec<PlacedRectangle>,
     pub available_blocks: Vec<Rectangle>,
     pub available_block_map: HashMap<RecId, Rectangle>,
-    pub rotated_available_block_map: HashMap<RecId, HashSet<Rectangle>>,
+    pub rotated_available_block_map: HashMap<RecId, BTreeSet<Rectangle>>,
     pub duplication_map: HashMap<RecId, Vec<Rectangle>>,
+    /// pinned pieces, these are never exchanged with their duplicates
+    pub pins: BTreeMap<RecId, Pin>,
+    pub layer_constraints: Vec<LayerConstraint>,
+    /// size of one grid step in the measured unit
+    pub resolution: Resolution,
 }
 
 impl RectConfiguration {
@@ -17,12 +75,15 @@ impl RectConfiguration {
         let block_map: HashMap<RecId, Rectangle> = available_blocks.iter().map(|b| (b.id, *b)).collect();
         RectConfiguration {
             big_rect,
+            blocked_zones: vec![],
             duplication_map: available_blocks.iter()
                 .map(
                     |r| (
                         r.id,
                         available_blocks.iter()
-                            .filter(|r1| r.dedup() == r1.dedup())
+                            .filter(|r1| r.dedup() == r1.dedup() && r.rotatable == r1.rotatable)
+                            // pieces which keep their orientation are only interchangeable in the same orientation
+                            .filter(|r1| r.rotatable || r.dedup_permutation() == r1.dedup_permutation())
                             .copied()
                             .sorted_by_key(|r| r.id)
                             .collect()
@@ -31,10 +92,237 @@ impl RectConfiguration {
             rotated_available_block_map: available_blocks.iter().map(|r| (r.id, r.get_possible_orientations(&big_rect))).collect(),
             available_blocks,
             available_block_map: block_map,
+            pins: BTreeMap::new(),
+            layer_constraints: vec![],
+            resolution: Resolution::default(),
+        }
+    }
+
+    #[allow(dead_code)]
+    /// configuration from measured decimal dimensions (height, width) in `unit`\
+    /// the solver uses the coarsest integer grid, on which all dimensions are exact
+    pub(crate) fn from_decimal(unit: &str, big_rect: (&str, &str), blocks: &[(RecId, &str, &str)]) -> PuzzleResult<RectConfiguration> {
+        let values = blocks.iter().flat_map(|(_, h, w)| [*h, *w]).chain([big_rect.0, big_rect.1]).collect::<Vec<_>>();
+        let resolution = Resolution::for_values(unit, &values)?;
+        let available_blocks = blocks.iter()
+            .map(|(id, h, w)| Ok(Rectangle::new(*id, resolution.to_grid(h)?, resolution.to_grid(w)?)))
+            .collect::<PuzzleResult<Vec<Rectangle>>>()?;
+        let big_rect = Rectangle::new(-1, resolution.to_grid(big_rect.0)?, resolution.to_grid(big_rect.1)?);
+        Ok(RectConfiguration { resolution, ..RectConfiguration::new(big_rect, available_blocks) })
+    }
+
+    #[allow(dead_code)]
+    /// container with the shape of a rectilinear polygon, given by its corners in order\
+    /// the big rect is the bounding box of the polygon, everything outside of the polygon is blocked
+    pub(crate) fn from_polygon(outline: &[(RecDimension, RecDimension)], available_blocks: Vec<Rectangle>) -> RectConfiguration {
+        let width = outline.iter().map(|(x, _)| *x).max().unwrap_or(0);
+        let height = outline.iter().map(|(_, y)| *y).max().unwrap_or(0);
+        let xs = outline.iter().map(|(x, _)| *x).chain([0, width]).sorted().dedup().collect::<Vec<_>>();
+        let ys = outline.iter().map(|(_, y)| *y).chain([0, height]).sorted().dedup().collect::<Vec<_>>();
+        // split the bounding box along all corners, each cell is either completely inside or outside
+        let mut zones: Vec<PlacedRectangle> = vec![];
+        for (y0, y1) in ys.iter().tuple_windows() {
+            let mut row: Vec<PlacedRectangle> = vec![];
+            for (x0, x1) in xs.iter().tuple_windows() {
+                if point_in_polygon(outline, x0 + x1, y0 + y1) {
+                    continue;
+                }
+                match row.last_mut() {
+                    Some(last) if last.x + last.rect.width == *x0 => last.rect = Rectangle::new(BLOCKED_ZONE_ID, y1 - y0, x1 - last.x),
+                    _ => row.push(blocked_zone(*x0, *y0, y1 - y0, x1 - x0)),
+                }
+            }
+            // merge with the row above, if a zone covers the same columns
+            for zone in row {
+                match zones.iter_mut().find(|z| z.x == zone.x && z.rect.width == zone.rect.width && z.y + z.rect.height == zone.y) {
+                    Some(above) => above.rect = Rectangle::new(BLOCKED_ZONE_ID, above.rect.height + zone.rect.height, zone.rect.width),
+                    None => zones.push(zone),
+                }
+            }
         }
+        // the cells are disjoint and inside of the bounding box
+        RectConfiguration::new(Rectangle::new(-1, height, width), available_blocks).add_blocked_zones(zones)
+    }
+
+    #[allow(dead_code)]
+    /// add blocked zones to this configuration, orientations which can not be placed anywhere anymore are removed\
+    /// the zones must not be empty, stick out of the big rect or overlap each other
+    pub(crate) fn with_blocked_zones(self, zones: Vec<PlacedRectangle>) -> PuzzleResult<RectConfiguration> {
+        let overlap = |a: &PlacedRectangle, b: &PlacedRectangle|
+            a.x < b.x + b.rect.width && b.x < a.x + a.rect.width && a.y < b.y + b.rect.height && b.y < a.y + a.rect.height;
+        for (i, zone) in zones.iter().enumerate() {
+            let invalid = |reason: &str| PuzzleError::InvalidBlockedZone { zone: (zone.x, zone.y, zone.rect.height, zone.rect.width), reason: reason.to_owned() };
+            if zone.rect.area == 0 {
+                return Err(invalid("it is empty"));
+            }
+            let inside = |start: RecDimension, length: RecDimension, size: RecDimension| start.checked_add(length).is_some_and(|end| end <= size);
+            if !inside(zone.x, zone.rect.width, self.big_rect.width) || !inside(zone.y, zone.rect.height, self.big_rect.height) {
+                return Err(invalid("it is not inside of the big rect"));
+            }
+            if self.blocked_zones.iter().chain(&zones[..i]).any(|other| overlap(zone, other)) {
+                return Err(invalid("it overlaps another blocked zone"));
+            }
+        }
+        Ok(self.add_blocked_zones(zones))
+    }
+
+    fn add_blocked_zones(mut self, zones: Vec<PlacedRectangle>) -> RectConfiguration {
+        self.blocked_zones.extend(zones);
+        for orientations in self.rotated_available_block_map.values_mut() {
+            orientations.retain(|r| self.blocked_zones.is_empty() || fits_somewhere(&self.big_rect, &self.blocked_zones, r));
+        }
+        self
+    }
+
+    #[allow(dead_code)]
+    /// pin a piece to a layer, a position and/or an orientation\
+    /// pins should be added after the blocked zones
+    pub(crate) fn with_pin(mut self, id: RecId, pin: Pin) -> PuzzleResult<RectConfiguration> {
+        let rect = self.get_block(id, "pins")?;
+        let invalid = |reason: &str| PuzzleError::InvalidPin { id, reason: reason.to_owned() };
+        if pin.layer.is_some_and(|layer| layer >= LAYER_COUNT) {
+            return Err(invalid(&format!("there are only {LAYER_COUNT} layers, the bottom layer is 0")));
+        }
+        let (big_rect, zones) = (self.big_rect, &self.blocked_zones);
+        let orientations = self.rotated_available_block_map.get_mut(&id).ok_or_else(|| invalid("it does not fit into the big rect"))?;
+        orientations.retain(|r| {
+            let rotated = r.height == rect.width && r.width == rect.height;
+            let as_given = r.height == rect.height && r.width == rect.width;
+            pin.rotated.is_none_or(|pinned| if pinned { rotated } else { as_given }) &&
+                pin.position.is_none_or(|(x, y)| {
+                    let placed = PlacedRectangle { rect: *r, x, y };
+                    x + r.width <= big_rect.width && y + r.height <= big_rect.height && !zones.iter().any(|z| z.check_collision(&placed))
+                })
+        });
+        if orientations.is_empty() {
+            return Err(invalid("it does not fit into the big rect with this position and orientation"));
+        }
+        self.distinguish(rect);
+        self.pins.insert(id, pin);
+        Ok(self)
+    }
+
+    #[allow(dead_code)]
+    /// add a constraint on which pieces share a layer\
+    /// `InLayer` pins the layer of the pieces, so it has to be added after their other pins
+    pub(crate) fn with_layer_constraint(mut self, constraint: LayerConstraint) -> PuzzleResult<RectConfiguration> {
+        let invalid = |reason: String| PuzzleError::InvalidLayerConstraint { constraint: constraint.clone(), reason };
+        if let LayerConstraint::Apart(ids) = &constraint {
+            if ids.len() > LAYER_COUNT as usize {
+                return Err(invalid(format!("{} pieces can not be in different layers, there are only {LAYER_COUNT}", ids.len())));
+            }
+        }
+        for id in constraint.ids() {
+            let rect = self.get_block(*id, "layer constraints")?;
+            self.distinguish(rect);
+        }
+        if let LayerConstraint::InLayer(ids, layer) = &constraint {
+            for id in ids {
+                let pin = self.pins.get(id).copied().unwrap_or_default();
+                if pin.layer.is_some_and(|l| l != *layer) {
+                    return Err(invalid(format!("piece {id} is already pinned to layer {}", pin.layer.unwrap_or_default())));
+                }
+                self = self.with_pin(*id, Pin { layer: Some(*layer), ..pin })?;
+            }
+        }
+        self.layer_constraints.push(constraint);
+        Ok(self)
+    }
+
+    /// a piece with a pin or layer constraint is not interchangeable with pieces of the same size anymore
+    fn distinguish(&mut self, rect: Rectangle) {
+        for duplicates in self.duplication_map.values_mut() {
+            duplicates.retain(|r| r.id != rect.id);
+        }
+        self.duplication_map.insert(rect.id, vec![rect]);
+    }
+
+    /// check if a piece has a pin or layer constraint
+    pub(crate) fn is_distinguished(&self, id: RecId) -> bool {
+        self.pins.contains_key(&id) || self.layer_constraints.iter().any(|c| c.ids().contains(&id))
+    }
+
+    /// get the pin of a piece, if it has one
+    pub(crate) fn pin(&self, id: RecId) -> Option<&Pin> {
+        self.pins.get(&id)
+    }
+
+    /// all ways to put the pieces of the combination with a fixed position into their place, without collisions\
+    /// if there are no such pieces, this is one empty layout
+    pub(crate) fn positioned_layouts(&self, combination: &Combination) -> Vec<Vec<PlacedRectangle>> {
+        let pieces: Vec<Vec<PlacedRectangle>> = combination.iter()
+            .filter_map(|r| self.pin(r.id)?.position.map(|(x, y)| (r.id, x, y)))
+            .map(|(id, x, y)| self.rotated_available_block_map[&id].iter().map(|r| PlacedRectangle { rect: *r, x, y }).collect())
+            .collect();
+        if pieces.is_empty() {
+            return vec![vec![]];
+        }
+        pieces.into_iter()
+            .multi_cartesian_product()
+            .filter(|placed| placed.iter().tuple_combinations().all(|(a, b)| !a.check_collision(b)))
+            .collect()
+    }
+
+    /// check if a combination can be one layer of a solution, regarding the pins and layer constraints\
+    /// all pieces have to be allowed in the same layer and the pieces with fixed positions must not collide
+    pub(crate) fn allows_layer(&self, combination: &Combination) -> bool {
+        combination.iter().filter_map(|r| self.pin(r.id)?.layer).unique().count() <= 1 &&
+            self.layer_constraints.iter().all(|c| c.allows_layer(combination)) &&
+            !self.positioned_layouts(combination).is_empty()
+    }
+
+    /// check if the layers can be stacked in an order, in which every piece is in its pinned layer\
+    /// and if each of them is allowed on its own
+    pub(crate) fn allows_layers(&self, layers: &[&Combination]) -> bool {
+        layers.iter().all(|l| self.allows_layer(l)) && (0..LAYER_COUNT).permutations(layers.len()).any(|order|
+            layers.iter().zip(order).all(|(layer, index)|
+                layer.iter().all(|r| self.pin(r.id).and_then(|p| p.layer).is_none_or(|pinned| pinned == index))
+            )
+        )
+    }
+
+    /// area of the big rect, which is not blocked
+    pub(crate) fn usable_area(&self) -> u32 {
+        self.big_rect.area - self.blocked_zones.iter().map(|z| z.rect.area).sum::<u32>()
+    }
+
+    /// get the rectangle with this id, `context` describes where the id came from
+    pub(crate) fn get_block(&self, id: RecId, context: &str) -> PuzzleResult<Rectangle> {
+        self.available_block_map.get(&id).copied()
+            .ok_or_else(|| PuzzleError::UnknownPieceId { id, context: context.to_owned() })
+    }
+
+    /// get the rectangles with the same dimensions as the rectangle with this id (including itself)
+    pub(crate) fn duplicates_of(&self, id: RecId) -> PuzzleResult<&Vec<Rectangle>> {
+        self.duplication_map.get(&id)
+            .ok_or_else(|| PuzzleError::UnknownPieceId { id, context: "duplication map".to_owned() })
     }
 }
 
+#[allow(dead_code)]
+/// a blocked zone with the upper left corner at (x, y)
+pub(crate) fn blocked_zone(x: RecDimension, y: RecDimension, height: RecDimension, width: RecDimension) -> PlacedRectangle {
+    PlacedRectangle { rect: Rectangle::new(BLOCKED_ZONE_ID, height, width), x, y }
+}
+
+/// check if the point (x / 2, y / 2) is inside of the polygon, the point must not be on an edge
+fn point_in_polygon(outline: &[(RecDimension, RecDimension)], x: RecDimension, y: RecDimension) -> bool {
+    // count the vertical edges right of the point
+    outline.iter().zip(outline.iter().cycle().skip(1))
+        .filter(|((x0, y0), (x1, y1))| x0 == x1 && 2 * x0 > x && min(2 * y0, 2 * y1) < y && y < max(2 * y0, 2 * y1))
+        .count() % 2 == 1
+}
+
+/// check if the rectangle can be placed at any corner position without hitting a blocked zone
+fn fits_somewhere(big_rect: &Rectangle, zones: &[PlacedRectangle], rect: &Rectangle) -> bool {
+    let xs = zones.iter().map(|z| z.x + z.rect.width).chain([0]);
+    let ys = zones.iter().map(|z| z.y + z.rect.height).chain([0]).collect::<Vec<_>>();
+    xs.cartesian_product(ys).any(|(x, y)| {
+        let placed = PlacedRectangle { rect: *rect, x, y };
+        x + rect.width <= big_rect.width && y + rect.height <= big_rect.height && !zones.iter().any(|z| z.check_collision(&placed))
+    })
+}
+
 #[allow(dead_code)]
 /// rounded to millimeter
 pub(crate) fn mm_rects() -> RectConfiguration {
@@ -202,3 +490,87 @@ pub(crate) fn mm100_rects_floor() -> RectConfiguration {
         ],
     )
 }
+
+#[allow(dead_code)]
+/// measured in cm, the same pieces as `mm10_rects_floor`
+pub(crate) fn cm_rects_floor() -> PuzzleResult<RectConfiguration> {
+    RectConfiguration::from_decimal(
+        "cm",
+            ("4.63", "7.04"),
+        &[
+            (1, "4.5", "1.98"),
+            (2, "2.74", "2.23"),
+            (3, "2.74", "2.49"),
+            (4, "2.74", "2.74"),
+            (5, "3.23", "1.73"),
+            (6, "3.23", "2.23"),
+            (7, "1.73", "2"),
+            (8, "2.24", "1.24"),
+            (9, "1.73", "1.48"),
+            (10, "1.73", "1.98"),
+            (11, "2.49", "1.48"),
+            (12, "3.23", "1.73"),
+            (13, "4.48", "1.74"),
+            (14, "3.23", "1.48"),
+            (15, "2.24", "1.24"),
+            (16, "5.24", "1.23"),
+            (17, "4.55", "1.23"),
+            (18, "2.24", "0.99"),
+        ],
+    )
+}
+
+#[test]
+fn test_from_polygon() {
+    // U shaped container, the gap in the middle is blocked
+    let rects = RectConfiguration::from_polygon(&[(0, 0), (1, 0), (1, 2), (3, 2), (3, 0), (4, 0), (4, 3), (0, 3)], vec![Rectangle::new(1, 3, 3)]);
+    assert_eq!((rects.big_rect.height, rects.big_rect.width), (3, 4));
+    assert_eq!(rects.blocked_zones.iter().map(|z| (z.x, z.y, z.rect.height, z.rect.width)).collect::<Vec<_>>(), vec![(1, 0, 2, 2)]);
+    assert_eq!(rects.usable_area(), 8);
+    // a 3x3 rectangle does not fit next to the gap
+    assert!(rects.rotated_available_block_map[&1].is_empty());
+
+    // a chamfered corner, split into two zones
+    let rects = RectConfiguration::from_polygon(&[(0, 0), (4, 0), (4, 2), (3, 2), (3, 3), (2, 3), (2, 4), (0, 4)], vec![]);
+    assert_eq!(
+        rects.blocked_zones.iter().map(|z| (z.x, z.y, z.rect.height, z.rect.width)).collect::<Vec<_>>(),
+        vec![(3, 2, 1, 1), (2, 3, 1, 2)],
+    );
+}
+
+#[test]
+fn test_invalid_blocked_zones() {
+    let rects = || RectConfiguration::new(Rectangle::new(-1, 4, 4), vec![Rectangle::new(1, 2, 2)]);
+    let invalid = |zones: Vec<PlacedRectangle>| matches!(rects().with_blocked_zones(zones), Err(PuzzleError::InvalidBlockedZone { .. }));
+    assert!(invalid(vec![blocked_zone(0, 0, 2, 2), blocked_zone(1, 1, 2, 2)]));
+    assert!(invalid(vec![blocked_zone(3, 0, 1, 2)]));
+    assert!(invalid(vec![blocked_zone(0, 0, 0, 2)]));
+    assert!(rects().with_blocked_zones(vec![blocked_zone(0, 0, 2, 2)]).unwrap().with_blocked_zones(vec![blocked_zone(1, 1, 1, 1)]).is_err());
+    // touching zones are fine
+    let rects = rects().with_blocked_zones(vec![blocked_zone(0, 0, 2, 2), blocked_zone(2, 0, 2, 2)]).unwrap();
+    assert_eq!(rects.usable_area(), 8);
+}
+
+#[test]
+fn test_from_decimal() {
+    use crate::rect::{placement_from_string, placement_to_string};
+    use crate::{ProgramStorage, Settings};
+
+    // the cm preset ends up on the same grid as the 0.1 mm preset
+    let cm = cm_rects_floor().unwrap();
+    let mm10 = mm10_rects_floor();
+    assert_eq!(cm.resolution.to_string(), "1 grid step = 0.01 cm");
+    assert_eq!((cm.big_rect.height, cm.big_rect.width), (mm10.big_rect.height, mm10.big_rect.width));
+    assert!(cm.available_blocks.iter().zip(&mm10.available_blocks).all(|(a, b)| (a.id, a.height, a.width) == (b.id, b.height, b.width)));
+
+    let rects = RectConfiguration::from_decimal("cm", ("2.5", "10"), &[(1, "2.5", "7.5"), (2, "1", "2.5")]).unwrap();
+    assert_eq!(rects.resolution.to_string(), "1 grid step = 0.5 cm");
+    assert_eq!((rects.big_rect.height, rects.big_rect.width), (5, 20));
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let line = "1 2.5 7.5 0 0  2 2.5 1 7.5 0 r";
+    let placed = placement_from_string(&storage, line).unwrap();
+    assert_eq!((placed[1].rect.height, placed[1].rect.width, placed[1].x), (5, 2, 15));
+    assert_eq!(placement_to_string(&rects.resolution, &placed), line);
+    assert!(placement_from_string(&storage, "1 2.5 7.5 0.2 0").is_err());
+    assert!(RectConfiguration::from_decimal("cm", ("2.5", "ten"), &[]).is_err());
+}
@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::data_configuration::LayerConstraint;
+use crate::rect::{RecDimension, RecId};
+
+/// everything that can go wrong while running the pipeline
+#[derive(Debug)]
+pub enum PuzzleError {
+    /// a line of an input or result file could not be parsed
+    Parse { context: String, line: String, reason: String },
+    /// an id, which is not part of the rectangle configuration
+    UnknownPieceId { id: RecId, context: String },
+    /// a result file belongs to another step, format or configuration
+    ResultFileMismatch { path: String, reason: String },
+    /// reading or writing a file failed
+    Io { path: String, source: io::Error },
+    /// drawing or saving an image failed
+    Render { path: String, reason: String },
+    /// the terminal can not be used interactively
+    Terminal { reason: String },
+    /// a pin of the rectangle configuration can not be fulfilled
+    InvalidPin { id: RecId, reason: String },
+    /// a blocked zone is empty, outside of the big rect or overlaps another one
+    InvalidBlockedZone { zone: (RecDimension, RecDimension, RecDimension, RecDimension), reason: String },
+    /// a layer constraint of the rectangle configuration can not be fulfilled
+    InvalidLayerConstraint { constraint: LayerConstraint, reason: String },
+    /// a layout read from a file breaks the rules of the puzzle
+    InvalidLayout { context: String, problems: String },
+}
+
+pub type PuzzleResult<T> = Result<T, PuzzleError>;
+
+impl PuzzleError {
+    pub(crate) fn io(path: &str, source: io::Error) -> PuzzleError {
+        PuzzleError::Io { path: path.to_owned(), source }
+    }
+}
+
+impl Display for PuzzleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PuzzleError::Parse { context, line, reason } =>
+                write!(f, "could not parse \"{line}\" in {context}: {reason}"),
+            PuzzleError::UnknownPieceId { id, context } =>
+                write!(f, "unknown piece id {id} in {context}, check that the file belongs to the selected rectangle configuration"),
+            PuzzleError::ResultFileMismatch { path, reason } =>
+                write!(f, "{path}: {reason}; delete the file or rerun the step that produces it"),
+            PuzzleError::Io { path, source } =>
+                write!(f, "could not access {path}: {source}; check that the directory exists and is writable"),
+            PuzzleError::Render { path, reason } =>
+                write!(f, "could not render {path}: {reason}"),
+            PuzzleError::Terminal { reason } =>
+                write!(f, "the terminal can not be used interactively: {reason}; run this in an interactive terminal"),
+            PuzzleError::InvalidPin { id, reason } =>
+                write!(f, "piece {id} can not be pinned: {reason}; check the pins of the rectangle configuration"),
+            PuzzleError::InvalidBlockedZone { zone: (x, y, height, width), reason } =>
+                write!(f, "the blocked zone {height}x{width} at ({x}, {y}) is invalid: {reason}; check the blocked zones of the rectangle configuration"),
+            PuzzleError::InvalidLayerConstraint { constraint, reason } =>
+                write!(f, "the layer constraint {constraint:?} is invalid: {reason}; check the layer constraints of the rectangle configuration"),
+            PuzzleError::InvalidLayout { context, problems } =>
+                write!(f, "the layout in {context} is invalid: {problems}; check that it belongs to the selected candidate"),
+        }
+    }
+}
+
+impl Error for PuzzleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PuzzleError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
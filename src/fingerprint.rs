@@ -0,0 +1,109 @@
+use std::fmt::{Display, Formatter};
+use std::hash::Hasher;
+
+use crate::data_configuration::RectConfiguration;
+use crate::resolution::Resolution;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// stable 64 bit FNV-1a hasher\
+/// unlike `DefaultHasher`, the result does not change between rust versions or runs,
+/// so it can be used for keys that are written to disk
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fingerprint {
+    state: u64,
+}
+
+impl Fingerprint {
+    pub(crate) fn new() -> Fingerprint {
+        Fingerprint { state: FNV_OFFSET_BASIS }
+    }
+
+    /// feed a labelled value, so that e.g. ("a", 12) and ("a1", 2) do not collide
+    pub(crate) fn field(&mut self, label: &str, value: u64) -> &mut Self {
+        self.write(label.as_bytes());
+        self.write_u8(0xff);
+        self.write_u64(value);
+        self
+    }
+
+    /// feed the shape of the container, the big rect and all blocked zones
+    pub(crate) fn container(&mut self, configuration: &RectConfiguration) -> &mut Self {
+        self.field("big_rect.height", configuration.big_rect.height as u64)
+            .field("big_rect.width", configuration.big_rect.width as u64);
+        // the same grid means something else with another resolution
+        if configuration.resolution != Resolution::default() {
+            self.field("resolution.step", configuration.resolution.step)
+                .field("resolution.decimals", configuration.resolution.decimals as u64);
+        }
+        for zone in &configuration.blocked_zones {
+            self.field("blocked.x", zone.x as u64)
+                .field("blocked.y", zone.y as u64)
+                .field("blocked.height", zone.rect.height as u64)
+                .field("blocked.width", zone.rect.width as u64);
+        }
+        self
+    }
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Fingerprint::new()
+    }
+}
+
+impl Hasher for Fingerprint {
+    fn finish(&self) -> u64 {
+        self.state
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    // always use little endian, so files can be shared between machines
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+}
+
+/// a finished fingerprint, printed as 16 hex digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct FingerprintValue(pub u64);
+
+impl FingerprintValue {
+    pub(crate) fn parse(s: &str) -> Option<FingerprintValue> {
+        u64::from_str_radix(s.trim(), 16).ok().map(FingerprintValue)
+    }
+}
+
+impl Display for FingerprintValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[test]
+fn test_fingerprint_is_stable() {
+    // reference value of FNV-1a for "a"
+    let mut f = Fingerprint::new();
+    f.write(b"a");
+    assert_eq!(f.finish(), 0xaf63dc4c8601ec8c);
+
+    let mut f1 = Fingerprint::new();
+    f1.field("a", 12);
+    let mut f2 = Fingerprint::new();
+    f2.field("a1", 2);
+    assert_ne!(f1.finish(), f2.finish());
+
+    let value = FingerprintValue(f1.finish());
+    assert_eq!(FingerprintValue::parse(&value.to_string()), Some(value));
+}
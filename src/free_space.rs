@@ -0,0 +1,162 @@
+use itertools::Itertools;
+
+use crate::ProgramStorage;
+use crate::rect::{PlacedRectangle, RecDimension, Rectangle};
+
+/// part of a container: x, y, width and height
+pub(crate) type Region = (RecDimension, RecDimension, RecDimension, RecDimension);
+
+/// free space of a container as maximal empty rectangles\
+/// every free spot is inside of at least one of them and none of them is inside of another one,\
+/// so the cost depends on the number of placed rectangles, not on the resolution
+#[derive(Debug, Clone)]
+pub(crate) struct FreeSpace {
+    free: Vec<Region>,
+}
+
+impl FreeSpace {
+    /// an empty container
+    pub(crate) fn new(width: RecDimension, height: RecDimension) -> FreeSpace {
+        FreeSpace { free: if width > 0 && height > 0 { vec![(0, 0, width, height)] } else { vec![] } }
+    }
+
+    /// the space of the big rect, which is left for the free pieces\
+    /// without the blocked zones, the fixed pieces and the clearance to them and to the walls
+    pub(crate) fn for_container(storage: &ProgramStorage, fixed: &[PlacedRectangle]) -> FreeSpace {
+        let big_rect = storage.rect_configuration.big_rect;
+        FreeSpace::for_container_of_size(storage, fixed, big_rect.width, big_rect.height)
+    }
+
+    /// like `for_container`, but the walls on the right and at the bottom are moved, to see how far rectangles stick out
+    pub(crate) fn for_container_of_size(storage: &ProgramStorage, fixed: &[PlacedRectangle], width: RecDimension, height: RecDimension) -> FreeSpace {
+        let policy = &storage.settings.contact_policy;
+        let wall = policy.wall_clearance;
+        let mut space = FreeSpace::new(width, height);
+        for strip in [
+            (0, 0, wall, height),
+            (0, 0, width, wall),
+            (width.saturating_sub(wall), 0, wall, height),
+            (0, height.saturating_sub(wall), width, wall),
+        ] {
+            space.occupy(strip);
+        }
+        for o in storage.rect_configuration.blocked_zones.iter().chain(fixed) {
+            space.occupy_around(o, policy.clearance_to(o));
+        }
+        space
+    }
+
+    /// mark a placed rectangle and the clearance around it as used
+    pub(crate) fn occupy_around(&mut self, placed: &PlacedRectangle, clearance: RecDimension) {
+        let (x, y) = (placed.x.saturating_sub(clearance), placed.y.saturating_sub(clearance));
+        self.occupy((x, y, placed.x + placed.rect.width + clearance - x, placed.y + placed.rect.height + clearance - y));
+    }
+
+    /// mark a region as used, parts of it outside of the container are ignored
+    pub(crate) fn occupy(&mut self, (x, y, width, height): Region) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        let mut split = vec![];
+        self.free.retain(|&(fx, fy, fw, fh)| {
+            if !(x < fx + fw && fx < x + width && y < fy + fh && fy < y + height) {
+                return true;
+            }
+            // the parts of the free region left, right, above and below of the used region
+            if x > fx {
+                split.push((fx, fy, x - fx, fh));
+            }
+            if x + width < fx + fw {
+                split.push((x + width, fy, fx + fw - x - width, fh));
+            }
+            if y > fy {
+                split.push((fx, fy, fw, y - fy));
+            }
+            if y + height < fy + fh {
+                split.push((fx, y + height, fw, fy + fh - y - height));
+            }
+            false
+        });
+        // only keep the maximal ones
+        let split = split.into_iter().unique().collect::<Vec<_>>();
+        let free = &self.free;
+        let maximal = split.iter()
+            .filter(|r| !free.iter().chain(split.iter().filter(|o| o != r)).any(|o| contains(*o, **r)))
+            .copied()
+            .collect::<Vec<_>>();
+        self.free.extend(maximal);
+    }
+
+    /// the lowest y, at which a rectangle of this size is completely free at x
+    pub(crate) fn lowest_at(&self, x: RecDimension, width: RecDimension, height: RecDimension) -> Option<RecDimension> {
+        self.free.iter()
+            .filter(|(fx, _, fw, fh)| *fx <= x && x + width <= fx + fw && height <= *fh)
+            .map(|(_, fy, _, _)| *fy)
+            .min()
+    }
+
+    /// check if a rectangle of this size fits anywhere
+    pub(crate) fn fits(&self, width: RecDimension, height: RecDimension) -> bool {
+        self.fitting(width, height).next().is_some()
+    }
+
+    /// all maximal free rectangles, which are big enough for a rectangle of this size
+    pub(crate) fn fitting(&self, width: RecDimension, height: RecDimension) -> impl Iterator<Item=&Region> {
+        self.free.iter().filter(move |(_, _, fw, fh)| width <= *fw && height <= *fh)
+    }
+
+    /// the exact free area, the maximal rectangles overlap, so it is not just their sum
+    pub(crate) fn free_area(&self) -> u64 {
+        let xs = self.free.iter().flat_map(|(x, _, w, _)| [*x, x + w]).sorted().dedup().collect::<Vec<_>>();
+        xs.iter().tuple_windows().map(|(x0, x1)| {
+            // free intervals in this vertical slice, merged
+            let mut covered = 0;
+            let mut reached = 0;
+            for (y0, y1) in self.free.iter()
+                .filter(|(x, _, w, _)| x <= x0 && x1 <= &(x + w))
+                .map(|(_, y, _, h)| (*y, y + h))
+                .sorted() {
+                covered += y1.saturating_sub(y0.max(reached));
+                reached = reached.max(y1);
+            }
+            (x1 - x0) as u64 * covered as u64
+        }).sum()
+    }
+
+    /// check if the rectangles could fit into the free space: the area is big enough and each one fits somewhere on its own\
+    /// this does not place them, so it only rules out candidates
+    pub(crate) fn can_hold(&self, rects: &[&Rectangle]) -> bool {
+        rects.iter().map(|r| r.area as u64).sum::<u64>() <= self.free_area() &&
+            rects.iter().all(|r| self.fits(r.width, r.height) || r.rotatable && self.fits(r.height, r.width))
+    }
+}
+
+/// check if region `a` contains region `b`
+fn contains((ax, ay, aw, ah): Region, (bx, by, bw, bh): Region) -> bool {
+    ax <= bx && ay <= by && bx + bw <= ax + aw && by + bh <= ay + ah
+}
+
+#[test]
+fn test_free_space() {
+    let mut space = FreeSpace::new(6, 6);
+    assert_eq!(space.free_area(), 36);
+    // a tall piece on the left, a flat one next to it and a wide one on top, which leaves a hole below it
+    space.occupy((0, 0, 2, 5));
+    space.occupy((2, 0, 4, 1));
+    space.occupy((0, 5, 4, 1));
+    assert_eq!(space.free_area(), 36 - 10 - 4 - 4);
+    assert_eq!(space.lowest_at(2, 2, 2), Some(1));
+    assert_eq!(space.lowest_at(4, 2, 5), Some(1));
+    assert_eq!(space.lowest_at(1, 2, 1), None);
+    assert!(space.fits(2, 5) && !space.fits(5, 1));
+    assert!(space.can_hold(&[&Rectangle::new(1, 2, 5)]));
+    assert!(!space.can_hold(&[&Rectangle::new(1, 2, 5).not_rotatable()]));
+    // each one fits, but not all of them together
+    assert!(!space.can_hold(&[&Rectangle::new(1, 4, 2), &Rectangle::new(2, 4, 2), &Rectangle::new(3, 4, 2)]));
+
+    // no maximal rectangle is inside of another one
+    assert!(space.free.iter().tuple_combinations().all(|(a, b)| !contains(*a, *b) && !contains(*b, *a)));
+    space.occupy((2, 1, 4, 4));
+    assert_eq!(space.free_area(), 2);
+    assert_eq!(space.free, vec![(4, 5, 2, 1)]);
+}
@@ -1,6 +1,3 @@
-use ::num::Unsigned;
-use std::num;
-
 /// Source: https://gist.github.com/victor-iyi/8a84185c1d52419b0d4915a648d5e3e1
 /// Computes the greatest common divisor of two integers using Euclid's algorithm
 /// (https://en.wikipedia.org/wiki/Euclidean_algorithm).
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::free_space::Region;
+use crate::ProgramStorage;
+use crate::rect::{Combination, PlacedRectangle, RecDimension, Rectangle};
+use crate::resolution::Resolution;
+
+/// how a region of the big rect is sawn\
+/// coordinates are relative to the region, y grows downwards like in the images of `draw_image`
+#[derive(Debug)]
+pub(crate) enum CutTree {
+    /// nothing in this region
+    Waste,
+    /// a rectangle in the corner at (0, 0) of the region, the rest of the region is waste
+    Piece(Rectangle),
+    /// a straight cut parallel to the y axis, `at` is the width of the left part\
+    /// the right part starts after the kerf of the saw
+    Vertical { at: RecDimension, left: Rc<CutTree>, right: Rc<CutTree> },
+    /// a straight cut parallel to the x axis, `at` is the height of the upper part
+    Horizontal { at: RecDimension, top: Rc<CutTree>, bottom: Rc<CutTree> },
+}
+
+impl CutTree {
+    /// get the positions of all rectangles, if the region starts at (x, y) and each cut removes `kerf`
+    pub(crate) fn to_layout(&self, x: RecDimension, y: RecDimension, kerf: RecDimension) -> Vec<PlacedRectangle> {
+        match self {
+            CutTree::Waste => vec![],
+            CutTree::Piece(rect) => vec![PlacedRectangle { rect: *rect, x, y }],
+            CutTree::Vertical { at, left, right } => {
+                let mut layout = left.to_layout(x, y, kerf);
+                layout.extend(right.to_layout(x + at + kerf, y, kerf));
+                layout
+            }
+            CutTree::Horizontal { at, top, bottom } => {
+                let mut layout = top.to_layout(x, y, kerf);
+                layout.extend(bottom.to_layout(x, y + at + kerf, kerf));
+                layout
+            }
+        }
+    }
+
+    /// describe the cuts in the order they have to be made, for a region at (x, y) with the given size\
+    /// the description uses the measured unit
+    pub(crate) fn describe_cuts(&self, x: RecDimension, y: RecDimension, width: RecDimension, height: RecDimension, kerf: RecDimension, resolution: &Resolution) -> Vec<String> {
+        let f = |v: RecDimension| resolution.format(v);
+        match self {
+            CutTree::Waste | CutTree::Piece(_) => vec![],
+            CutTree::Vertical { at, left, right } => {
+                let mut cuts = vec![format!("cut {}x{} at ({}, {}) vertically at x = {}", f(width), f(height), f(x), f(y), f(x + at))];
+                cuts.extend(left.describe_cuts(x, y, *at, height, kerf, resolution));
+                cuts.extend(right.describe_cuts(x + at + kerf, y, width - at - kerf, height, kerf, resolution));
+                cuts
+            }
+            CutTree::Horizontal { at, top, bottom } => {
+                let mut cuts = vec![format!("cut {}x{} at ({}, {}) horizontally at y = {}", f(width), f(height), f(x), f(y), f(y + at))];
+                cuts.extend(top.describe_cuts(x, y, width, *at, kerf, resolution));
+                cuts.extend(bottom.describe_cuts(x, y + at + kerf, width, height - at - kerf, kerf, resolution));
+                cuts
+            }
+        }
+    }
+}
+
+/// exact search for guillotine packings, using recursive splits of the region\
+/// the pieces of a region are split into two sets, one for each side of the first cut
+struct GuillotineSearch {
+    /// all allowed orientations of each piece
+    orientations: Vec<Vec<Rectangle>>,
+    areas: Vec<u32>,
+    /// width of the material removed by each cut
+    kerf: RecDimension,
+    blocked_zones: Vec<PlacedRectangle>,
+    /// without blocked zones, the position of a region does not matter
+    memo: HashMap<(Region, u32), Option<Rc<CutTree>>>,
+}
+
+impl GuillotineSearch {
+    fn area(&self, mask: u32) -> u32 {
+        (0..self.areas.len()).filter(|i| mask & (1 << i) != 0).map(|i| self.areas[i]).sum()
+    }
+
+    /// all widths (or heights) below `limit`, which can be the sum of the sides of a subset of the pieces\
+    /// the smallest region a set of pieces fits into always has such a size
+    fn normal_positions(&self, mask: u32, limit: RecDimension, horizontal: bool) -> Vec<RecDimension> {
+        let mut reachable = vec![false; limit as usize + 1];
+        reachable[0] = true;
+        for i in (0..self.orientations.len()).filter(|i| mask & (1 << i) != 0) {
+            let sides = self.orientations[i].iter().map(|r| if horizontal { r.height } else { r.width }).collect::<Vec<_>>();
+            for position in (0..=limit).rev() {
+                if reachable[position as usize] {
+                    continue;
+                }
+                reachable[position as usize] = sides.iter().any(|s| *s <= position && reachable[(position - s) as usize]);
+            }
+        }
+        (1..=limit).filter(|p| reachable[*p as usize]).collect()
+    }
+
+    /// cuts along the edges of the blocked zones inside of the region, relative to the region
+    fn zone_positions(&self, (x, y, width, height): Region, limit: RecDimension, horizontal: bool) -> Vec<RecDimension> {
+        self.blocked_zones.iter()
+            .filter(|z| z.x < x + width && x < z.x + z.rect.width && z.y < y + height && y < z.y + z.rect.height)
+            .flat_map(|z| match horizontal {
+                true => [z.y.checked_sub(y), (z.y + z.rect.height).checked_sub(y + self.kerf)],
+                false => [z.x.checked_sub(x), (z.x + z.rect.width).checked_sub(x + self.kerf)],
+            })
+            .flatten()
+            .filter(|p| *p > 0 && *p <= limit)
+            .collect()
+    }
+
+    fn solve(&mut self, region: Region, mask: u32) -> Option<Rc<CutTree>> {
+        let (_, _, width, height) = region;
+        if mask == 0 {
+            return Some(Rc::new(CutTree::Waste));
+        }
+        if self.area(mask) > width * height {
+            return None;
+        }
+        let key = if self.blocked_zones.is_empty() { ((0, 0, width, height), mask) } else { (region, mask) };
+        if let Some(result) = self.memo.get(&key) {
+            return result.clone();
+        }
+        let result = self.split(region, mask);
+        self.memo.insert(key, result.clone());
+        result
+    }
+
+    fn split(&mut self, region: Region, mask: u32) -> Option<Rc<CutTree>> {
+        let (x, y, width, height) = region;
+        let zones = !self.blocked_zones.is_empty();
+        if mask.count_ones() == 1 {
+            let i = mask.trailing_zeros() as usize;
+            let piece = self.orientations[i].iter()
+                .find(|r| r.width <= width && r.height <= height &&
+                    !self.blocked_zones.iter().any(|z| z.check_collision(&PlacedRectangle { rect: **r, x, y })))
+                .map(|r| Rc::new(CutTree::Piece(*r)));
+            // next to blocked zones, the piece may have to be moved away from the corner by cutting off waste
+            if piece.is_some() || !zones {
+                return piece;
+            }
+        }
+        // without blocked zones, the part with the lowest piece is always the left (or upper) part, the other order is just mirrored
+        // with blocked zones, a part may also be empty, to cut away the waste around a zone
+        let lowest = if zones { 0 } else { mask & mask.wrapping_neg() };
+        let rest = mask & !lowest;
+        let mut subset = rest;
+        loop {
+            let first = subset | lowest;
+            let second = mask & !first;
+            if second != 0 || zones {
+                for horizontal in [false, true] {
+                    let (length, other_length) = if horizontal { (height, width) } else { (width, height) };
+                    let lower_bound = self.area(first).div_ceil(other_length);
+                    let upper_bound = length.saturating_sub(self.area(second).div_ceil(other_length) + self.kerf);
+                    let mut positions = self.normal_positions(first, upper_bound, horizontal);
+                    if zones {
+                        positions.extend(self.zone_positions(region, upper_bound, horizontal));
+                        positions.sort();
+                        positions.dedup();
+                    }
+                    // the first part as small as possible leaves the most room for the second part
+                    for at in positions.into_iter().filter(|p| *p >= lower_bound && *p < length) {
+                        let remaining = length - at - self.kerf;
+                        let (first_region, second_region) = match horizontal {
+                            true => ((x, y, width, at), (x, y + at + self.kerf, width, remaining)),
+                            false => ((x, y, at, height), (x + at + self.kerf, y, remaining, height)),
+                        };
+                        if let Some(first_tree) = self.solve(first_region, first) {
+                            if let Some(second_tree) = self.solve(second_region, second) {
+                                return Some(Rc::new(match horizontal {
+                                    true => CutTree::Horizontal { at, top: first_tree, bottom: second_tree },
+                                    false => CutTree::Vertical { at, left: first_tree, right: second_tree },
+                                }));
+                            }
+                            // a bigger first part only helps, if it moves the second part away from a blocked zone
+                            if !zones {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            if subset == 0 {
+                return None;
+            }
+            subset = (subset - 1) & rest;
+        }
+    }
+}
+
+/// the pieces of a search are kept as bits of a `u32`, so more free pieces than this can not be searched
+pub(crate) const MAX_GUILLOTINE_PIECES: usize = u32::BITS as usize - 1;
+
+/// search for a guillotine packing of the candidate in the big rect\
+/// `distance_between_rectangles` is used as the kerf of the saw, but at least the piece clearance of the contact policy\
+/// blocked zones and pieces pinned to a position are cut away as waste, together with their clearance\
+/// returns the cut tree and the resulting layout, `None` also for more than `MAX_GUILLOTINE_PIECES` free pieces
+pub(crate) fn solve_guillotine(storage: &ProgramStorage, candidate: &Combination) -> Option<(Rc<CutTree>, Vec<PlacedRectangle>)> {
+    let configuration = storage.rect_configuration;
+    let policy = &storage.settings.contact_policy;
+    let wall = policy.wall_clearance;
+    // the pieces have to stay away from the border, so only the inner part is sawn
+    let region = (wall, wall, configuration.big_rect.width.checked_sub(2 * wall)?, configuration.big_rect.height.checked_sub(2 * wall)?);
+    let free: Vec<&Rectangle> = candidate.iter().filter(|r| configuration.pin(r.id).is_none_or(|p| p.position.is_none())).collect();
+    if free.len() > MAX_GUILLOTINE_PIECES {
+        return None;
+    }
+    let orientations: Vec<Vec<Rectangle>> = free.iter()
+        .map(|r| configuration.rotated_available_block_map.get(&r.id).map(|o| o.iter().copied().collect()).unwrap_or_default())
+        .collect();
+    for fixed in configuration.positioned_layouts(candidate) {
+        let mut search = GuillotineSearch {
+            areas: free.iter().map(|r| r.area).collect(),
+            orientations: orientations.clone(),
+            kerf: guillotine_kerf(storage),
+            blocked_zones: configuration.blocked_zones.iter().chain(&fixed).map(|o| grown(o, policy.clearance_to(o))).collect(),
+            memo: HashMap::new(),
+        };
+        if let Some(tree) = search.solve(region, (1u32 << free.len()) - 1) {
+            let mut layout = tree.to_layout(wall, wall, search.kerf);
+            layout.extend(fixed);
+            return Some((tree, layout));
+        }
+    }
+    None
+}
+
+/// the width of each cut
+pub(crate) fn guillotine_kerf(storage: &ProgramStorage) -> RecDimension {
+    storage.settings.distance_between_rectangles.max(storage.settings.contact_policy.piece_clearance)
+}
+
+/// an obstacle together with the clearance around it
+fn grown(obstacle: &PlacedRectangle, clearance: RecDimension) -> PlacedRectangle {
+    let (x, y) = (obstacle.x.saturating_sub(clearance), obstacle.y.saturating_sub(clearance));
+    PlacedRectangle {
+        rect: Rectangle::new(obstacle.rect.id, obstacle.y + obstacle.rect.height + clearance - y, obstacle.x + obstacle.rect.width + clearance - x),
+        x,
+        y,
+    }
+}
+
+#[test]
+fn test_guillotine() {
+    use crate::data_configuration::{blocked_zone, RectConfiguration};
+    use crate::Settings;
+    use crate::layer_solver::{GuillotineSolver, LayerSolver, LayerVerdict};
+    use crate::verify::verify_layout;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 4, 10),
+        vec![
+            Rectangle::new(1, 2, 2),
+            Rectangle::new(2, 1, 2),
+            Rectangle::new(3, 2, 3),
+            Rectangle::new(4, 1, 3),
+            Rectangle::new(5, 2, 4),
+            Rectangle::new(6, 2, 2),
+            Rectangle::new(7, 3, 2),
+            Rectangle::new(8, 1, 5),
+            Rectangle::new(9, 1, 2),
+        ],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = rects.available_blocks.iter().copied().collect();
+    let (tree, layout) = solve_guillotine(&storage, &candidate).unwrap();
+    assert!(verify_layout(&storage, &candidate, &layout).is_empty());
+    assert_eq!(tree.describe_cuts(0, 0, 10, 4, 0, &rects.resolution).len(), 8);
+    // the solver does not print the cuts, they come with the verdict
+    assert!(matches!(GuillotineSolver.solve(&storage, &candidate), LayerVerdict::Fits(_, cuts) if cuts.len() == 8));
+
+    // a pinwheel can not be sawn with straight cuts
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 5, 5),
+        vec![
+            Rectangle::new(1, 2, 3),
+            Rectangle::new(2, 3, 2),
+            Rectangle::new(3, 2, 3),
+            Rectangle::new(4, 3, 2),
+            Rectangle::new(5, 1, 1),
+        ],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = rects.available_blocks.iter().copied().collect();
+    assert!(solve_guillotine(&storage, &candidate).is_none());
+
+    // the blocked corner has to be cut away, before the pieces fit
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 3, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 1, 4), Rectangle::new(3, 2, 1)],
+    ).with_blocked_zones(vec![blocked_zone(0, 0, 2, 1)]).unwrap();
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = rects.available_blocks.iter().copied().collect();
+    let (_, layout) = solve_guillotine(&storage, &candidate).unwrap();
+    assert!(verify_layout(&storage, &candidate, &layout).is_empty());
+
+    // too many pieces for the bit masks
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 1, 40),
+        (1..=32).map(|id| Rectangle::new(id, 1, 1)).collect(),
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = rects.available_blocks.iter().copied().collect();
+    assert!(solve_guillotine(&storage, &candidate).is_none());
+    assert!(matches!(GuillotineSolver.solve(&storage, &candidate), LayerVerdict::Unknown(_)));
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fs;
+
+use itertools::Itertools;
+
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::ProgramStorage;
+use crate::rect::{Combination, combination_to_string, get_unique_combination_key, PlacedRectangle};
+use crate::result_file::relevant_settings;
+use crate::statistics::Report;
+use crate::verdict_cache::VerdictCache;
+
+/// at most this many combined solutions are drawn, the layouts of the others are not searched
+const MAX_DRAWN_SOLUTIONS: usize = 50;
+
+/// click on a column header to sort the table by it, numbers are sorted as numbers
+const SORT_SCRIPT: &str = r#"
+document.querySelectorAll("table.sortable th").forEach((th, column) => th.addEventListener("click", () => {
+  const body = th.closest("table").tBodies[0];
+  const ascending = th.dataset.order !== "asc";
+  th.dataset.order = ascending ? "asc" : "desc";
+  const value = row => row.cells[column].innerText;
+  const rows = Array.from(body.rows).sort((a, b) => {
+    const [x, y] = [value(a), value(b)];
+    const order = isNaN(parseFloat(x)) || isNaN(parseFloat(y)) ? x.localeCompare(y) : parseFloat(x) - parseFloat(y);
+    return ascending ? order : -order;
+  });
+  rows.forEach(row => body.appendChild(row));
+}));
+"#;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; } \
+table { border-collapse: collapse; margin-bottom: 1em; } \
+td, th { border: 1px solid #999; padding: 0.2em 0.6em; text-align: left; } \
+table.sortable th { cursor: pointer; background: #eee; } \
+.layers { display: flex; gap: 1em; flex-wrap: wrap; margin-bottom: 1.5em; } \
+svg { max-width: 30em; border: 1px solid #333; }";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// a table, which can be sorted by clicking on the headers
+fn table<I: IntoIterator<Item=Vec<String>>>(headers: &[&str], rows: I) -> String {
+    let cells = |row: Vec<String>, tag: &str| row.iter().map(|c| format!("<{tag}>{}</{tag}>", escape(c))).join("");
+    format!(
+        "<table class=\"sortable\"><thead><tr>{}</tr></thead><tbody>\n{}\n</tbody></table>",
+        cells(headers.iter().map(|h| h.to_string()).collect(), "th"),
+        rows.into_iter().map(|row| format!("<tr>{}</tr>", cells(row, "td"))).join("\n"),
+    )
+}
+
+/// the layout of one layer as vector drawing, blocked zones are grey
+fn svg(storage: &ProgramStorage, layout: &[PlacedRectangle]) -> String {
+    let big_rect = storage.rect_configuration.big_rect;
+    let rect = |p: &PlacedRectangle, fill: String| format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{fill}\" stroke=\"#333\" vector-effect=\"non-scaling-stroke\"/>",
+        p.x, p.y, p.rect.width, p.rect.height,
+    );
+    let zones = storage.rect_configuration.blocked_zones.iter().map(|z| rect(z, "#888".to_owned()));
+    let pieces = layout.iter().map(|p| {
+        let font_size = p.rect.width.min(p.rect.height) as f64 / 3.0;
+        format!(
+            "{}<text x=\"{}\" y=\"{}\" font-size=\"{font_size:.1}\" text-anchor=\"middle\" dominant-baseline=\"middle\">{}</text>",
+            rect(p, format!("hsl({}, 60%, 75%)", (p.rect.id as i32 * 47).rem_euclid(360))),
+            p.x as f64 + p.rect.width as f64 / 2.0, p.y as f64 + p.rect.height as f64 / 2.0, escape(&p.rect.label()),
+        )
+    });
+    format!(
+        "<svg viewBox=\"0 0 {w} {h}\"><rect width=\"{w}\" height=\"{h}\" fill=\"#fff\"/>{}</svg>",
+        zones.chain(pieces).join(""),
+        w = big_rect.width, h = big_rect.height,
+    )
+}
+
+/// the layout of a layer from the verdict cache, or from the layer solver, if it is not cached
+fn layer_layout(storage: &ProgramStorage, cache: &mut VerdictCache, layer: &Combination) -> Option<Vec<PlacedRectangle>> {
+    match cache.lookup(storage, layer) {
+        Some(verdict) => verdict.layout(),
+        None => storage.settings.layer_solver().solve(storage, layer).layout(),
+    }
+}
+
+/// one html file with the configuration, the settings, the combined solutions with drawings of each layer,\
+/// the ranking of step5 and the statistics of the run, without any external files
+pub(crate) fn write_html_report(storage: &ProgramStorage, path: &str) -> PuzzleResult<()> {
+    let configuration = storage.rect_configuration;
+    let resolution = &configuration.resolution;
+    let size = |v| format!("{} {}", resolution.format(v), resolution.unit);
+    let mut cache = match storage.settings.verdict_cache_path {
+        Some(cache_path) => VerdictCache::from_file(cache_path)?,
+        None => VerdictCache::default(),
+    };
+    let mut sections = vec![
+        "<h1>holzpuzzle</h1>".to_owned(),
+        "<h2>Configuration</h2>".to_owned(),
+        format!("<p>big rect {} x {} ({resolution})</p>", size(configuration.big_rect.width), size(configuration.big_rect.height)),
+        table(&["id", "height", "width", "area", "rotatable", "pin"], configuration.available_blocks.iter().map(|r| vec![
+            r.id.to_string(), resolution.format(r.height), resolution.format(r.width), resolution.format_area(r.area), r.rotatable.to_string(),
+            configuration.pin(r.id).map_or(String::new(), |p| format!("{p:?}")),
+        ])),
+    ];
+    if !configuration.blocked_zones.is_empty() {
+        sections.push(table(&["blocked zone x", "y", "width", "height"], configuration.blocked_zones.iter().map(|z|
+            [z.x, z.y, z.rect.width, z.rect.height].map(|v| resolution.format(v)).to_vec())));
+    }
+    if !configuration.layer_constraints.is_empty() {
+        sections.push(table(&["layer constraint"], configuration.layer_constraints.iter().map(|c| vec![format!("{c:?}")])));
+    }
+    sections.push("<h2>Settings</h2>".to_owned());
+    sections.push(table(&["setting", "value"], relevant_settings(storage).into_iter().map(|(name, value)| vec![name.to_owned(), value])));
+
+    // unproven solutions have a layer, which step3 could neither place nor rule out
+    let solutions = storage.combined_solutions.iter()
+        .map(|s| (s.iter().sorted_by_key(|c| combination_to_string(c)).collect::<Vec<_>>(), storage.unproven_solutions.contains(s)))
+        .sorted_by_key(|(s, _)| s.iter().map(|c| combination_to_string(c)).join(" "))
+        .collect::<Vec<_>>();
+    sections.push(format!("<h2>Combined solutions ({}, {} unproven)</h2>", solutions.len(), storage.unproven_solutions.len()));
+    if solutions.len() > MAX_DRAWN_SOLUTIONS {
+        sections.push(format!("<p>only the first {MAX_DRAWN_SOLUTIONS} are drawn</p>"));
+    }
+    for (i, (solution, unproven)) in solutions.iter().take(MAX_DRAWN_SOLUTIONS).enumerate() {
+        let unproven = match unproven {
+            true => " (unproven, a layer is only unknown to fit)",
+            false => "",
+        };
+        sections.push(format!("<h3>{}. {}{unproven}</h3>\n<div class=\"layers\">", i + 1, escape(&solution.iter().map(|c| combination_to_string(c)).join(" "))));
+        for layer in solution {
+            sections.push(match layer_layout(storage, &mut cache, layer) {
+                Some(layout) => svg(storage, &layout),
+                None => format!("<p>no layout found for {}</p>", combination_to_string(layer)),
+            });
+        }
+        sections.push("</div>".to_owned());
+    }
+
+    // how often each final combination is part of a combined solution, like in step5
+    let occurrences = storage.combined_solutions.iter().flatten().map(get_unique_combination_key).counts();
+    sections.push("<h2>Ranking (step5)</h2>".to_owned());
+    sections.push(table(&["rank", "combination", "solutions", "area"], storage.final_combinations.iter().rev().enumerate().map(|(i, c)| vec![
+        (i + 1).to_string(), combination_to_string(c),
+        occurrences.get(&get_unique_combination_key(c)).copied().unwrap_or(0).to_string(),
+        resolution.format_area(c.iter().map(|r| r.area).sum()),
+    ])));
+
+    let report = Report::new(storage, &storage.statistics.lock().unwrap());
+    sections.push("<h2>Run statistics</h2>".to_owned());
+    sections.push(table(&["step", "seconds"], report.steps.iter().map(|(name, seconds)| vec![name.to_string(), format!("{seconds:.2}")])));
+    sections.push(table(&["step3 thread", "seconds", "candidates"], report.threads.iter()
+        .map(|(number, seconds, candidates)| vec![number.to_string(), format!("{seconds:.2}"), candidates.to_string()])));
+    let pieces = report.pieces_per_combination.iter().flat_map(|(_, counts)| counts.keys()).unique().sorted().collect::<Vec<_>>();
+    let headers = ["step".to_owned()].into_iter().chain(pieces.iter().map(|p| format!("{p} pieces"))).collect::<Vec<_>>();
+    sections.push(table(&headers.iter().map(String::as_str).collect::<Vec<_>>(), report.pieces_per_combination.iter().map(|(step, counts)|
+        [step.to_string()].into_iter().chain(pieces.iter().map(|p| counts.get(p).copied().unwrap_or(0).to_string())).collect())));
+    sections.push(table(&["area utilisation from %", "fitting layers"], report.utilisation.iter().map(|(from, count)| vec![from.to_string(), count.to_string()])));
+    let frequency: HashMap<_, _> = report.piece_frequency.iter().cloned().collect();
+    sections.push(table(&["piece", "fitting layers", "combined solutions"], configuration.available_blocks.iter().map(|r| vec![
+        r.id.to_string(),
+        frequency.get("fitting_layers").and_then(|c| c.get(&r.id)).copied().unwrap_or(0).to_string(),
+        frequency.get("combined_solutions").and_then(|c| c.get(&r.id)).copied().unwrap_or(0).to_string(),
+    ])));
+
+    let html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>holzpuzzle</title><style>{STYLE}</style></head><body>\n{}\n<script>{SORT_SCRIPT}</script></body></html>\n",
+        sections.join("\n"),
+    );
+    fs::write(path, html).map_err(|e| PuzzleError::io(path, e))
+}
+
+#[test]
+fn test_html_report() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::rect::{RecId, Rectangle};
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 2, 2), Rectangle::new(3, 4, 2)],
+    );
+    let mut storage = ProgramStorage::new(&rects, Settings::default());
+    let combination = |ids: &[RecId]| ids.iter().map(|id| rects.available_block_map[id]).collect::<Combination>();
+    storage.solutions = [combination(&[1, 2]), combination(&[3])].into_iter().collect();
+    storage.combined_solutions = [[combination(&[1, 2]), combination(&[3])].into_iter().collect()].into_iter().collect();
+    storage.final_combinations = vec![combination(&[3]), combination(&[1, 2])];
+
+    let path = std::env::temp_dir().join(format!("holzpuzzle_test_{}.html", std::process::id()));
+    let path = path.to_str().unwrap();
+    write_html_report(&storage, path).unwrap();
+    let html = fs::read_to_string(path).unwrap();
+    let _ = fs::remove_file(path);
+    // both layers are drawn, the rotated piece is marked
+    assert_eq!(html.matches("<svg").count(), 2);
+    assert!(html.contains(">3↻</text>"), "{html}");
+    assert!(html.contains("<h3>1. 1,2 3</h3>"));
+    assert!(html.contains("<h2>Combined solutions (1, 0 unproven)</h2>"));
+    assert!(html.contains("<td>layer_solver</td><td>heuristic</td>"));
+    assert!(html.contains("<tr><td>1</td><td>1,2</td><td>1</td><td>8</td></tr>"), "{html}");
+    // nothing is loaded from outside of the file
+    assert!(!html.contains("src=") && !html.contains("href=") && !html.contains("http"));
+
+    storage.unproven_solutions = storage.combined_solutions.clone();
+    write_html_report(&storage, path).unwrap();
+    let html = fs::read_to_string(path).unwrap();
+    let _ = fs::remove_file(path);
+    assert!(html.contains("<h3>1. 1,2 3 (unproven, a layer is only unknown to fit)</h3>"), "{html}");
+}
@@ -0,0 +1,385 @@
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+
+use crate::budget::BudgetTracker;
+use crate::free_space::FreeSpace;
+use crate::guillotine::{guillotine_kerf, MAX_GUILLOTINE_PIECES, solve_guillotine};
+use crate::ProgramStorage;
+use crate::sat::SatSolver;
+use crate::rect::{Combination, get_unique_permutation_key, PlacedRectangle, RecDimension, Rectangle};
+use crate::verify::verify_layout;
+
+/// answer to "does this candidate fit into one layer, and how?"
+#[derive(Debug, Clone)]
+pub(crate) enum LayerVerdict {
+    /// the layout and how to saw it, if the solver knows the cuts
+    Fits(Vec<PlacedRectangle>, Vec<String>),
+    /// proven not to fit, with the reason
+    DoesNotFit(String),
+    /// the solver could not decide, with the reason
+    Unknown(String),
+    /// the solver reached a limit of the candidate budget, with the limit
+    OutOfBudget(String),
+}
+
+impl LayerVerdict {
+    /// the layout, if the candidate fits
+    pub(crate) fn layout(self) -> Option<Vec<PlacedRectangle>> {
+        match self {
+            LayerVerdict::Fits(layout, _) => Some(layout),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LayerVerdict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayerVerdict::Fits(..) => write!(f, "fits"),
+            LayerVerdict::DoesNotFit(reason) => write!(f, "does not fit ({reason})"),
+            LayerVerdict::Unknown(reason) => write!(f, "unknown ({reason})"),
+            LayerVerdict::OutOfBudget(reason) => write!(f, "undecided ({reason})"),
+        }
+    }
+}
+
+/// which layouts a solver searches, its layouts and proofs only hold for solvers of the same kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LayoutKind {
+    /// any layout, which respects the contact policy
+    Any,
+    /// layouts, which can be sawn with straight cuts of this kerf
+    Guillotine(RecDimension),
+}
+
+/// checks if a candidate fits into the big rect, respecting pins, blocked zones and the contact policy
+pub(crate) trait LayerSolver: Sync {
+    /// name for the log, the command line and the fingerprints of cache and result files
+    fn name(&self) -> String;
+
+    /// the kind of layouts the verdicts are about, `None` if they can be about different kinds
+    fn layout_kind(&self, _storage: &ProgramStorage) -> Option<LayoutKind> {
+        Some(LayoutKind::Any)
+    }
+
+    /// the candidate budget of the settings applies to each call
+    fn solve(&self, storage: &ProgramStorage, candidate: &Combination) -> LayerVerdict;
+}
+
+/// find a layer solver by its name on the command line
+pub(crate) fn layer_solver_by_name(name: &str) -> Option<&'static dyn LayerSolver> {
+    match name {
+        "heuristic" => Some(&HeuristicSolver),
+        "exact" => Some(&ExactSolver { max_nodes: 1_000_000 }),
+        "guillotine" => Some(&GuillotineSolver),
+        "chained" => Some(&ChainedSolver(&[&HeuristicSolver, &ExactSolver { max_nodes: 1_000_000 }])),
+        "sat" => Some(&SatSolver { command: None, max_decisions: 1_000_000 }),
+        _ => None,
+    }
+}
+
+/// the pieces of a candidate, which are not pinned to a position
+pub(crate) fn free_pieces<'a>(storage: &ProgramStorage, candidate: &'a Combination) -> Vec<&'a Rectangle> {
+    candidate.iter()
+        .filter(|r| storage.rect_configuration.pin(r.id).is_none_or(|p| p.position.is_none()))
+        .collect()
+}
+
+/// the positions of the pinned pieces, which leave enough space for the free pieces
+fn fixed_layouts(storage: &ProgramStorage, candidate: &Combination, free: &[&Rectangle]) -> Vec<Vec<PlacedRectangle>> {
+    storage.rect_configuration.positioned_layouts(candidate).into_iter()
+        .filter(|fixed| FreeSpace::for_container(storage, fixed).can_hold(free))
+        .collect()
+}
+
+/// try every orientation and order of the pieces with the placement strategies of the settings\
+/// it only finds layouts, so a candidate without one is unknown, unless there is not enough space at all
+pub(crate) struct HeuristicSolver;
+
+impl LayerSolver for HeuristicSolver {
+    fn name(&self) -> String {
+        "heuristic".to_owned()
+    }
+
+    fn solve(&self, storage: &ProgramStorage, candidate: &Combination) -> LayerVerdict {
+        let free = free_pieces(storage, candidate);
+        let fixed_layouts = fixed_layouts(storage, candidate, &free);
+        if fixed_layouts.is_empty() {
+            return LayerVerdict::DoesNotFit("not enough space left for the pieces".to_owned());
+        }
+        // because the small rectangles can be rotated, we need to check each combination of each rotation for the input
+        let orientations = free.iter()
+            .map(|r| storage.rect_configuration.rotated_available_block_map.get(&r.id).unwrap())
+            .collect::<Vec<_>>();
+        let products = match orientations.is_empty() {
+            true => vec![vec![]],
+            false => orientations.into_iter().multi_cartesian_product().collect::<Vec<Vec<&Rectangle>>>(),
+        };
+        let budget = BudgetTracker::new(storage.settings.candidate_budget);
+        for strategy in &storage.settings.placement_strategies {
+            for (fixed, product) in fixed_layouts.iter().cartesian_product(&products) {
+                // because I have no better idea, just check each permutation of each combination individually
+                for per in product.iter().cloned().permutations(product.len()).unique_by(get_unique_permutation_key) {
+                    if !budget.permutation() {
+                        return LayerVerdict::OutOfBudget(budget.exhausted().unwrap_or_default().to_owned());
+                    }
+                    let Some(layout) = strategy.place(storage, &per, fixed, &budget) else {
+                        if let Some(reason) = budget.exhausted() {
+                            return LayerVerdict::OutOfBudget(reason.to_owned());
+                        }
+                        continue;
+                    };
+                    if !verify_layout(storage, candidate, &layout).is_empty() {
+                        continue;
+                    }
+                    println!("PLACED_BY {}", strategy.name());
+                    *storage.placements_by_strategy.lock().unwrap().entry(strategy.name()).or_default() += 1;
+                    return LayerVerdict::Fits(layout, vec![]);
+                }
+            }
+        }
+        LayerVerdict::Unknown("no placement strategy found a layout".to_owned())
+    }
+}
+
+/// only accept layouts, which can be sawn out of the big rect with straight cuts, the search is exact
+pub(crate) struct GuillotineSolver;
+
+impl LayerSolver for GuillotineSolver {
+    fn name(&self) -> String {
+        "guillotine".to_owned()
+    }
+
+    fn layout_kind(&self, storage: &ProgramStorage) -> Option<LayoutKind> {
+        Some(LayoutKind::Guillotine(guillotine_kerf(storage)))
+    }
+
+    fn solve(&self, storage: &ProgramStorage, candidate: &Combination) -> LayerVerdict {
+        if free_pieces(storage, candidate).len() > MAX_GUILLOTINE_PIECES {
+            return LayerVerdict::Unknown(format!("more than {MAX_GUILLOTINE_PIECES} free pieces for the guillotine search"));
+        }
+        let Some((tree, layout)) = solve_guillotine(storage, candidate) else {
+            return LayerVerdict::DoesNotFit("no guillotine packing exists".to_owned());
+        };
+        let (big_rect, wall) = (storage.rect_configuration.big_rect, storage.settings.contact_policy.wall_clearance);
+        let cuts = tree.describe_cuts(wall, wall, big_rect.width - 2 * wall, big_rect.height - 2 * wall, guillotine_kerf(storage), &storage.rect_configuration.resolution);
+        LayerVerdict::Fits(layout, cuts)
+    }
+}
+
+/// exact search over all normal positions: every layout can be pushed up and left, until each piece touches\
+/// a wall, a blocked zone or another piece on both sides, so x is a wall or obstacle edge plus a sum of widths\
+/// gives up after `max_nodes` tried positions or when the time of the candidate budget is up
+pub(crate) struct ExactSolver {
+    pub max_nodes: u64,
+}
+
+impl LayerSolver for ExactSolver {
+    fn name(&self) -> String {
+        format!("exact({})", self.max_nodes)
+    }
+
+    fn solve(&self, storage: &ProgramStorage, candidate: &Combination) -> LayerVerdict {
+        let free = free_pieces(storage, candidate);
+        let budget = BudgetTracker::new(storage.settings.candidate_budget);
+        let mut gave_up = false;
+        for fixed in fixed_layouts(storage, candidate, &free) {
+            let mut search = ExactSearch::new(storage, &free, &fixed, self.max_nodes, &budget);
+            match search.place(0) {
+                Some(true) => {
+                    let mut layout = search.placed;
+                    layout.extend(fixed);
+                    return LayerVerdict::Fits(layout, vec![]);
+                }
+                Some(false) => {}
+                None => gave_up = true,
+            }
+        }
+        match gave_up {
+            true => LayerVerdict::OutOfBudget(budget.exhausted().map_or_else(|| format!("gave up after {} positions", self.max_nodes), str::to_owned)),
+            false => LayerVerdict::DoesNotFit("no layout exists".to_owned()),
+        }
+    }
+}
+
+struct ExactSearch<'a> {
+    storage: &'a ProgramStorage<'a>,
+    /// allowed orientations of each piece, the biggest pieces first
+    pieces: Vec<Vec<Rectangle>>,
+    /// true if a piece has the same dimensions as the one before
+    same_as_previous: Vec<bool>,
+    obstacles: Vec<PlacedRectangle>,
+    xs: Vec<RecDimension>,
+    ys: Vec<RecDimension>,
+    placed: Vec<PlacedRectangle>,
+    nodes: u64,
+    max_nodes: u64,
+    budget: &'a BudgetTracker,
+}
+
+impl<'a> ExactSearch<'a> {
+    fn new(storage: &'a ProgramStorage<'a>, free: &[&Rectangle], fixed: &[PlacedRectangle], max_nodes: u64, budget: &'a BudgetTracker) -> ExactSearch<'a> {
+        let configuration = storage.rect_configuration;
+        let policy = &storage.settings.contact_policy;
+        let pieces = free.iter()
+            .sorted_by_key(|r| (std::cmp::Reverse(r.area), r.rotatable, r.height, r.width))
+            .map(|r| configuration.rotated_available_block_map[&r.id].iter().copied().sorted_by_key(|o| o.rotated).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let dimensions = |orientations: &Vec<Rectangle>| orientations.iter().map(|o| (o.height, o.width)).collect::<Vec<_>>();
+        let same_as_previous = (0..pieces.len()).map(|i| i > 0 && dimensions(&pieces[i]) == dimensions(&pieces[i - 1])).collect();
+        let obstacles = configuration.blocked_zones.iter().chain(fixed).copied().collect::<Vec<_>>();
+        let normal_positions = |limit: RecDimension, side: fn(&Rectangle) -> RecDimension, start: fn(&PlacedRectangle) -> RecDimension| {
+            let mut reachable = vec![false; limit as usize + 1];
+            for base in [policy.wall_clearance].into_iter().chain(obstacles.iter().map(|o| start(o) + policy.clearance_to(o))) {
+                if base <= limit {
+                    reachable[base as usize] = true;
+                }
+            }
+            // each piece can be left of or above the next one only once
+            for orientations in &pieces {
+                let sides = orientations.iter().map(|o| side(o) + policy.piece_clearance).unique().collect::<Vec<_>>();
+                for position in (0..=limit).rev() {
+                    if !reachable[position as usize] {
+                        reachable[position as usize] = sides.iter().any(|s| *s <= position && reachable[(position - s) as usize]);
+                    }
+                }
+            }
+            (0..=limit).filter(|p| reachable[*p as usize]).collect::<Vec<_>>()
+        };
+        let big_rect = configuration.big_rect;
+        ExactSearch {
+            storage,
+            xs: normal_positions(big_rect.width, |r| r.width, |o| o.x + o.rect.width),
+            ys: normal_positions(big_rect.height, |r| r.height, |o| o.y + o.rect.height),
+            pieces,
+            same_as_previous,
+            obstacles,
+            placed: vec![],
+            nodes: 0,
+            max_nodes,
+            budget,
+        }
+    }
+
+    /// place piece `index` and all after it, `None` if the budget is used up
+    fn place(&mut self, index: usize) -> Option<bool> {
+        let Some(orientations) = self.pieces.get(index).cloned() else {
+            return Some(true);
+        };
+        let policy = &self.storage.settings.contact_policy;
+        // equal pieces are placed in order, so their swapped layouts are not tried again
+        let after = match self.same_as_previous[index] {
+            true => self.placed.last().map(|p| (p.y, p.x)),
+            false => None,
+        };
+        for rect in orientations {
+            for y_index in 0..self.ys.len() {
+                for x_index in 0..self.xs.len() {
+                    let (x, y) = (self.xs[x_index], self.ys[y_index]);
+                    if after.is_some_and(|a| (y, x) <= a) {
+                        continue;
+                    }
+                    let p = PlacedRectangle { rect, x, y };
+                    if !p.check_bounds(self.storage) ||
+                        self.placed.iter().chain(&self.obstacles).any(|o| o.check_clearance(&p, policy.clearance_to(o))) {
+                        continue;
+                    }
+                    self.nodes += 1;
+                    if self.nodes > self.max_nodes || !self.budget.within_time() {
+                        return None;
+                    }
+                    self.placed.push(p);
+                    if self.place(index + 1)? {
+                        return Some(true);
+                    }
+                    self.placed.pop();
+                }
+            }
+        }
+        Some(false)
+    }
+}
+
+/// ask the solvers in order, until one of them decides
+pub(crate) struct ChainedSolver(pub &'static [&'static dyn LayerSolver]);
+
+impl LayerSolver for ChainedSolver {
+    fn name(&self) -> String {
+        self.0.iter().map(|s| s.name()).join(">")
+    }
+
+    fn layout_kind(&self, storage: &ProgramStorage) -> Option<LayoutKind> {
+        self.0.iter().map(|s| s.layout_kind(storage)).all_equal_value().ok().flatten()
+    }
+
+    fn solve(&self, storage: &ProgramStorage, candidate: &Combination) -> LayerVerdict {
+        let mut reasons = vec![];
+        let mut out_of_budget = false;
+        for solver in self.0 {
+            match solver.solve(storage, candidate) {
+                LayerVerdict::Unknown(reason) => reasons.push(format!("{}: {reason}", solver.name())),
+                LayerVerdict::OutOfBudget(reason) => {
+                    out_of_budget = true;
+                    reasons.push(format!("{}: {reason}", solver.name()));
+                }
+                verdict => return verdict,
+            }
+        }
+        // a bigger budget may still decide it
+        match out_of_budget {
+            true => LayerVerdict::OutOfBudget(reasons.join(", ")),
+            false => LayerVerdict::Unknown(reasons.join(", ")),
+        }
+    }
+}
+
+#[test]
+fn test_layer_solvers() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::budget::CandidateBudget;
+    use crate::rect::ContactPolicy;
+    use crate::Settings;
+
+    // four pieces around a small one in the middle, which can not be sawn with straight cuts
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 5, 5),
+        vec![Rectangle::new(1, 2, 3), Rectangle::new(2, 2, 3), Rectangle::new(3, 2, 3), Rectangle::new(4, 2, 3), Rectangle::new(5, 1, 1), Rectangle::new(6, 4, 4)],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let combination = |ids: &[i8]| ids.iter().map(|id| rects.available_block_map[id]).collect::<Combination>();
+    let pinwheel = combination(&[1, 2, 3, 4, 5]);
+    let layout = ExactSolver { max_nodes: 100_000 }.solve(&storage, &pinwheel).layout().unwrap();
+    assert!(verify_layout(&storage, &pinwheel, &layout).is_empty());
+    assert!(matches!(GuillotineSolver.solve(&storage, &pinwheel), LayerVerdict::DoesNotFit(_)));
+    assert!(matches!(ExactSolver { max_nodes: 3 }.solve(&storage, &pinwheel), LayerVerdict::OutOfBudget(_)));
+    let chained = ChainedSolver(&[&ExactSolver { max_nodes: 3 }, &ExactSolver { max_nodes: 100_000 }]);
+    assert_eq!(chained.name(), "exact(3)>exact(100000)");
+    assert!(matches!(chained.solve(&storage, &pinwheel), LayerVerdict::Fits(..)));
+
+    // enough area, but the two big pieces can not be next to each other
+    let too_wide = combination(&[1, 6]);
+    assert!(matches!(ExactSolver { max_nodes: 100_000 }.solve(&storage, &too_wide), LayerVerdict::DoesNotFit(_)));
+    assert!(matches!(HeuristicSolver.solve(&storage, &too_wide), LayerVerdict::Unknown(_)));
+    assert!(matches!(HeuristicSolver.solve(&storage, &combination(&[1, 2, 3, 4, 6])), LayerVerdict::DoesNotFit(_)));
+
+    // a candidate, which reaches a limit, is undecided, also in a chain
+    let budget = |budget: CandidateBudget| ProgramStorage::new(&rects, Settings { candidate_budget: budget, ..Default::default() });
+    let storage = budget(CandidateBudget { max_permutations: Some(1), ..Default::default() });
+    assert!(matches!(HeuristicSolver.solve(&storage, &too_wide), LayerVerdict::OutOfBudget(_)));
+    assert!(matches!(ChainedSolver(&[&HeuristicSolver, &ExactSolver { max_nodes: 3 }]).solve(&storage, &pinwheel), LayerVerdict::OutOfBudget(_)));
+    let storage = ProgramStorage::new(&rects, Settings {
+        candidate_budget: CandidateBudget { max_compaction_steps: Some(0), ..Default::default() },
+        distance_between_rectangles: 1,
+        ..Default::default()
+    });
+    assert!(matches!(HeuristicSolver.solve(&storage, &combination(&[1, 2])), LayerVerdict::OutOfBudget(reason) if reason.starts_with("compaction")));
+    let storage = budget(CandidateBudget { max_duration: Some(std::time::Duration::ZERO), ..Default::default() });
+    assert!(matches!(ExactSolver { max_nodes: 100_000 }.solve(&storage, &pinwheel), LayerVerdict::OutOfBudget(reason) if reason.starts_with("time")));
+
+    // the exact solver keeps the clearance, so the pinwheel does not fit anymore
+    let storage = ProgramStorage::new(&rects, Settings { contact_policy: ContactPolicy { piece_clearance: 1, wall_clearance: 0 }, ..Default::default() });
+    assert!(matches!(ExactSolver { max_nodes: 100_000 }.solve(&storage, &pinwheel), LayerVerdict::DoesNotFit(_)));
+    let layout = ExactSolver { max_nodes: 100_000 }.solve(&storage, &combination(&[1, 2, 5])).layout().unwrap();
+    assert!(verify_layout(&storage, &combination(&[1, 2, 5]), &layout).is_empty());
+}
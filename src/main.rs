@@ -1,20 +1,60 @@
 extern crate core;
 
-use std::collections::{BTreeSet, HashSet};
-use std::time::Instant;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use itertools::Itertools;
 
 use crate::data_configuration::RectConfiguration;
-use crate::rect::{Combination, RecId, RectCombinationStorage};
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::html_report::write_html_report;
+use crate::budget::{BudgetTracker, CandidateBudget};
+use crate::layer_solver::{GuillotineSolver, HeuristicSolver, layer_solver_by_name, LayerSolver, LayerVerdict};
+use crate::milp::{read_solution, to_lp};
+use crate::near_miss::NearMiss;
+use crate::placement::{ContactPerimeter, MaxRectsBestShortSideFit, PlacementStrategy, ShelfCompact, SkylineBottomLeft};
+use crate::rect::{Combination, combination_from_string, combination_to_string, ContactPolicy, placement_to_string, RecDimension, RecId, Rectangle, RectCombinationStorage};
+#[cfg(test)]
 use crate::rect_image::draw_image;
-use crate::steps::{step1_generate_candiates, step2_deduplication, step3_check_candidate, step3_filter_fitting_candidates, step4_calculate_matches, step5_sort_final_combinations};
+use crate::sat::{import_model, SatInstance};
+use crate::slack::slack;
+use crate::statistics::{report_statistics, RunStatistics};
+#[cfg(test)]
+use crate::steps::step3_check_candidate;
+use crate::steps::{step1_generate_candiates, step2_deduplication, step3_check_permutation_traced, step3_filter_fitting_candidates, step3_retry_undecided, step4_calculate_matches, step5_sort_final_combinations};
+use crate::text_render::render_layout;
+use crate::trace::PlacementTrace;
+use crate::tui::run_tui;
 
 mod rect;
 mod steps;
 mod rect_image;
 mod data_configuration;
 mod gcd;
+mod fingerprint;
+mod verdict_cache;
+mod result_file;
+mod error;
+mod tui;
+mod text_render;
+mod verify;
+mod guillotine;
+mod resolution;
+mod free_space;
+mod placement;
+mod layer_solver;
+mod sat;
+mod milp;
+mod near_miss;
+mod slack;
+mod statistics;
+mod html_report;
+mod trace;
+mod budget;
 
 pub(crate) struct ProgramStorage<'a> {
     pub rect_configuration: &'a RectConfiguration,
@@ -22,36 +62,98 @@ pub(crate) struct ProgramStorage<'a> {
     pub deduplicated_combinations: RectCombinationStorage,
     pub solutions: RectCombinationStorage,
     pub combined_solutions: HashSet<BTreeSet<Combination>>,
+    /// the combined solutions with a layer, which is only unknown to fit (`unknown_layers_as_possible`)
+    pub unproven_solutions: HashSet<BTreeSet<Combination>>,
     pub final_combinations: Vec<Combination>,
     pub settings: Settings,
+    /// how many candidates each placement strategy placed in step3
+    pub placements_by_strategy: Mutex<BTreeMap<&'static str, u32>>,
+    /// the closest candidates, which did not fit in step3
+    pub near_misses: Mutex<Vec<NearMiss>>,
+    /// time per step and per step3 thread
+    pub statistics: Mutex<RunStatistics>,
+    /// candidates of step3, which reached a limit of the candidate budget
+    pub undecided_candidates: Mutex<RectCombinationStorage>,
+    /// candidates of step3, which neither fit nor are proven not to fit
+    pub unknown_candidates: Mutex<RectCombinationStorage>,
 }
 
 impl ProgramStorage<'_> {
-    fn new(rect_configuration: &RectConfiguration, settings: Settings) -> ProgramStorage {
+    fn new(rect_configuration: &RectConfiguration, settings: Settings) -> ProgramStorage<'_> {
         ProgramStorage {
             rect_configuration,
             gathered_combinations: Default::default(),
             deduplicated_combinations: Default::default(),
             solutions: Default::default(),
             combined_solutions: Default::default(),
+            unproven_solutions: Default::default(),
             final_combinations: vec![],
             settings,
+            placements_by_strategy: Default::default(),
+            near_misses: Default::default(),
+            statistics: Default::default(),
+            undecided_candidates: Default::default(),
+            unknown_candidates: Default::default(),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Settings {
     pub thread_count: u8,
     pub min_solution_area: u32,
     pub min_rectangle_amount: u8,
     pub max_rectangle_amount: u8,
+    /// extra space between the rectangles, when they are first put into rows in step3\
+    /// only a heuristic, the gaps which have to stay are set in `contact_policy`
     pub distance_between_rectangles: u32,
+    /// how close rectangles may get to each other and to the walls
+    pub contact_policy: ContactPolicy,
+    /// the heuristics step3 tries for each candidate, in this order
+    pub(crate) placement_strategies: Vec<&'static dyn PlacementStrategy>,
     pub steps: [bool; 4],
     pub candidates_path: Option<&'static str>,
     pub fitting_candidates_path: Option<&'static str>,
     pub deduplicated_combinations_path: Option<&'static str>,
     pub solutions_filepath: Option<&'static str>,
     pub final_combinations_path: Option<&'static str>,
+    pub verdict_cache_path: Option<&'static str>,
+    pub accept_mismatched_result_files: bool,
+    pub text_render_width: Option<usize>,
+    /// only search layouts, which can be sawn with straight cuts through the whole piece, replaces `layer_solver`
+    pub guillotine_only: bool,
+    /// decides for each candidate in step3, if it fits into one layer
+    pub(crate) layer_solver: &'static dyn LayerSolver,
+    /// analyze the candidates, which do not fit, and write the closest ones to this file
+    pub near_miss_report_path: Option<&'static str>,
+    /// how many near misses the report contains
+    pub near_miss_count: usize,
+    /// write the statistics of the run as text to this file
+    pub statistics_path: Option<&'static str>,
+    /// write the statistics of the run as JSON to this file
+    pub statistics_json_path: Option<&'static str>,
+    /// write one html file with the configuration, the solutions and the statistics to this file
+    pub html_report_path: Option<&'static str>,
+    /// combinations with the same area or rank are ordered by a hash with this seed, so every run writes the same files
+    pub tie_break_seed: u64,
+    /// limits for each candidate in step3, so one bad candidate can not keep a thread busy for hours
+    pub candidate_budget: CandidateBudget,
+    /// write the candidates, which reached a limit of the budget, to this file, to retry them later
+    pub undecided_candidates_path: Option<&'static str>,
+    /// write the candidates, which the layer solver could neither place nor prove not to fit, to this file
+    pub unknown_candidates_path: Option<&'static str>,
+    /// step4 uses the unknown candidates as layers too, so no solution is lost because the heuristic missed a layout
+    pub unknown_layers_as_possible: bool,
+}
+
+impl Settings {
+    /// the layer solver step3 uses
+    pub(crate) fn layer_solver(&self) -> &'static dyn LayerSolver {
+        match self.guillotine_only {
+            true => &GuillotineSolver,
+            false => self.layer_solver,
+        }
+    }
 }
 
 impl Default for Settings {
@@ -62,17 +164,34 @@ impl Default for Settings {
             min_rectangle_amount: 1,
             max_rectangle_amount: 100,
             distance_between_rectangles: 0,
+            contact_policy: ContactPolicy::default(),
+            placement_strategies: vec![&ShelfCompact],
             steps: [false, false, false, false],
             candidates_path: None,
             fitting_candidates_path: None,
             deduplicated_combinations_path: None,
             solutions_filepath: None,
             final_combinations_path: None,
+            verdict_cache_path: None,
+            accept_mismatched_result_files: false,
+            text_render_width: None,
+            guillotine_only: false,
+            layer_solver: &HeuristicSolver,
+            near_miss_report_path: None,
+            near_miss_count: 10,
+            statistics_path: None,
+            statistics_json_path: None,
+            html_report_path: None,
+            tie_break_seed: 0,
+            candidate_budget: CandidateBudget::default(),
+            undecided_candidates_path: None,
+            unknown_candidates_path: None,
+            unknown_layers_as_possible: false,
         }
     }
 }
 
-fn main() {
+fn main() -> ExitCode {
     let start = Instant::now();
     let rects = data_configuration::mm10_rects_floor();
 
@@ -82,26 +201,237 @@ fn main() {
         min_rectangle_amount: 3,
         max_rectangle_amount: 9,
         distance_between_rectangles: 50,
+        contact_policy: ContactPolicy::default(),
+        placement_strategies: vec![&ShelfCompact, &MaxRectsBestShortSideFit, &SkylineBottomLeft, &ContactPerimeter],
         candidates_path: Some("./step1_candidates.txt"),
         deduplicated_combinations_path: Some("./step1_deduplicated_candidates.txt"),
         fitting_candidates_path: Some("./step2_fitting_candidates.txt"),
         solutions_filepath: Some("./step3_solutions.txt"),
         final_combinations_path: Some("./step4_final_candidates.txt"),
+        verdict_cache_path: Some("./step3_verdict_cache.txt"),
+        accept_mismatched_result_files: false,
+        text_render_width: Some(80),
+        guillotine_only: false,
+        layer_solver: &HeuristicSolver,
+        // e.g. Some("./step3_near_misses.txt"), costs some time for each candidate, which does not fit
+        near_miss_report_path: None,
+        near_miss_count: 20,
+        statistics_path: Some("./run_statistics.txt"),
+        statistics_json_path: Some("./run_statistics.json"),
+        html_report_path: Some("./report.html"),
+        tie_break_seed: 0,
+        candidate_budget: CandidateBudget { max_duration: Some(Duration::from_secs(600)), max_permutations: None, max_compaction_steps: None },
+        undecided_candidates_path: Some("./step2_undecided_candidates.txt"),
+        unknown_candidates_path: Some("./step2_unknown_candidates.txt"),
+        unknown_layers_as_possible: false,
         steps: [ true, true, true, false],
     };
     let mut storage = ProgramStorage::new(&rects, settings);
 
-    println!("Using blocks:\n{}\n", storage.rect_configuration.available_blocks.iter().map(|b| format!("ID: {}, area: {}", b.id, b.area)).join("\n"));
-    println!("Big rect area = {}\nSmall react area sum = {}\n", 3 * rects.big_rect.area, storage.rect_configuration.available_blocks.iter().map(|b| b.area).sum::<u32>());
+    // "holzpuzzle tui [layout file] [units per cell]" opens the layout editor instead of running the pipeline
+    let args = env::args().collect::<Vec<String>>();
+    if args.get(1).map(String::as_str) == Some("tui") {
+        let path = args.get(2).map(String::as_str).unwrap_or("./layout.txt");
+        let scale = match args.get(3).map(|s| s.parse::<RecDimension>()) {
+            Some(Ok(scale)) => Some(scale),
+            Some(Err(e)) => {
+                eprintln!("ERROR: units per cell has to be a positive number ({e})");
+                return ExitCode::FAILURE;
+            }
+            None => None,
+        };
+        return match run_tui(&storage, path, scale) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
 
-    step1_generate_candiates(&mut storage);
-    step2_deduplication(&mut storage);
-    step3_filter_fitting_candidates(&mut storage);
-    step4_calculate_matches(&mut storage);
-    step5_sort_final_combinations(&mut storage);
+    // "holzpuzzle check <heuristic|exact|guillotine|chained|sat> <ids>" asks a layer solver about one candidate
+    if args.get(1).map(String::as_str) == Some("check") {
+        let Some(solver) = args.get(2).and_then(|name| layer_solver_by_name(name)) else {
+            eprintln!("ERROR: the solver has to be one of heuristic, exact, guillotine, chained or sat");
+            return ExitCode::FAILURE;
+        };
+        return match combination_from_string(&storage, args.get(3).map(String::as_str).unwrap_or_default()) {
+            Ok(candidate) => {
+                let verdict = solver.solve(&storage, &candidate);
+                println!("{}: {verdict}", solver.name());
+                if let LayerVerdict::Fits(layout, cuts) = &verdict {
+                    for cut in cuts {
+                        println!("CUT {cut}");
+                    }
+                    println!("SOLUTION_DEBUG {}", placement_to_string(&storage.rect_configuration.resolution, layout));
+                    println!("{}", render_layout(storage.rect_configuration, layout, storage.settings.text_render_width.unwrap_or(80)));
+                }
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // "holzpuzzle retry <heuristic|exact|guillotine|chained|sat>" decides the undecided candidates of step3 again
+    if args.get(1).map(String::as_str) == Some("retry") {
+        let Some(solver) = args.get(2).and_then(|name| layer_solver_by_name(name)) else {
+            eprintln!("ERROR: the solver has to be one of heuristic, exact, guillotine, chained or sat");
+            return ExitCode::FAILURE;
+        };
+        return match step3_retry_undecided(&storage, solver) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // "holzpuzzle slack <ids>" shows by how much a candidate misses the big rect
+    if args.get(1).map(String::as_str) == Some("slack") {
+        return match combination_from_string(&storage, args.get(2).map(String::as_str).unwrap_or_default()) {
+            Ok(candidate) => {
+                println!("{}: {}", combination_to_string(&candidate), slack(&storage, &candidate).format(&storage.rect_configuration.resolution));
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // "holzpuzzle trace <ids in order, "r" for rotated, e.g. 1,4r,7> [file.gif or directory]" records how step3 places one order
+    if args.get(1).map(String::as_str) == Some("trace") {
+        let path = args.get(3).map(String::as_str).unwrap_or("./trace.gif");
+        let result = trace_order(&storage, args.get(2).map(String::as_str).unwrap_or_default()).and_then(|(order, candidate)| {
+            let fixed = storage.rect_configuration.positioned_layouts(&candidate).into_iter().next().unwrap_or_default();
+            let mut trace = PlacementTrace::default();
+            let layout = step3_check_permutation_traced(&storage, order.iter().collect(), &fixed, &BudgetTracker::unlimited(), Some(&mut trace));
+            println!("{} frames, {}", trace.frames.len(), if layout.is_some() { "fits" } else { "does not fit in this order" });
+            trace.save(&storage, path)
+        });
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // "holzpuzzle sat <export|import> <ids> <file>" writes a candidate as DIMACS CNF or reads a model of a SAT solver
+    if args.get(1).map(String::as_str) == Some("sat") {
+        let path = args.get(4).map(String::as_str).unwrap_or("./layer.cnf");
+        let result = combination_from_string(&storage, args.get(3).map(String::as_str).unwrap_or_default()).and_then(|candidate| {
+            match args.get(2).map(String::as_str) {
+                Some("export") => fs::write(path, SatInstance::new(&storage, &candidate).to_dimacs()).map_err(|e| PuzzleError::io(path, e)),
+                Some("import") => import_model(&storage, &candidate, path).map(|layout| match layout {
+                    Some(layout) => {
+                        println!("SOLUTION_DEBUG {}", placement_to_string(&storage.rect_configuration.resolution, &layout));
+                        println!("{}", render_layout(storage.rect_configuration, &layout, storage.settings.text_render_width.unwrap_or(80)));
+                    }
+                    None => println!("the SAT solver found no model, the candidate does not fit"),
+                }),
+                _ => Err(PuzzleError::Parse { context: "the command line".to_owned(), line: args[1..].join(" "), reason: "expected sat export or sat import".to_owned() }),
+            }
+        });
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // "holzpuzzle milp <export|import> <file>" writes the whole puzzle as LP file or reads the solution of a MIP solver
+    if args.get(1).map(String::as_str) == Some("milp") {
+        let path = args.get(3).map(String::as_str).unwrap_or("./puzzle.lp");
+        let result = match args.get(2).map(String::as_str) {
+            Some("export") => fs::write(path, to_lp(&storage)).map_err(|e| PuzzleError::io(path, e)),
+            Some("import") => read_solution(&storage, path).map(|solution| match solution {
+                Some(solution) => {
+                    for (k, layout) in solution.layers.iter().enumerate() {
+                        println!("LAYER {k}: SOLUTION_DEBUG {}", placement_to_string(&storage.rect_configuration.resolution, layout));
+                        println!("{}", render_layout(storage.rect_configuration, layout, storage.settings.text_render_width.unwrap_or(80)));
+                    }
+                    println!("COMBINED_SOLUTION {}", solution.combined(&storage).iter().map(combination_to_string).join(" "));
+                }
+                None => println!("the MIP solver found no solution"),
+            }),
+            _ => Err(PuzzleError::Parse { context: "the command line".to_owned(), line: args[1..].join(" "), reason: "expected milp export or milp import".to_owned() }),
+        };
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let resolution = &storage.rect_configuration.resolution;
+    println!("Resolution: {resolution}");
+    println!("Using blocks:\n{}\n", storage.rect_configuration.available_blocks.iter().map(|b| format!("ID: {}, area: {}", b.id, resolution.format_area(b.area))).join("\n"));
+    println!("Big rect area = {}\nSmall react area sum = {}\n", resolution.format_area(3 * rects.big_rect.area),
+             resolution.format_area(storage.rect_configuration.available_blocks.iter().map(|b| b.area).sum::<u32>()));
+
+    if let Err(e) = run_pipeline(&mut storage) {
+        eprintln!("ERROR: {e}");
+        return ExitCode::FAILURE;
+    }
 
     println!("The whole run took us {} seconds!", start.elapsed().as_secs());
     println!("{}", storage.solutions.len());
+    ExitCode::SUCCESS
+}
+
+/// the rectangles in the order of the command line, ids with "r" are rotated, and the candidate of them
+fn trace_order(storage: &ProgramStorage, ids: &str) -> PuzzleResult<(Vec<Rectangle>, Combination)> {
+    let parse_error = |reason: String| PuzzleError::Parse { context: "the command line".to_owned(), line: ids.to_owned(), reason };
+    let order = ids.split(',').map(|token| {
+        let (id, rotated) = match token.strip_suffix('r') {
+            Some(id) => (id, true),
+            None => (token, false),
+        };
+        let id = id.trim().parse::<RecId>().map_err(|e| parse_error(format!("\"{token}\" is not an id ({e})")))?;
+        let rect = storage.rect_configuration.get_block(id, "the command line")?;
+        match (rotated, rect.rotatable) {
+            (false, _) => Ok(rect),
+            (true, true) => Ok(rect.rotate()),
+            (true, false) => Err(parse_error(format!("piece {id} must not be rotated"))),
+        }
+    }).collect::<PuzzleResult<Vec<_>>>()?;
+    let candidate = order.iter().map(|r| storage.rect_configuration.available_block_map[&r.id]).collect();
+    Ok((order, candidate))
+}
+
+/// one step of the pipeline
+type Step = fn(&mut ProgramStorage) -> PuzzleResult<()>;
+
+/// run all steps, stop at the first error
+fn run_pipeline(storage: &mut ProgramStorage) -> PuzzleResult<()> {
+    let steps: [(&'static str, Step); 5] = [
+        ("step1", step1_generate_candiates),
+        ("step2", step2_deduplication),
+        ("step3", step3_filter_fitting_candidates),
+        ("step4", step4_calculate_matches),
+        ("step5", step5_sort_final_combinations),
+    ];
+    for (name, step) in steps {
+        let start = Instant::now();
+        step(storage)?;
+        storage.statistics.get_mut().unwrap().steps.push((name, start.elapsed()));
+    }
+    report_statistics(storage)?;
+    if let Some(path) = storage.settings.html_report_path {
+        write_html_report(storage, path)?;
+    }
+    Ok(())
 }
 
 #[test]
@@ -114,7 +444,7 @@ fn test() {
         distance_between_rectangles: 10,
         ..Default::default()
     };
-    let mut storage = ProgramStorage::new(&rects, settings);
+    let storage = ProgramStorage::new(&rects, settings);
 
     /*step1_generate_candiates(&mut storage);
     step2_deduplication(&mut storage);
@@ -164,7 +494,7 @@ fn test() {
         if let Some(data) = step3_check_candidate(100, 0, &storage, &c) {
             println!("Solution is: {}", data.iter().map(|r| format!("{} {} {}", r.rect.id, r.rect.height, r.rect.width)).join("  "));
             println!("Area is: {}", data.iter().map(|r| r.rect.area).sum::<u32>());
-            draw_image(&format!("./out{i}.png"), &storage, &data);
+            draw_image(std::env::temp_dir().join(format!("out{i}.png")).to_str().unwrap(), &storage, &data).unwrap();
         } else {
             println!("Not a solution!");
         }
@@ -0,0 +1,300 @@
+use std::collections::BTreeSet;
+use std::fs;
+
+use itertools::Itertools;
+
+use crate::data_configuration::{LAYER_COUNT, LayerConstraint};
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::ProgramStorage;
+use crate::rect::{Combination, PlacedRectangle, RecDimension, Rectangle};
+use crate::verify::verify_layout;
+
+/// a linear expression: coefficient and variable name
+type Terms = Vec<(i64, String)>;
+
+/// a side of a piece, which depends on the orientation: the length without rotation and how it changes with `r_<id>`
+#[derive(Clone, Copy)]
+struct Side {
+    length: i64,
+    rotated: i64,
+}
+
+/// the variables and sides of one piece in the MILP
+struct MilpPiece {
+    rect: Rectangle,
+    /// the piece can be used in both orientations, `r_<id>` is 1 if it is rotated
+    rotatable: bool,
+    width: Side,
+    height: Side,
+}
+
+impl MilpPiece {
+    fn new(storage: &ProgramStorage, rect: &Rectangle) -> MilpPiece {
+        let orientations = &storage.rect_configuration.rotated_available_block_map[&rect.id];
+        match orientations.iter().exactly_one() {
+            // the other orientation does not fit anywhere, or the piece must not be rotated
+            Ok(o) => MilpPiece {
+                rect: *rect,
+                rotatable: false,
+                width: Side { length: o.width as i64, rotated: 0 },
+                height: Side { length: o.height as i64, rotated: 0 },
+            },
+            Err(_) => MilpPiece {
+                rect: *rect,
+                rotatable: rect.width != rect.height,
+                width: Side { length: rect.width as i64, rotated: rect.height as i64 - rect.width as i64 },
+                height: Side { length: rect.height as i64, rotated: rect.width as i64 - rect.height as i64 },
+            },
+        }
+    }
+
+    /// `position + side` as terms and a constant
+    fn end(&self, position: &str, side: Side) -> (Terms, i64) {
+        let mut terms = vec![(1, format!("{position}_{}", self.rect.id))];
+        if self.rotatable {
+            terms.push((side.rotated, format!("r_{}", self.rect.id)));
+        }
+        (terms, side.length)
+    }
+}
+
+/// write a linear expression of the LP format
+fn expression(terms: &[(i64, String)]) -> String {
+    terms.iter()
+        .filter(|(c, _)| *c != 0)
+        .enumerate()
+        .map(|(i, (c, v))| match (i, *c) {
+            (0, 1) => v.to_owned(),
+            (0, c) => format!("{c} {v}"),
+            (_, 1) => format!("+ {v}"),
+            (_, -1) => format!("- {v}"),
+            (_, c) if c < 0 => format!("- {} {v}", -c),
+            (_, c) => format!("+ {c} {v}"),
+        })
+        .join(" ")
+}
+
+/// write a constraint of the LP format, variables on the left, constant on the right
+fn constraint(name: String, terms: &[(i64, String)], operator: &str, constant: i64) -> String {
+    format!(" {name}: {} {operator} {constant}", expression(terms))
+}
+
+/// the whole puzzle as mixed integer linear program in LP file format\
+/// each piece gets one of the layers (`a_<id>_<layer>`), an orientation (`r_<id>`) and a position (`x_<id>`, `y_<id>`),\
+/// two pieces in the same layer are left of or above each other (`s_<id>_<id>_<side>`, big M), the same for pieces and blocked zones
+pub(crate) fn to_lp(storage: &ProgramStorage) -> String {
+    let configuration = storage.rect_configuration;
+    let policy = &storage.settings.contact_policy;
+    let (width, height) = (configuration.big_rect.width as i64, configuration.big_rect.height as i64);
+    let (piece_clearance, wall) = (policy.piece_clearance as i64, policy.wall_clearance as i64);
+    let pieces = configuration.available_blocks.iter().map(|r| MilpPiece::new(storage, r)).collect::<Vec<_>>();
+    let layers = 0..LAYER_COUNT;
+    let mut constraints = vec![];
+    let mut binaries = vec![];
+
+    for piece in &pieces {
+        let id = piece.rect.id;
+        let assignment = layers.clone().map(|k| (1, format!("a_{id}_{k}"))).collect::<Terms>();
+        constraints.push(constraint(format!("layer_{id}"), &assignment, "=", 1));
+        binaries.extend(assignment.into_iter().map(|(_, v)| v));
+        if piece.rotatable {
+            binaries.push(format!("r_{id}"));
+        }
+        for (position, side, size) in [("x", piece.width, width), ("y", piece.height, height)] {
+            let (terms, length) = piece.end(position, side);
+            constraints.push(constraint(format!("inside_{position}_{id}"), &terms, "<=", size - wall - length));
+        }
+        if let Some(pin) = configuration.pin(id) {
+            if let Some(layer) = pin.layer {
+                constraints.push(constraint(format!("pin_layer_{id}"), &[(1, format!("a_{id}_{layer}"))], "=", 1));
+            }
+            if let (true, Some(rotated)) = (piece.rotatable, pin.rotated) {
+                constraints.push(constraint(format!("pin_rotated_{id}"), &[(1, format!("r_{id}"))], "=", rotated as i64));
+            }
+            if let Some((x, y)) = pin.position {
+                constraints.push(constraint(format!("pin_x_{id}"), &[(1, format!("x_{id}"))], "=", x as i64));
+                constraints.push(constraint(format!("pin_y_{id}"), &[(1, format!("y_{id}"))], "=", y as i64));
+            }
+        }
+        // outside of each blocked zone: left, right, above or below of it
+        for (n, zone) in configuration.blocked_zones.iter().enumerate() {
+            let sides = ["l", "r", "a", "b"].map(|side| format!("z_{id}_{n}_{side}"));
+            constraints.push(constraint(format!("zone_{id}_{n}"), &sides.iter().map(|s| (1, s.clone())).collect::<Terms>(), ">=", 1));
+            for (position, side, z, zone_length, m, (before, after)) in [
+                ("x", piece.width, zone.x as i64, zone.rect.width as i64, width + wall, (&sides[0], &sides[1])),
+                ("y", piece.height, zone.y as i64, zone.rect.height as i64, height + wall, (&sides[2], &sides[3])),
+            ] {
+                // position + side + wall <= z + M (1 - before)
+                let (mut terms, length) = piece.end(position, side);
+                terms.push((m, before.clone()));
+                constraints.push(constraint(format!("c_{before}"), &terms, "<=", m + z - length - wall));
+                // position >= z + zone_length + wall - M (1 - after)
+                let terms = [(-1, format!("{position}_{id}")), (m, after.clone())];
+                constraints.push(constraint(format!("c_{after}"), &terms, "<=", m - z - zone_length - wall));
+            }
+            binaries.extend(sides);
+        }
+    }
+
+    for (a, b) in pieces.iter().tuple_combinations() {
+        let (i, j) = (a.rect.id, b.rect.id);
+        let sides = [(a, b, "x"), (b, a, "x"), (a, b, "y"), (b, a, "y")]
+            .map(|(first, second, position)| (first, second, position, format!("s_{}_{}_{position}", first.rect.id, second.rect.id)));
+        // if both pieces are in the same layer, at least one of them is left of or above the other one
+        for k in layers.clone() {
+            let mut terms = sides.iter().map(|s| (1, s.3.clone())).collect::<Terms>();
+            terms.extend([(-1, format!("a_{i}_{k}")), (-1, format!("a_{j}_{k}"))]);
+            constraints.push(constraint(format!("same_layer_{i}_{j}_{k}"), &terms, ">=", -1));
+        }
+        for (first, second, position, side) in sides {
+            // first + length + clearance <= second + M (1 - side)
+            let (length, m) = match position {
+                "x" => (first.width, width + piece_clearance),
+                _ => (first.height, height + piece_clearance),
+            };
+            let (mut terms, length) = first.end(position, length);
+            terms.extend([(-1, format!("{position}_{}", second.rect.id)), (m, side.clone())]);
+            constraints.push(constraint(format!("c_{side}"), &terms, "<=", m - length - piece_clearance));
+            binaries.push(side);
+        }
+    }
+
+    for (n, layer_constraint) in configuration.layer_constraints.iter().enumerate() {
+        match layer_constraint {
+            LayerConstraint::Together(ids) => for ((i, j), k) in ids.iter().tuple_windows().cartesian_product(layers.clone()) {
+                constraints.push(constraint(format!("together_{n}_{i}_{j}_{k}"), &[(1, format!("a_{i}_{k}")), (-1, format!("a_{j}_{k}"))], "=", 0));
+            },
+            LayerConstraint::Apart(ids) => for k in layers.clone() {
+                constraints.push(constraint(format!("apart_{n}_{k}"), &ids.iter().map(|id| (1, format!("a_{id}_{k}"))).collect::<Terms>(), "<=", 1));
+            },
+            LayerConstraint::InLayer(ids, k) => for id in ids {
+                constraints.push(constraint(format!("in_layer_{n}_{id}"), &[(1, format!("a_{id}_{k}"))], "=", 1));
+            },
+        }
+    }
+
+    let positions = pieces.iter().flat_map(|p| [format!("x_{}", p.rect.id), format!("y_{}", p.rect.id)]).collect::<Vec<_>>();
+    [
+        format!("\\ holzpuzzle: {} pieces in {LAYER_COUNT} layers, big rect {width}x{height}", pieces.len()),
+        // push the pieces towards the upper left corner, any feasible solution is fine
+        "Minimize".to_owned(),
+        format!(" obj: {}", expression(&positions.iter().map(|p| (1, p.clone())).collect::<Terms>())),
+        "Subject To".to_owned(),
+        constraints.join("\n"),
+        "Bounds".to_owned(),
+        positions.iter().zip([width, height].iter().cycle()).map(|(p, size)| format!(" {wall} <= {p} <= {size}")).join("\n"),
+        "General".to_owned(),
+        format!(" {}", positions.join(" ")),
+        "Binary".to_owned(),
+        format!(" {}", binaries.join(" ")),
+        "End".to_owned(),
+    ].join("\n")
+}
+
+/// all layers of a solution with the layout of each one, 0 is the bottom layer
+#[derive(Debug, Clone)]
+pub(crate) struct LayeredSolution {
+    pub layers: Vec<Vec<PlacedRectangle>>,
+}
+
+impl LayeredSolution {
+    /// the pieces of each layer, as in the combined solutions of step4
+    pub(crate) fn combined(&self, storage: &ProgramStorage) -> BTreeSet<Combination> {
+        self.layers.iter()
+            .map(|layout| layout.iter().map(|p| storage.rect_configuration.available_block_map[&p.rect.id]).collect())
+            .collect()
+    }
+}
+
+/// read the solution file of a MIP solver for the program of `to_lp`\
+/// lines with a variable name followed by its value are used (CBC, Gurobi, HiGHS, SCIP), everything else is skipped\
+/// returns `Ok(None)` if the solver reports, that there is no solution
+pub(crate) fn read_solution(storage: &ProgramStorage, filepath: &str) -> PuzzleResult<Option<LayeredSolution>> {
+    let text = fs::read_to_string(filepath).map_err(|e| PuzzleError::io(filepath, e))?;
+    if text.lines().take(5).any(|l| l.to_lowercase().contains("infeasible")) {
+        return Ok(None);
+    }
+    let mut values = std::collections::HashMap::new();
+    for line in text.lines() {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        if let Some(i) = tokens.iter().position(|t| t.starts_with(['a', 'r', 'x', 'y']) && t[1..].starts_with('_')) {
+            let value = tokens.get(i + 1).map(|v| v.parse::<f64>()).unwrap_or(Ok(0.0)).map_err(|e| PuzzleError::Parse {
+                context: filepath.to_owned(), line: line.to_owned(), reason: format!("the value of {} is not a number ({e})", tokens[i]),
+            })?;
+            values.insert(tokens[i].to_owned(), value);
+        }
+    }
+    let value = |name: String| values.get(&name).copied().unwrap_or(0.0);
+    let mut layers = vec![vec![]; LAYER_COUNT as usize];
+    for piece in storage.rect_configuration.available_blocks.iter().map(|r| MilpPiece::new(storage, r)) {
+        let id = piece.rect.id;
+        let layer = (0..LAYER_COUNT).max_by(|a, b| value(format!("a_{id}_{a}")).total_cmp(&value(format!("a_{id}_{b}")))).unwrap_or_default();
+        let rotated = piece.rotatable && value(format!("r_{id}")) > 0.5 ||
+            !piece.rotatable && piece.width.length != piece.rect.width as i64;
+        layers[layer as usize].push(PlacedRectangle {
+            rect: if rotated { piece.rect.rotate() } else { piece.rect },
+            x: value(format!("x_{id}")).round().max(0.0) as RecDimension,
+            y: value(format!("y_{id}")).round().max(0.0) as RecDimension,
+        });
+    }
+    let solution = LayeredSolution { layers };
+    let configuration = storage.rect_configuration;
+    let mut problems = vec![];
+    for (k, layout) in solution.layers.iter().enumerate() {
+        let candidate: Combination = layout.iter().map(|p| configuration.available_block_map[&p.rect.id]).collect();
+        problems.extend(verify_layout(storage, &candidate, layout).iter().map(|p| p.to_string()));
+        problems.extend(candidate.iter()
+            .filter(|r| configuration.pin(r.id).and_then(|p| p.layer).is_some_and(|layer| layer as usize != k))
+            .map(|r| format!("piece {} is not in its pinned layer", r.id)));
+        if !candidate.is_empty() && !configuration.allows_layer(&candidate) {
+            problems.push(format!("layer {k} breaks a pin or layer constraint"));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(PuzzleError::InvalidLayout { context: filepath.to_owned(), problems: problems.join(", ") });
+    }
+    Ok(Some(solution))
+}
+
+#[test]
+fn test_milp_round_trip() {
+    use crate::data_configuration::{Pin, RectConfiguration};
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 2, 2), Rectangle::new(3, 2, 4), Rectangle::new(4, 1, 2)],
+    ).with_pin(3, Pin { layer: Some(2), ..Default::default() }).unwrap();
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let lp = to_lp(&storage);
+    for line in [
+        " layer_1: a_1_0 + a_1_1 + a_1_2 = 1",
+        " inside_x_4: x_4 - r_4 <= 2",
+        " inside_y_4: y_4 + r_4 <= 1",
+        " pin_layer_3: a_3_2 = 1",
+        " same_layer_1_2_0: s_1_2_x + s_2_1_x + s_1_2_y + s_2_1_y - a_1_0 - a_2_0 >= -1",
+        " c_s_1_2_x: x_1 - x_2 + 4 s_1_2_x <= 2",
+        " c_s_4_1_y: y_4 + r_4 - y_1 + 2 s_4_1_y <= 1",
+    ] {
+        assert!(lp.lines().any(|l| l == line), "{line} is missing in\n{lp}");
+    }
+
+    let path = std::env::temp_dir().join(format!("holzpuzzle_test_{}.sol", std::process::id()));
+    let path = path.to_str().unwrap();
+    // Gurobi writes "name value", CBC "index name value cost"
+    let solution_text = |x2: &str, layer3: &str| format!("# Objective value = 2\na_1_0 1\na_2_0 1\n      3 x_2     {x2}     0\n{layer3} 1\na_4_1 1\nr_4 1\n");
+    fs::write(path, solution_text("2", "a_3_2")).unwrap();
+    let solution = read_solution(&storage, path).unwrap().unwrap();
+    assert_eq!(solution.layers.iter().map(|l| l.iter().map(|p| (p.rect.id, p.x, p.y, p.rect.width)).collect::<Vec<_>>()).collect::<Vec<_>>(),
+               vec![vec![(1, 0, 0, 2), (2, 2, 0, 2)], vec![(4, 0, 0, 1)], vec![(3, 0, 0, 4)]]);
+    assert_eq!(solution.combined(&storage).len(), 3);
+
+    // overlapping pieces and pieces outside of their pinned layer are found
+    fs::write(path, solution_text("1", "a_3_2")).unwrap();
+    assert!(matches!(read_solution(&storage, path), Err(PuzzleError::InvalidLayout { .. })));
+    fs::write(path, solution_text("2", "a_3_1")).unwrap();
+    assert!(matches!(read_solution(&storage, path), Err(PuzzleError::InvalidLayout { .. })));
+    fs::write(path, "Infeasible - objective value 0\n").unwrap();
+    assert!(read_solution(&storage, path).unwrap().is_none());
+    let _ = fs::remove_file(path);
+}
@@ -0,0 +1,155 @@
+use std::fs;
+
+use itertools::Itertools;
+
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::free_space::FreeSpace;
+use crate::layer_solver::free_pieces;
+use crate::ProgramStorage;
+use crate::rect::{Combination, combination_to_string, PlacedRectangle, placement_to_string, RecDimension, Rectangle};
+use crate::text_render::render_layout;
+
+/// orders and orientations tried for each candidate, which does not fit
+const ATTEMPTS: usize = 2000;
+
+/// the best attempt for a candidate, which does not fit
+#[derive(Debug, Clone)]
+pub(crate) struct NearMiss {
+    pub candidate: Combination,
+    /// the layout with the biggest placed area, without the left out pieces
+    pub layout: Vec<PlacedRectangle>,
+    pub left_out: Vec<Rectangle>,
+    /// how far the pieces stick out of the big rect at least, at the bottom or on the right, when all of them are placed
+    pub overflow: Option<RecDimension>,
+}
+
+impl NearMiss {
+    pub(crate) fn missing_area(&self) -> u32 {
+        self.left_out.iter().map(|r| r.area).sum()
+    }
+
+    /// the closest near misses first
+    fn rank(&self) -> (u32, RecDimension, String) {
+        (self.missing_area(), self.overflow.unwrap_or(RecDimension::MAX), combination_to_string(&self.candidate))
+    }
+}
+
+/// put each rectangle into the lowest, then leftmost free corner, rectangles without space are left out
+fn place_greedy(mut space: FreeSpace, rects: &[Rectangle], clearance: RecDimension) -> (Vec<PlacedRectangle>, Vec<Rectangle>) {
+    let mut placed = vec![];
+    let mut left_out = vec![];
+    for rect in rects {
+        match space.fitting(rect.width, rect.height).map(|(x, y, _, _)| (*y, *x)).min() {
+            Some((y, x)) => {
+                let p = PlacedRectangle { rect: *rect, x, y };
+                space.occupy_around(&p, clearance);
+                placed.push(p);
+            }
+            None => left_out.push(*rect),
+        }
+    }
+    (placed, left_out)
+}
+
+/// try some orders and orientations of the pieces and keep the best partial layout and the smallest overflow\
+/// returns `None` if the pinned pieces can not be placed at all
+pub(crate) fn analyze_near_miss(storage: &ProgramStorage, candidate: &Combination) -> Option<NearMiss> {
+    let configuration = storage.rect_configuration;
+    let (big_rect, wall) = (configuration.big_rect, storage.settings.contact_policy.wall_clearance);
+    let clearance = storage.settings.contact_policy.piece_clearance;
+    let fixed = configuration.positioned_layouts(candidate).into_iter().next()?;
+    // the biggest pieces first, so the first order is the most promising one
+    let free = free_pieces(storage, candidate).into_iter().sorted_by_key(|r| std::cmp::Reverse(r.area)).collect::<Vec<_>>();
+    let orientations = free.iter()
+        .map(|r| match configuration.rotated_available_block_map[&r.id].iter().sorted().collect::<Vec<_>>() {
+            // the piece fits nowhere, so it will be left out anyway
+            orientations if orientations.is_empty() => vec![*r],
+            orientations => orientations,
+        })
+        .collect::<Vec<_>>();
+    // enough space for all pieces below and right of the big rect
+    let extra = free.iter().map(|r| r.width.max(r.height) + clearance).sum::<RecDimension>();
+    let mut best: Option<NearMiss> = None;
+    let attempts = (0..free.len()).permutations(free.len())
+        .flat_map(|order| orientations.iter().multi_cartesian_product().map(move |product| order.iter().map(|i| **product[*i]).collect::<Vec<_>>()))
+        .take(ATTEMPTS);
+    for rects in attempts {
+        let (mut layout, left_out) = place_greedy(FreeSpace::for_container(storage, &fixed), &rects, clearance);
+        let overflow = [(big_rect.width, big_rect.height + extra), (big_rect.width + extra, big_rect.height)].into_iter()
+            .filter_map(|(width, height)| {
+                let (placed, left_out) = place_greedy(FreeSpace::for_container_of_size(storage, &fixed, width, height), &rects, clearance);
+                left_out.is_empty().then(|| placed.iter()
+                    .map(|p| (p.x + p.rect.width + wall).saturating_sub(big_rect.width).max((p.y + p.rect.height + wall).saturating_sub(big_rect.height)))
+                    .max()
+                    .unwrap_or(0))
+            })
+            .min();
+        layout.extend_from_slice(&fixed);
+        let attempt = NearMiss { candidate: candidate.clone(), layout, left_out, overflow };
+        let overflow = best.as_ref().and_then(|b| b.overflow).into_iter().chain(overflow).min();
+        best = Some(match best {
+            Some(b) if b.missing_area() <= attempt.missing_area() => NearMiss { overflow, ..b },
+            _ => NearMiss { overflow, ..attempt },
+        });
+    }
+    best
+}
+
+/// keep the closest near misses, at most `count`
+pub(crate) fn record_near_miss(near_misses: &mut Vec<NearMiss>, near_miss: NearMiss, count: usize) {
+    near_misses.push(near_miss);
+    near_misses.sort_by_cached_key(NearMiss::rank);
+    near_misses.truncate(count);
+}
+
+/// write the near misses with their layouts, in the format of `SOLUTION_DEBUG` and as text
+pub(crate) fn write_near_miss_report(storage: &ProgramStorage, path: &str, near_misses: &[NearMiss]) -> PuzzleResult<()> {
+    let resolution = &storage.rect_configuration.resolution;
+    let width = storage.settings.text_render_width.unwrap_or(80);
+    let mut report = vec!["# candidates, which do not fit, closest first: the biggest placed area and how far all pieces stick out".to_owned()];
+    for (i, near_miss) in near_misses.iter().enumerate() {
+        let area = near_miss.candidate.iter().map(|r| r.area).sum::<u32>();
+        report.push(format!("\n{}. candidate {}: placed {} of {} {unit}², left out {}, overflow {}",
+                            i + 1, combination_to_string(&near_miss.candidate),
+                            resolution.format_area(area - near_miss.missing_area()), resolution.format_area(area),
+                            near_miss.left_out.iter().map(|r| r.id).join(","),
+                            near_miss.overflow.map_or("unknown".to_owned(), |o| format!("{} {}", resolution.format(o), resolution.unit)),
+                            unit = resolution.unit));
+        report.push(format!("SOLUTION_DEBUG {}", placement_to_string(resolution, &near_miss.layout)));
+        report.push(render_layout(storage.rect_configuration, &near_miss.layout, width));
+    }
+    fs::write(path, report.join("\n")).map_err(|e| PuzzleError::io(path, e))
+}
+
+#[test]
+fn test_near_miss() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::Settings;
+
+    // one unit of area too much: everything but the small piece fits perfectly
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 4, 6),
+        vec![Rectangle::new(1, 4, 2), Rectangle::new(2, 2, 4), Rectangle::new(3, 2, 4), Rectangle::new(4, 1, 1)],
+    );
+    let candidate: Combination = [1, 2, 3, 4].iter().map(|id| rects.available_block_map[id]).collect();
+    let storage = ProgramStorage::new(&rects, Settings { text_render_width: Some(12), ..Default::default() });
+    let near_miss = analyze_near_miss(&storage, &candidate).unwrap();
+    assert_eq!(near_miss.left_out.iter().map(|r| r.id).collect::<Vec<_>>(), vec![4]);
+    assert_eq!(near_miss.layout.len(), 3);
+    assert_eq!(near_miss.overflow, Some(1));
+
+    // only the closest ones are kept
+    let mut near_misses = vec![];
+    let farther = NearMiss { left_out: vec![rects.available_block_map[&1]], ..near_miss.clone() };
+    record_near_miss(&mut near_misses, farther, 1);
+    record_near_miss(&mut near_misses, near_miss, 1);
+    assert_eq!(near_misses[0].missing_area(), 1);
+
+    let path = std::env::temp_dir().join(format!("holzpuzzle_test_{}_near_misses.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+    write_near_miss_report(&storage, path, &near_misses).unwrap();
+    let report = fs::read_to_string(path).unwrap();
+    assert!(report.contains("1. candidate 1,2,3,4: placed 24 of 25 units², left out 4, overflow 1 units"), "{report}");
+    assert!(report.contains("SOLUTION_DEBUG"));
+    let _ = fs::remove_file(path);
+}
@@ -0,0 +1,253 @@
+use std::cmp::{max, min, Reverse};
+
+use crate::budget::BudgetTracker;
+use crate::free_space::{FreeSpace, Region};
+use crate::ProgramStorage;
+use crate::rect::{PlacedRectangle, RecDimension, Rectangle};
+use crate::steps::step3_check_permutation_traced;
+
+/// a heuristic, which tries to place rectangles into the big rect in the given order and orientation\
+/// `fixed` are rectangles, which are already placed and must not be moved, they are part of the returned layout
+pub(crate) trait PlacementStrategy: Sync {
+    /// name for the log and the fingerprints of cache and result files
+    fn name(&self) -> &'static str;
+
+    /// `budget` is shared by all orders of one candidate, strategies with many steps per order count them there
+    fn place(&self, storage: &ProgramStorage, rects: &[&Rectangle], fixed: &[PlacedRectangle], budget: &BudgetTracker) -> Option<Vec<PlacedRectangle>>;
+}
+
+/// put the rectangles into rows with some space between them, then push them towards (0, 0)
+pub(crate) struct ShelfCompact;
+
+impl PlacementStrategy for ShelfCompact {
+    fn name(&self) -> &'static str {
+        "shelf_compact"
+    }
+
+    fn place(&self, storage: &ProgramStorage, rects: &[&Rectangle], fixed: &[PlacedRectangle], budget: &BudgetTracker) -> Option<Vec<PlacedRectangle>> {
+        step3_check_permutation_traced(storage, rects.to_vec(), fixed, budget, None)
+    }
+}
+
+/// keep the upper outline of the placed rectangles and put each rectangle at the lowest, then leftmost spot on it
+pub(crate) struct SkylineBottomLeft;
+
+/// part of the skyline: x, y and width
+type Segment = (RecDimension, RecDimension, RecDimension);
+
+impl PlacementStrategy for SkylineBottomLeft {
+    fn name(&self) -> &'static str {
+        "skyline_bottom_left"
+    }
+
+    fn place(&self, storage: &ProgramStorage, rects: &[&Rectangle], fixed: &[PlacedRectangle], _budget: &BudgetTracker) -> Option<Vec<PlacedRectangle>> {
+        let policy = &storage.settings.contact_policy;
+        let big_rect = storage.rect_configuration.big_rect;
+        let wall = policy.wall_clearance;
+        let right = big_rect.width.checked_sub(wall).filter(|r| *r > wall)?;
+        let obstacles = storage.rect_configuration.blocked_zones.iter().chain(fixed).copied().collect::<Vec<_>>();
+        let mut skyline: Vec<Segment> = vec![(wall, wall, right - wall)];
+        let mut placed: Vec<PlacedRectangle> = vec![];
+        for rect in rects {
+            let (y, x) = skyline.iter()
+                .map(|(x, _, _)| *x)
+                .filter(|x| x + rect.width <= right)
+                .filter_map(|x| {
+                    let mut p = PlacedRectangle { rect: **rect, x, y: skyline_height(&skyline, x, rect.width) };
+                    // the skyline does not know the obstacles and the clearance to the sides, go above them
+                    while let Some(o) = obstacles.iter().chain(&placed).find(|o| o.check_clearance(&p, policy.clearance_to(o))) {
+                        p.y = o.y + o.rect.height + policy.clearance_to(o);
+                    }
+                    (p.y + rect.height + wall <= big_rect.height).then_some((p.y, x))
+                })
+                .min()?;
+            // the clearance to the right belongs to the rect, so the next one starts after it
+            let clearance = policy.piece_clearance;
+            raise_skyline(&mut skyline, x, min(x + rect.width + clearance, right), y + rect.height + clearance);
+            placed.push(PlacedRectangle { rect: **rect, x, y });
+        }
+        placed.extend_from_slice(fixed);
+        Some(placed)
+    }
+}
+
+/// the highest point of the skyline between x and x + width
+fn skyline_height(skyline: &[Segment], x: RecDimension, width: RecDimension) -> RecDimension {
+    skyline.iter()
+        .filter(|(sx, _, sw)| *sx < x + width && x < sx + sw)
+        .map(|(_, y, _)| *y)
+        .max()
+        .unwrap_or(0)
+}
+
+/// raise the skyline between x0 and x1 to at least `height`, merging neighbours with the same height
+fn raise_skyline(skyline: &mut Vec<Segment>, x0: RecDimension, x1: RecDimension, height: RecDimension) {
+    let mut raised = vec![];
+    for &(x, y, w) in skyline.iter() {
+        // the parts left of, inside of and right of the raised range
+        let (start, end) = (max(x, x0), min(x + w, x1));
+        if x < x0 {
+            raised.push((x, y, min(x + w, x0) - x));
+        }
+        if start < end {
+            raised.push((start, max(y, height), end - start));
+        }
+        if x + w > x1 {
+            raised.push((max(x, x1), y, x + w - max(x, x1)));
+        }
+    }
+    raised.sort();
+    skyline.clear();
+    for segment in raised {
+        match skyline.last_mut() {
+            Some(last) if last.1 == segment.1 && last.0 + last.2 == segment.0 => last.2 += segment.2,
+            _ => skyline.push(segment),
+        }
+    }
+}
+
+/// put each rectangle into the corner of a free maximal rectangle, where it touches the most walls and other rectangles
+pub(crate) struct ContactPerimeter;
+
+impl PlacementStrategy for ContactPerimeter {
+    fn name(&self) -> &'static str {
+        "contact_perimeter"
+    }
+
+    fn place(&self, storage: &ProgramStorage, rects: &[&Rectangle], fixed: &[PlacedRectangle], _budget: &BudgetTracker) -> Option<Vec<PlacedRectangle>> {
+        place_in_free_space(storage, rects, fixed, |spot, placed| {
+            let p = &spot.placed;
+            let others = storage.rect_configuration.blocked_zones.iter().chain(fixed).chain(placed);
+            (contact_perimeter(storage, p, others), Reverse(p.y), Reverse(p.x))
+        })
+    }
+}
+
+/// length of the edges of `p`, which touch a wall or another rectangle, as close as the contact policy allows
+pub(crate) fn contact_perimeter<'a, I>(storage: &ProgramStorage, p: &PlacedRectangle, others: I) -> RecDimension
+    where
+        I: IntoIterator<Item=&'a PlacedRectangle>
+{
+    let policy = &storage.settings.contact_policy;
+    let big_rect = storage.rect_configuration.big_rect;
+    let wall = policy.wall_clearance;
+    let (width, height) = (p.rect.width, p.rect.height);
+    let mut contact = 0;
+    if p.x == wall {
+        contact += height;
+    }
+    if p.x + width + wall == big_rect.width {
+        contact += height;
+    }
+    if p.y == wall {
+        contact += width;
+    }
+    if p.y + height + wall == big_rect.height {
+        contact += width;
+    }
+    for o in others {
+        let clearance = policy.clearance_to(o);
+        if o.x + o.rect.width + clearance == p.x || p.x + width + clearance == o.x {
+            contact += min(p.y + height, o.y + o.rect.height).saturating_sub(max(p.y, o.y));
+        }
+        if o.y + o.rect.height + clearance == p.y || p.y + height + clearance == o.y {
+            contact += min(p.x + width, o.x + o.rect.width).saturating_sub(max(p.x, o.x));
+        }
+    }
+    contact
+}
+
+/// put each rectangle into the free maximal rectangle, where the shorter leftover side is the shortest
+pub(crate) struct MaxRectsBestShortSideFit;
+
+impl PlacementStrategy for MaxRectsBestShortSideFit {
+    fn name(&self) -> &'static str {
+        "maxrects_best_short_side_fit"
+    }
+
+    fn place(&self, storage: &ProgramStorage, rects: &[&Rectangle], fixed: &[PlacedRectangle], _budget: &BudgetTracker) -> Option<Vec<PlacedRectangle>> {
+        place_in_free_space(storage, rects, fixed, |spot, _| {
+            let (_, _, width, height) = spot.free;
+            let (dw, dh) = (width - spot.placed.rect.width, height - spot.placed.rect.height);
+            Reverse((min(dw, dh), max(dw, dh), spot.placed.y, spot.placed.x))
+        })
+    }
+}
+
+/// a possible position: the corner of a free maximal rectangle
+struct Spot {
+    placed: PlacedRectangle,
+    free: Region,
+}
+
+/// place the rectangles one after another into the corner of the free maximal rectangle with the best score
+fn place_in_free_space<K, F>(storage: &ProgramStorage, rects: &[&Rectangle], fixed: &[PlacedRectangle], score: F) -> Option<Vec<PlacedRectangle>>
+    where
+        K: Ord,
+        F: Fn(&Spot, &[PlacedRectangle]) -> K
+{
+    let clearance = storage.settings.contact_policy.piece_clearance;
+    let mut space = FreeSpace::for_container(storage, fixed);
+    let mut placed: Vec<PlacedRectangle> = vec![];
+    for rect in rects {
+        let best = space.fitting(rect.width, rect.height)
+            .map(|&free| Spot { placed: PlacedRectangle { rect: **rect, x: free.0, y: free.1 }, free })
+            .max_by_key(|spot| score(spot, &placed))?
+            .placed;
+        space.occupy_around(&best, clearance);
+        placed.push(best);
+    }
+    placed.extend_from_slice(fixed);
+    Some(placed)
+}
+
+#[test]
+fn test_placement_strategies() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::rect::{Combination, ContactPolicy};
+    use crate::steps::step3_check_candidate;
+    use crate::verify::verify_layout;
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 4, 6),
+        vec![Rectangle::new(1, 4, 2), Rectangle::new(2, 2, 4), Rectangle::new(3, 2, 4), Rectangle::new(4, 1, 1)],
+    );
+    let candidate: Combination = [1, 2, 3].iter().map(|id| rects.available_block_map[id]).collect();
+    let ordered = candidate.iter().collect::<Vec<_>>();
+    let strategies: [&dyn PlacementStrategy; 3] = [&SkylineBottomLeft, &ContactPerimeter, &MaxRectsBestShortSideFit];
+
+    // a perfect packing: the tall piece on the left, the flat ones on top of each other
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    for strategy in strategies {
+        let layout = strategy.place(&storage, &ordered, &[], &BudgetTracker::unlimited()).unwrap();
+        assert!(verify_layout(&storage, &candidate, &layout).is_empty(), "{}", strategy.name());
+        assert_eq!(layout.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>(), vec![(0, 0), (2, 0), (2, 2)], "{}", strategy.name());
+    }
+    // walls and other pieces count the same, on a tie the lowest, then leftmost corner wins
+    let fixed = [PlacedRectangle { rect: rects.available_block_map[&1], x: 2, y: 0 }];
+    let small = |x, y| PlacedRectangle { rect: rects.available_block_map[&4], x, y };
+    assert_eq!(contact_perimeter(&storage, &small(4, 0), &fixed), 2);
+    assert_eq!(contact_perimeter(&storage, &small(4, 1), &fixed), 1);
+    assert_eq!(contact_perimeter(&storage, &small(5, 3), &fixed), 2);
+    assert_eq!(contact_perimeter(&storage, &PlacedRectangle { rect: rects.available_block_map[&1], x: 4, y: 0 }, &fixed), 12);
+    let layout = ContactPerimeter.place(&storage, &[&rects.available_block_map[&4]], &fixed, &BudgetTracker::unlimited()).unwrap();
+    assert_eq!((layout[0].x, layout[0].y), (0, 0));
+
+    // with clearance the pieces keep their distance, or do not fit at all
+    let storage = ProgramStorage::new(&rects, Settings { contact_policy: ContactPolicy { piece_clearance: 1, wall_clearance: 0 }, ..Default::default() });
+    for strategy in strategies {
+        assert!(strategy.place(&storage, &ordered, &[], &BudgetTracker::unlimited()).is_none(), "{}", strategy.name());
+    }
+    let roomy = RectConfiguration::new(Rectangle::new(-1, 5, 7), rects.available_blocks.clone());
+    let storage = ProgramStorage::new(&roomy, Settings { contact_policy: ContactPolicy { piece_clearance: 1, wall_clearance: 0 }, ..Default::default() });
+    for strategy in strategies {
+        let layout = strategy.place(&storage, &ordered, &[], &BudgetTracker::unlimited()).unwrap_or_else(|| panic!("{}", strategy.name()));
+        assert!(verify_layout(&storage, &candidate, &layout).is_empty(), "{}", strategy.name());
+    }
+
+    // step3 tries the strategies in the configured order and records which one placed the candidate
+    let storage = ProgramStorage::new(&rects, Settings { placement_strategies: vec![&MaxRectsBestShortSideFit, &ShelfCompact], ..Default::default() });
+    assert!(step3_check_candidate(0, 0, &storage, &candidate).is_some());
+    assert_eq!(storage.placements_by_strategy.lock().unwrap().iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(), vec![("maxrects_best_short_side_fit", 1)]);
+}
@@ -1,13 +1,16 @@
-use std::borrow::Borrow;
-use std::cmp::{min, Ordering};
+use std::cmp::{min, Ordering, Reverse};
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::collections::hash_map::DefaultHasher;
-use std::fs;
 use std::hash::{Hash, Hasher};
 
 use itertools::Itertools;
 
+use crate::data_configuration::BLOCKED_ZONE_ID;
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::fingerprint::Fingerprint;
 use crate::ProgramStorage;
+use crate::resolution::Resolution;
+use crate::result_file::{read_result_file_allowing, ResultStep, write_result_file};
 
 pub type RecId = i8;
 pub type RecDimension = u32;
@@ -15,8 +18,17 @@ pub(crate) type Combination = BTreeSet<Rectangle>;
 pub(crate) type RectCombinationStorage = HashSet<Combination>;
 
 /// load a combination from a string
-pub(crate) fn combination_from_string(storage: &ProgramStorage, line: &str) -> Combination {
-    line.split(',').map(|x| *storage.rect_configuration.available_block_map.get(&x.parse::<RecId>().unwrap()).unwrap()).collect()
+pub(crate) fn combination_from_string(storage: &ProgramStorage, line: &str) -> PuzzleResult<Combination> {
+    line.split(',')
+        .map(|x| {
+            let id = x.trim().parse::<RecId>().map_err(|e| PuzzleError::Parse {
+                context: "combination".to_owned(),
+                line: line.to_owned(),
+                reason: format!("\"{x}\" is not an id ({e})"),
+            })?;
+            storage.rect_configuration.get_block(id, &format!("combination \"{line}\""))
+        })
+        .collect()
 }
 
 /// convert combination to string
@@ -24,31 +36,104 @@ pub(crate) fn combination_to_string(combination: &Combination) -> String {
     combination.iter().map(|r| r.id).join(",")
 }
 
+/// stable total order of combinations: the biggest area first, ties are broken by a hash of the ids with the seed, then by the ids\
+/// the same seed gives the same order in every run, another seed shuffles the ties
+pub(crate) fn combination_order_key(combination: &Combination, seed: u64) -> (Reverse<u32>, u64, Vec<RecId>) {
+    let mut tie_break = Fingerprint::new();
+    tie_break.field("seed", seed);
+    for r in combination {
+        tie_break.field("id", r.id as u64);
+    }
+    (Reverse(combination.iter().map(|r| r.area).sum()), tie_break.finish(), combination.iter().map(|r| r.id).collect())
+}
+
 /// load all combinations from file
-pub(crate) fn combination_storage_from_file(filepath: &str, storage: &ProgramStorage) -> RectCombinationStorage {
-    fs::read_to_string(filepath).unwrap_or_else(|_| "".to_owned())
-        .split('\n').filter(|s| !s.is_empty())
-        .map(|line| combination_from_string(storage, line))
-        .collect::<RectCombinationStorage>()
+pub(crate) fn combination_storage_from_file(filepath: &str, step: ResultStep, storage: &ProgramStorage) -> PuzzleResult<RectCombinationStorage> {
+    combination_storage_from_file_allowing(filepath, step, storage, &[])
+}
+
+/// load all combinations from file, the settings in `may_differ` do not have to match the file
+pub(crate) fn combination_storage_from_file_allowing(filepath: &str, step: ResultStep, storage: &ProgramStorage, may_differ: &[&str]) -> PuzzleResult<RectCombinationStorage> {
+    read_result_file_allowing(filepath, step, storage, may_differ)?.unwrap_or_default().iter()
+        .map(|line| combination_from_string(storage, line).map_err(|e| in_file(e, filepath)))
+        .collect::<PuzzleResult<RectCombinationStorage>>()
+}
+
+/// add the file name to the context of a parse error
+pub(crate) fn in_file(error: PuzzleError, filepath: &str) -> PuzzleError {
+    match error {
+        PuzzleError::Parse { context, line, reason } => PuzzleError::Parse { context: format!("{context} in {filepath}"), line, reason },
+        PuzzleError::UnknownPieceId { id, context } => PuzzleError::UnknownPieceId { id, context: format!("{context} in {filepath}") },
+        e => e,
+    }
 }
 
 /// store combinations to file
-pub(crate) fn combination_storage_to_file(filepath: &str, combination_storage: &RectCombinationStorage) {
-    fs::write(
+pub(crate) fn combination_storage_to_file(filepath: &str, step: ResultStep, storage: &ProgramStorage, combination_storage: &RectCombinationStorage) -> PuzzleResult<()> {
+    write_result_file(
         filepath,
+        step,
+        storage,
         combination_storage.iter()
-            .sorted_by_key(|c| -(c.iter().map(|r| r.area).sum::<u32>() as i32))
-            .map(combination_to_string)
-            .join("\n"),
-    ).expect("Unable to write file");
+            .sorted_by_cached_key(|c| combination_order_key(c, storage.settings.tie_break_seed))
+            .map(combination_to_string),
+    )
 }
 
-/// get deduplication key for combination, useful for `unique_by_key`
+/// convert placed rectangles to the solution format: "id height width x y", separated by two spaces\
+/// the dimensions are in the measured unit, rotated rectangles are marked with an additional "r"
+pub(crate) fn placement_to_string(resolution: &Resolution, placement: &[PlacedRectangle]) -> String {
+    placement.iter().map(|r| {
+        let values = [r.rect.height, r.rect.width, r.x, r.y].map(|v| resolution.format(v)).join(" ");
+        match r.rect.rotated {
+            true => format!("{} {values} r", r.rect.id),
+            false => format!("{} {values}", r.rect.id),
+        }
+    }).join("  ")
+}
+
+/// load placed rectangles from the solution format\
+/// the dimensions have to match the rectangle with the same id, in any orientation it may be placed in
+pub(crate) fn placement_from_string(storage: &ProgramStorage, line: &str) -> PuzzleResult<Vec<PlacedRectangle>> {
+    let parse_error = |reason: String| PuzzleError::Parse { context: "placement".to_owned(), line: line.to_owned(), reason };
+    line.split("  ").map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| {
+        let values = s.split(' ').collect::<Vec<&str>>();
+        let marked_rotated = match values.get(5) {
+            _ if values.len() < 5 || values.len() > 6 => return Err(parse_error(format!("\"{s}\" needs 5 values: id height width x y, and \"r\" if rotated"))),
+            Some(&"r") => true,
+            Some(v) => return Err(parse_error(format!("\"{v}\" is not \"r\""))),
+            None => false,
+        };
+        let id = values[0].parse::<RecId>().map_err(|e| parse_error(format!("\"{}\" is not an id ({e})", values[0])))?;
+        let resolution = &storage.rect_configuration.resolution;
+        let dimensions = values[1..5].iter()
+            .map(|v| resolution.to_grid(v).map_err(|e| parse_error(format!("\"{v}\" is not a dimension ({e})"))))
+            .collect::<PuzzleResult<Vec<RecDimension>>>()?;
+        let rect = storage.rect_configuration.get_block(id, &format!("placement \"{line}\""))?;
+        let (height, width) = (dimensions[0], dimensions[1]);
+        if !(rect.height == height && rect.width == width || rect.height == width && rect.width == height) {
+            return Err(parse_error(format!("rectangle {id} is {}x{}, not {}x{}",
+                                           resolution.format(rect.height), resolution.format(rect.width), values[1], values[2])));
+        }
+        // files without the marker are still accepted, then the dimensions tell if it is rotated
+        let rotated = marked_rotated || rect.height != height;
+        if marked_rotated && rect.height != rect.width && rect.height == height {
+            return Err(parse_error(format!("rectangle {id} is marked as rotated, but is {}x{} like in the configuration", values[1], values[2])));
+        }
+        if rotated && !rect.rotatable {
+            return Err(parse_error(format!("rectangle {id} must not be rotated")));
+        }
+        Ok(PlacedRectangle { rect: if rotated { rect.rotate() } else { rect }, x: dimensions[2], y: dimensions[3] })
+    }).collect()
+}
+
+/// get deduplication key for combination, useful for `unique_by_key`\
+/// rectangles, which must not be rotated, keep their orientation in the key
 pub(crate) fn get_unique_combination_key(combination: &Combination) -> String {
     combination.iter()
-        .map(|r| r.dedup())
+        .map(|r| if r.rotatable { (r.dedup(), "") } else { (r.dedup_permutation(), "f") })
         .sorted()
-        .map(|s| format!("{},{}", s.0, s.1))
+        .map(|(s, fixed)| format!("{},{}{fixed}", s.0, s.1))
         .join(" ")
 }
 
@@ -74,7 +159,7 @@ pub(crate) fn get_unique_combination_key<T, Q>(combination: &T) -> String
 }*/
 
 #[allow(dead_code)]
-pub(crate) fn dedup_comb_iter<I: Iterator>(iter: I) -> impl Iterator + Iterator<Item=Combination>
+pub(crate) fn dedup_comb_iter<I>(iter: I) -> impl Iterator + Iterator<Item=Combination>
     where
         I: Iterator<Item=Combination>
 {
@@ -82,28 +167,33 @@ pub(crate) fn dedup_comb_iter<I: Iterator>(iter: I) -> impl Iterator + Iterator<
 }
 
 #[allow(dead_code)]
-pub(crate) fn redup_comb_iter<'a, I: Iterator + 'a>(iter: I, storage: &'a ProgramStorage<'_>) -> impl Iterator + Iterator<Item=Combination> + 'a
+pub(crate) fn redup_comb_iter<'a, I>(iter: I, storage: &'a ProgramStorage<'_>) -> impl Iterator + Iterator<Item=PuzzleResult<Combination>> + 'a
     where
-        I: Iterator<Item=&'a Combination>
+        I: Iterator<Item=&'a Combination> + 'a
 {
-    iter.flat_map(move |c| duplicate_combination(&mut c.clone(), &storage.rect_configuration.duplication_map, &BTreeSet::new())).unique()
+    iter.flat_map(move |c| match duplicate_combination(&mut c.clone(), &storage.rect_configuration.duplication_map, &BTreeSet::new()) {
+        Ok(duplicates) => duplicates.into_iter().map(Ok).collect(),
+        Err(e) => vec![Err(e)],
+    }).unique_by(|c| c.as_ref().ok().cloned())
 }
 
-pub(crate) fn duplicate_combination(combination: &mut Combination, duplicated: &HashMap<RecId, Vec<Rectangle>>, used_rects: &Combination) -> Vec<Combination> {
-    if combination.is_empty() {
-        return vec![];
-    }
-    let mut out = vec![];
+pub(crate) fn duplicate_combination(combination: &mut Combination, duplicated: &HashMap<RecId, Vec<Rectangle>>, used_rects: &Combination) -> PuzzleResult<Vec<Combination>> {
     // nimm erstes element
-    let element = combination.pop_first().unwrap();
+    let element = match combination.pop_first() {
+        Some(element) => element,
+        None => return Ok(vec![]),
+    };
+    let mut out = vec![];
     // berechne alle duplikate
-    let elements: Vec<Rectangle> = duplicated.get(&element.id).unwrap().iter().filter(|r| !used_rects.contains(r)).copied().collect();
+    let elements: Vec<Rectangle> = duplicated.get(&element.id)
+        .ok_or_else(|| PuzzleError::UnknownPieceId { id: element.id, context: "duplication map".to_owned() })?
+        .iter().filter(|r| !used_rects.contains(r)).copied().collect();
     // recurse -> out2
-    let out2 = duplicate_combination(combination, duplicated, used_rects);
+    let out2 = duplicate_combination(combination, duplicated, used_rects)?;
     if out2.is_empty() {
         out = elements.iter().map(|r| BTreeSet::from([*r])).collect();
         out.push(BTreeSet::from([element]));
-        return out;
+        return Ok(out);
     }
     // hänge an jedes duplikat out2 dran
     out2.iter().for_each(|c| {
@@ -113,10 +203,10 @@ pub(crate) fn duplicate_combination(combination: &mut Combination, duplicated: &
             out.push(c2);
         })
     });
-    out
+    Ok(out)
 }
 
-pub(crate) fn get_smallest_side(rects: &Vec<PlacedRectangle>) -> RecDimension {
+pub(crate) fn get_smallest_side(rects: &[PlacedRectangle]) -> RecDimension {
     rects.iter().map(|r| min(r.rect.width, r.rect.height)).min().unwrap_or(0)
 }
 
@@ -127,29 +217,48 @@ pub struct Rectangle {
     pub width: RecDimension,
     pub height: RecDimension,
     pub area: u32,
+    /// false if the piece has to keep its orientation (grain direction, printed face...)
+    pub rotatable: bool,
+    /// true if height and width are swapped compared to the configuration
+    pub rotated: bool,
 }
 
 impl Rectangle {
     pub(crate) fn new(id: RecId, height: RecDimension, width: RecDimension) -> Rectangle {
-        Rectangle { id, width, height, area: width as u32 * height as u32 }
+        Rectangle { id, width, height, area: width * height, rotatable: true, rotated: false }
     }
 
-    fn rotate(&self) -> Rectangle {
-        Rectangle::new(
-            self.id,
-            self.width,
-            self.height,
-        )
+    #[allow(dead_code)]
+    /// this rectangle has to keep its orientation
+    pub(crate) fn not_rotatable(self) -> Rectangle {
+        Rectangle { rotatable: false, ..self }
+    }
+
+    pub(crate) fn rotate(&self) -> Rectangle {
+        Rectangle {
+            width: self.height,
+            height: self.width,
+            rotated: !self.rotated,
+            ..*self
+        }
+    }
+
+    /// id, marked with an arrow if the rectangle is rotated
+    pub(crate) fn label(&self) -> String {
+        match self.rotated && self.height != self.width {
+            true => format!("{}↻", self.id),
+            false => self.id.to_string(),
+        }
     }
 
     /// get all possible orientations for this rectangle\
-    /// if an orientation does no fit inside the big rectangle at all, it is excluded
-    pub(crate) fn get_possible_orientations(&self, big_rect: &Rectangle) -> HashSet<Rectangle> {
-        let mut orientations = HashSet::new();
+    /// if an orientation does no fit inside the big rectangle at all or the rectangle is not rotatable, it is excluded
+    pub(crate) fn get_possible_orientations(&self, big_rect: &Rectangle) -> BTreeSet<Rectangle> {
+        let mut orientations = BTreeSet::new();
         if self.height <= big_rect.height && self.width <= big_rect.width {
             orientations.insert(*self);
         }
-        if self.width <= big_rect.height && self.height <= big_rect.width {
+        if self.rotatable && self.width <= big_rect.height && self.height <= big_rect.width {
             orientations.insert(self.rotate());
         }
         orientations
@@ -245,8 +354,11 @@ impl PlacedRectangle {
         )
     }
 
-    /// move this rectangle as close as possible to (0,0) without colliding with any other rectangle
-    pub(crate) fn compact(&mut self, others: &[PlacedRectangle]) -> bool {
+    /// move this rectangle as close as possible to (0,0) without getting closer to any other rectangle or blocked zone than the policy allows
+    pub(crate) fn compact(&mut self, others: &[PlacedRectangle], blocked_zones: &[PlacedRectangle], policy: &ContactPolicy) -> bool {
+        // the rectangle can not get closer to the walls than this
+        let min_position = policy.wall_clearance;
+        let blocked = |p: &PlacedRectangle| others.iter().chain(blocked_zones).any(|r| r.check_clearance(p, policy.clearance_to(r)));
         // store old x and y to reset if collision
         let mut old_x_val;
         let mut old_y_val;
@@ -259,21 +371,16 @@ impl PlacedRectangle {
             moved_during_iteration = false;
             // try moving -x and -y at the same time
             loop {
-                if self.x == 0 || self.y == 0 {
+                if self.x <= min_position || self.y <= min_position {
                     break;
                 }
                 old_x_val = self.x;
                 old_y_val = self.y;
-                // do not overshoot
-                if self.x > 0 {
-                    self.x -= 1;
-                }
-                if self.y > 0 {
-                    self.y -= 1;
-                }
+                self.x -= 1;
+                self.y -= 1;
                 // if collision with any other rectangle
                 // revert last change and break loop
-                if others.iter().any(|r| r.check_collision(self)) {
+                if blocked(self) {
                     self.x = old_x_val;
                     self.y = old_y_val;
                     break;
@@ -283,12 +390,12 @@ impl PlacedRectangle {
             }
             // same thing but for x only
             loop {
-                if self.x == 0 {
+                if self.x <= min_position {
                     break;
                 }
                 old_x_val = self.x;
                 self.x -= 1;
-                if others.iter().any(|r| r.check_collision(self)) {
+                if blocked(self) {
                     self.x = old_x_val;
                     break;
                 } else {
@@ -297,12 +404,12 @@ impl PlacedRectangle {
             }
             // same thing but for y only
             loop {
-                if self.y == 0 {
+                if self.y <= min_position {
                     break;
                 }
                 old_y_val = self.y;
                 self.y -= 1;
-                if others.iter().any(|r| r.check_collision(self)) {
+                if blocked(self) {
                     self.y = old_y_val;
                     break;
                 } else {
@@ -316,27 +423,57 @@ impl PlacedRectangle {
         moved_at_all
     }
 
-    /// check if two rectangles collide
+    /// check if two rectangles overlap, touching edges or corners is not a collision
     pub(crate) fn check_collision(&self, other: &Self) -> bool {
+        self.check_clearance(other, 0)
+    }
+
+    /// check if two rectangles overlap or the gap between them is smaller than `clearance`\
+    /// the gap is measured along the axes, so two rectangles are far enough apart, \
+    /// if they are at least `clearance` apart horizontally or vertically
+    pub(crate) fn check_clearance(&self, other: &Self, clearance: RecDimension) -> bool {
         if self.rect.id == other.rect.id {
             return false;
         }
         let ((sx0, sy0), (sx1, sy1)) = self.get_corners();
         let ((ox0, oy0), (ox1, oy1)) = other.get_corners();
-        ox0 <= sx0 && oy0 <= sy0 && ox1 >= sx0 && oy1 >= sy0 ||         // self: bottom left corner collides with other
-            ox0 <= sx1 && oy0 <= sy0 && ox1 >= sx1 && oy1 >= sy0 ||     // self: bottom right corner collides with other
-            ox0 <= sx0 && oy0 <= sy1 && ox1 >= sx0 && oy1 >= sy1 ||     // self: upper left corner collides with other
-            ox0 <= sx1 && oy0 <= sy1 && ox1 >= sx1 && oy1 >= sy1 ||     // self: upper right corner collides with other´
-            sx0 <= ox0 && sy0 <= oy0 && sx1 >= ox0 && sy1 >= oy0 ||     // other: bottom left corner collides with self
-            sx0 <= ox1 && sy0 <= oy0 && sx1 >= ox1 && sy1 >= oy0 ||     // other: bottom right corner collides with self
-            sx0 <= ox0 && sy0 <= oy1 && sx1 >= ox0 && sy1 >= oy1 ||     // other: upper left corner collides with self
-            sx0 <= ox1 && sy0 <= oy1 && sx1 >= ox1 && sy1 >= oy1        // other: upper right corner collides with self
+        // the corners are inclusive, so touching rectangles have a gap of 0
+        sx0 <= ox1 + clearance && ox0 <= sx1 + clearance && sy0 <= oy1 + clearance && oy0 <= sy1 + clearance
     }
 
-    /// check if this rectangle is completely inside of the bound of the big rectangle
+    /// check if this rectangle is completely inside of the bound of the big rectangle and outside of all blocked zones\
+    /// it has to keep the wall clearance of the contact policy to both
     pub(crate) fn check_bounds(&self, storage: &ProgramStorage) -> bool {
-        self.x + self.rect.width <= storage.rect_configuration.big_rect.width &&
-            self.y + self.rect.height <= storage.rect_configuration.big_rect.height
+        self.check_bounds_with_clearance(storage, storage.settings.contact_policy.wall_clearance)
+    }
+
+    /// like `check_bounds`, but with any wall clearance
+    pub(crate) fn check_bounds_with_clearance(&self, storage: &ProgramStorage, clearance: RecDimension) -> bool {
+        let big_rect = storage.rect_configuration.big_rect;
+        self.x >= clearance && self.y >= clearance &&
+            self.x + self.rect.width + clearance <= big_rect.width &&
+            self.y + self.rect.height + clearance <= big_rect.height &&
+            !storage.rect_configuration.blocked_zones.iter().any(|z| z.check_clearance(self, clearance))
+    }
+}
+
+/// how close rectangles may get to each other and to the walls, used by placement and verification\
+/// overlapping is never allowed, with a clearance of 0 rectangles may touch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContactPolicy {
+    /// minimal gap between two rectangles
+    pub piece_clearance: RecDimension,
+    /// minimal gap between a rectangle and the border of the big rect or a blocked zone
+    pub wall_clearance: RecDimension,
+}
+
+impl ContactPolicy {
+    /// the clearance, which has to be kept to another rectangle or a blocked zone
+    pub(crate) fn clearance_to(&self, other: &PlacedRectangle) -> RecDimension {
+        match other.rect.id {
+            BLOCKED_ZONE_ID => self.wall_clearance,
+            _ => self.piece_clearance,
+        }
     }
 }
 
@@ -348,6 +485,8 @@ fn test_collision() {
             width: 2,
             height: 1,
             area: 2,
+            rotatable: true,
+            rotated: false,
         },
         x: 0,
         y: 0,
@@ -358,6 +497,8 @@ fn test_collision() {
             width: 2,
             height: 2,
             area: 4,
+            rotatable: true,
+            rotated: false,
         },
         x: 0,
         y: 0,
@@ -368,6 +509,8 @@ fn test_collision() {
             width: 1,
             height: 2,
             area: 2,
+            rotatable: true,
+            rotated: false,
         },
         x: 0,
         y: 0,
@@ -376,3 +519,80 @@ fn test_collision() {
     assert!(rect2.check_collision(&rect3));
     assert!(rect3.check_collision(&rect1));
 }
+
+#[test]
+fn test_combination_from_string_errors() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 1, 3)],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    assert_eq!(combination_from_string(&storage, "1,2").unwrap().len(), 2);
+    assert!(matches!(combination_from_string(&storage, "1,x"), Err(PuzzleError::Parse { .. })));
+    assert!(matches!(combination_from_string(&storage, "1,7"), Err(PuzzleError::UnknownPieceId { id: 7, .. })));
+}
+
+#[test]
+fn test_rotation_permission() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::steps::step3_check_candidate;
+    use crate::verify::{LayoutProblem, verify_layout};
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 4, 1), Rectangle::new(2, 4, 1).not_rotatable(), Rectangle::new(3, 1, 2).not_rotatable(), Rectangle::new(4, 1, 2)],
+    );
+    assert_eq!(rects.rotated_available_block_map[&1].len(), 1);
+    assert!(rects.rotated_available_block_map[&2].is_empty());
+    assert_eq!(rects.rotated_available_block_map[&4].len(), 2);
+    // a piece, which keeps its orientation, is not interchangeable with a rotatable one
+    assert_eq!(rects.duplicates_of(3).unwrap().len(), 1);
+
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = [1, 3].iter().map(|id| rects.available_block_map[id]).collect();
+    let layout = step3_check_candidate(0, 0, &storage, &candidate).unwrap();
+    assert!(layout.iter().any(|p| p.rect.id == 1 && p.rect.rotated && p.rect.label() == "1↻"));
+    assert!(layout.iter().any(|p| p.rect.id == 3 && !p.rect.rotated));
+
+    // the marker is written for rotated pieces and checked when reading
+    let line = placement_to_string(&rects.resolution, &layout);
+    assert!(line.split("  ").any(|p| p.starts_with("1 1 4 ") && p.ends_with(" r")));
+    assert_eq!(placement_from_string(&storage, &line).unwrap().iter().map(|p| p.rect.rotated).collect::<Vec<_>>(),
+               layout.iter().map(|p| p.rect.rotated).collect::<Vec<_>>());
+    assert!(placement_from_string(&storage, "3 2 1 0 0 r").is_err());
+    assert!(placement_from_string(&storage, "4 1 2 0 0 r").is_err());
+    assert!(placement_from_string(&storage, "4 2 1 0 0").unwrap()[0].rect.rotated);
+
+    let turned = PlacedRectangle { rect: rects.available_block_map[&3].rotate(), x: 0, y: 0 };
+    let candidate: Combination = [3].iter().map(|id| rects.available_block_map[id]).collect();
+    assert_eq!(verify_layout(&storage, &candidate, &[turned]), vec![LayoutProblem::Rotated(3)]);
+}
+
+#[test]
+fn test_contact_policy() {
+    let placed = |id, height, width, x, y| PlacedRectangle { rect: Rectangle::new(id, height, width), x, y };
+    let a = placed(1, 2, 2, 0, 0);
+    // touching edges or corners is no collision, but too close for any clearance
+    for b in [placed(2, 2, 2, 2, 0), placed(3, 2, 2, 0, 2), placed(4, 2, 2, 2, 2)] {
+        assert!(!a.check_collision(&b) && !b.check_collision(&a));
+        assert!(a.check_clearance(&b, 1) && b.check_clearance(&a, 1));
+    }
+    // a gap of 1 is enough for a clearance of 1, also diagonally
+    assert!(!a.check_clearance(&placed(2, 2, 2, 3, 0), 1));
+    assert!(!a.check_clearance(&placed(2, 2, 2, 3, 3), 1));
+    assert!(a.check_clearance(&placed(2, 2, 2, 3, 0), 2));
+    // no corner of one rectangle is inside of the other
+    assert!(placed(1, 1, 5, 0, 2).check_collision(&placed(2, 5, 1, 2, 0)));
+
+    let policy = ContactPolicy { piece_clearance: 1, wall_clearance: 2 };
+    let mut b = placed(2, 1, 1, 6, 6);
+    assert!(b.compact(&[a], &[placed(0, 1, 1, 3, 3)], &policy));
+    assert_eq!((b.x, b.y), (2, 6));
+    let mut c = placed(3, 1, 1, 6, 0);
+    c.compact(&[a], &[], &ContactPolicy { piece_clearance: 1, ..Default::default() });
+    assert_eq!((c.x, c.y), (3, 0));
+}
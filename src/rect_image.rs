@@ -7,6 +7,7 @@ use itertools::Itertools;
 use rusttype::{Font, Scale};
 use crate::gcd::gcd;
 
+use crate::error::{PuzzleError, PuzzleResult};
 use crate::ProgramStorage;
 use crate::rect::{get_smallest_side, PlacedRectangle, RecId};
 
@@ -21,10 +22,11 @@ pub(crate) fn get_color(id: RecId, len: usize) -> Rgb<u8> {
 }
 
 #[allow(dead_code)]
-pub(crate) fn draw_image(path: &str, storage: &ProgramStorage, data: &Vec<PlacedRectangle>) {
+pub(crate) fn draw_image(path: &str, storage: &ProgramStorage, data: &[PlacedRectangle]) -> PuzzleResult<()> {
     let font_size = 0_f32;
     let sides = data.iter().flat_map(|r| [r.rect.height as u64, r.rect.width as u64]).collect_vec();
-    let divisor = sides.iter().fold(*sides.first().unwrap(), |acc, x| gcd(acc, *x)) as u32;
+    let first_side = *sides.first().ok_or_else(|| PuzzleError::Render { path: path.to_owned(), reason: "there are no rectangles to draw".to_owned() })?;
+    let divisor = sides.iter().fold(first_side, |acc, x| gcd(acc, *x)) as u32;
     let multiplyer = max(1, 80 / (get_smallest_side(data) / divisor));
     println!("{}", multiplyer);
     let big_rect = storage.rect_configuration.big_rect;
@@ -34,9 +36,14 @@ pub(crate) fn draw_image(path: &str, storage: &ProgramStorage, data: &Vec<Placed
     draw_filled_rect_mut(&mut image, Rect::at(9, 9).of_size(width + 2, height + 2), Rgb([0u8, 0u8, 255u8]));
     draw_hollow_rect_mut(&mut image, Rect::at(9, 9).of_size(width + 2, height + 2), Rgb([0u8, 255u8, 0u8]));
 
-    let font = Font::try_from_vec(Vec::from(include_bytes!("../DejaVuSans.ttf") as &[u8])).unwrap();
+    let font = Font::try_from_vec(Vec::from(include_bytes!("../DejaVuSans.ttf") as &[u8]))
+        .ok_or_else(|| PuzzleError::Render { path: path.to_owned(), reason: "the embedded font is broken".to_owned() })?;
 
-    data.iter().for_each(|r| {
+    for z in &storage.rect_configuration.blocked_zones {
+        draw_filled_rect_mut(&mut image, Rect::at((z.x * multiplyer) as i32 + 10, (z.y * multiplyer) as i32 + 10).of_size(z.rect.width * multiplyer, z.rect.height * multiplyer), Rgb([96u8, 96u8, 96u8]));
+    }
+
+    for r in data {
         let col = get_color(r.rect.id, storage.rect_configuration.available_blocks.len());
         draw_filled_rect_mut(&mut image, Rect::at((r.x * multiplyer) as i32 + 10, (r.y * multiplyer) as i32 + 10).of_size(r.rect.width * multiplyer, r.rect.height * multiplyer), col);
         let col = Rgb([255 - col[0], 255 - col[1], 255 - col[2]]);
@@ -48,10 +55,9 @@ pub(crate) fn draw_image(path: &str, storage: &ProgramStorage, data: &Vec<Placed
             (r.y * multiplyer + r.rect.height * multiplyer / 2) as i32,
             Scale { x: font_size, y: font_size },
             &font,
-            &format!("{}", r.rect.id),
+            &r.rect.label(),
         );
-        image.save(path).expect("no panic!");
-    });
+    }
 
     draw_text_mut(
         &mut image,
@@ -62,5 +68,5 @@ pub(crate) fn draw_image(path: &str, storage: &ProgramStorage, data: &Vec<Placed
         &font,
         &data.iter().map(|r| r.rect.id).sorted().join(", "),
     );
-    image.save(path).expect("no panic!");
+    image.save(path).map_err(|e| PuzzleError::Render { path: path.to_owned(), reason: e.to_string() })
 }
@@ -0,0 +1,131 @@
+use std::fmt::{Display, Formatter};
+
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::gcd::gcd;
+use crate::rect::RecDimension;
+
+/// how the integer grid of the solver relates to the measured dimensions\
+/// one grid step is `step / 10^decimals` of `unit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Resolution {
+    pub step: u64,
+    pub decimals: u32,
+    pub unit: String,
+}
+
+impl Default for Resolution {
+    /// the dimensions are the grid itself, like in the integer presets
+    fn default() -> Self {
+        Resolution { step: 1, decimals: 0, unit: "units".to_owned() }
+    }
+}
+
+impl Display for Resolution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "1 grid step = {} {}", format_scaled(self.step as u128, self.decimals), self.unit)
+    }
+}
+
+impl Resolution {
+    /// choose the coarsest grid, on which all values are exact
+    pub(crate) fn for_values(unit: &str, values: &[&str]) -> PuzzleResult<Resolution> {
+        let parsed = values.iter().map(|v| parse_decimal(v)).collect::<PuzzleResult<Vec<(u64, u32)>>>()?;
+        let mut decimals = parsed.iter().map(|(_, d)| *d).max().unwrap_or(0);
+        let mut step = values.iter().zip(&parsed)
+            .map(|(value, (m, d))| shift(value, *m, decimals - d))
+            .collect::<PuzzleResult<Vec<u64>>>()?
+            .into_iter()
+            .filter(|v| *v != 0)
+            .fold(0, |acc, v| if acc == 0 { v } else { gcd(acc, v) })
+            .max(1);
+        // 20 * 0.01 is written as 2 * 0.1
+        while decimals > 0 && step.is_multiple_of(10) {
+            step /= 10;
+            decimals -= 1;
+        }
+        if step > u32::MAX as u64 {
+            return Err(decimal_error(&values.join(" "), "the grid step is too big, the values have too many digits".to_owned()));
+        }
+        Ok(Resolution { step, decimals, unit: unit.to_owned() })
+    }
+
+    /// convert a measured value to grid steps, it has to be exactly on the grid
+    pub(crate) fn to_grid(&self, value: &str) -> PuzzleResult<RecDimension> {
+        let (mantissa, decimals) = parse_decimal(value)?;
+        let common = decimals.max(self.decimals);
+        let scaled = shift(value, mantissa, common - decimals)?;
+        let step = shift(value, self.step, common - self.decimals)?;
+        if !scaled.is_multiple_of(step) {
+            return Err(decimal_error(value, format!("it is not a multiple of the grid step ({self})")));
+        }
+        RecDimension::try_from(scaled / step).map_err(|_| decimal_error(value, "it is too big for the grid".to_owned()))
+    }
+
+    /// convert grid steps back to the measured unit, without the unit
+    pub(crate) fn format(&self, value: RecDimension) -> String {
+        format_scaled(value as u128 * self.step as u128, self.decimals)
+    }
+
+    /// convert an area in grid steps back to the measured unit, without the unit
+    pub(crate) fn format_area(&self, area: u32) -> String {
+        // fits, because the step is at most u32::MAX
+        format_scaled(area as u128 * self.step as u128 * self.step as u128, 2 * self.decimals)
+    }
+}
+
+fn decimal_error(value: &str, reason: String) -> PuzzleError {
+    PuzzleError::Parse { context: "dimensions".to_owned(), line: value.to_owned(), reason }
+}
+
+/// `mantissa * 10^exponent`, an error for `value` if it does not fit
+fn shift(value: &str, mantissa: u64, exponent: u32) -> PuzzleResult<u64> {
+    10u64.checked_pow(exponent).and_then(|factor| mantissa.checked_mul(factor))
+        .ok_or_else(|| decimal_error(value, "it has too many digits compared to the other values".to_owned()))
+}
+
+/// "27.40" is (2740, 2)
+fn parse_decimal(value: &str) -> PuzzleResult<(u64, u32)> {
+    let (integer, fraction) = value.trim().split_once('.').unwrap_or((value.trim(), ""));
+    if integer.is_empty() && fraction.is_empty() || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(decimal_error(value, "it is not a positive decimal number like 27.4".to_owned()));
+    }
+    format!("{integer}{fraction}").parse::<u64>()
+        .map(|mantissa| (mantissa, fraction.len() as u32))
+        .map_err(|e| decimal_error(value, e.to_string()))
+}
+
+/// `value / 10^decimals` as decimal number, without trailing zeros
+fn format_scaled(value: u128, decimals: u32) -> String {
+    // shifting the decimal point in the digits can not overflow
+    let digits = format!("{value:0>width$}", width = decimals as usize + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals as usize);
+    match fraction.trim_end_matches('0') {
+        "" => integer.to_owned(),
+        fraction => format!("{integer}.{fraction}"),
+    }
+}
+
+#[test]
+fn test_resolution() {
+    let resolution = Resolution::for_values("cm", &["27.4", "12", "0.6", "4.80"]).unwrap();
+    assert_eq!(resolution, Resolution { step: 2, decimals: 1, unit: "cm".to_owned() });
+    assert_eq!(resolution.to_string(), "1 grid step = 0.2 cm");
+    assert_eq!(resolution.to_grid("27.4").unwrap(), 137);
+    assert_eq!(resolution.to_grid("0.6").unwrap(), 3);
+    assert_eq!(resolution.to_grid("0.20").unwrap(), 1);
+    assert_eq!(resolution.format(137), "27.4");
+    assert_eq!(resolution.format(60), "12");
+    assert_eq!(resolution.format_area(137 * 3), "16.44");
+    assert!(resolution.to_grid("0.1").is_err());
+    assert!(resolution.to_grid("0.25").is_err());
+    assert!(resolution.to_grid("2,5").is_err());
+    assert!(resolution.to_grid("-1").is_err());
+    assert_eq!(Resolution::default().format(42), "42");
+
+    // very different numbers of decimals do not overflow
+    assert!(matches!(Resolution::for_values("cm", &["0.0000000000000000001", "5"]), Err(PuzzleError::Parse { .. })));
+    assert!(Resolution::for_values("cm", &["0.5", "5"]).unwrap().to_grid("0.00000000000000000001").is_err());
+    let resolution = Resolution::for_values("cm", &["0.000000000000000001", "1"]).unwrap();
+    assert_eq!(resolution.format(1_000_000_000), "0.000000001");
+    assert_eq!(resolution.format_area(4), "0.000000000000000000000000000000000004");
+}
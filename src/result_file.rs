@@ -0,0 +1,232 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::hash::Hasher;
+
+use itertools::Itertools;
+
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::fingerprint::{Fingerprint, FingerprintValue};
+use crate::ProgramStorage;
+
+/// version of the result file format, increase if the format of the body changes
+pub(crate) const RESULT_FILE_VERSION: u32 = 1;
+
+const HEADER_PREFIX: &str = "# ";
+
+/// the step, which produced a result file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ResultStep {
+    Candidates,
+    DeduplicatedCandidates,
+    FittingCandidates,
+    UndecidedCandidates,
+    UnknownCandidates,
+    CombinedSolutions,
+    FinalCombinations,
+}
+
+impl ResultStep {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ResultStep::Candidates => "step1_candidates",
+            ResultStep::DeduplicatedCandidates => "step2_deduplicated_candidates",
+            ResultStep::FittingCandidates => "step3_fitting_candidates",
+            ResultStep::UndecidedCandidates => "step3_undecided_candidates",
+            ResultStep::UnknownCandidates => "step3_unknown_candidates",
+            ResultStep::CombinedSolutions => "step4_combined_solutions",
+            ResultStep::FinalCombinations => "step5_final_combinations",
+        }
+    }
+
+    /// the step of the pipeline, which writes this file
+    fn number(&self) -> u8 {
+        match self {
+            ResultStep::Candidates => 1,
+            ResultStep::DeduplicatedCandidates => 2,
+            ResultStep::FittingCandidates | ResultStep::UndecidedCandidates | ResultStep::UnknownCandidates => 3,
+            ResultStep::CombinedSolutions => 4,
+            ResultStep::FinalCombinations => 5,
+        }
+    }
+}
+
+/// all settings, which change the content of a result file, with the first step they change
+fn settings_by_step(storage: &ProgramStorage) -> Vec<(u8, &'static str, String)> {
+    let settings = &storage.settings;
+    vec![
+        (1, "min_solution_area", settings.min_solution_area.to_string()),
+        (1, "min_rectangle_amount", settings.min_rectangle_amount.to_string()),
+        (1, "max_rectangle_amount", settings.max_rectangle_amount.to_string()),
+        (3, "distance_between_rectangles", settings.distance_between_rectangles.to_string()),
+        (3, "guillotine_only", settings.guillotine_only.to_string()),
+        (3, "piece_clearance", settings.contact_policy.piece_clearance.to_string()),
+        (3, "wall_clearance", settings.contact_policy.wall_clearance.to_string()),
+        (3, "placement_strategies", settings.placement_strategies.iter().map(|s| s.name()).join(",")),
+        (3, "layer_solver", settings.layer_solver().name()),
+        // decides, which candidates are fitting and which undecided
+        (3, "candidate_budget", settings.candidate_budget.to_string()),
+        // before step4 the seed only changes the order of the lines, which does not matter when loading them
+        (4, "tie_break_seed", settings.tie_break_seed.to_string()),
+        (4, "unknown_layers_as_possible", settings.unknown_layers_as_possible.to_string()),
+    ]
+}
+
+/// all settings, which change the content of any result file
+pub(crate) fn relevant_settings(storage: &ProgramStorage) -> Vec<(&'static str, String)> {
+    settings_by_step(storage).into_iter().map(|(_, name, value)| (name, value)).collect()
+}
+
+/// the settings, which change the content of the result file of this step
+fn step_settings(storage: &ProgramStorage, step: ResultStep) -> Vec<(&'static str, String)> {
+    settings_by_step(storage).into_iter().filter(|(first, _, _)| *first <= step.number()).map(|(_, name, value)| (name, value)).collect()
+}
+
+/// fingerprint of the rectangles and the settings, which the result file of this step depends on
+pub(crate) fn configuration_fingerprint(storage: &ProgramStorage, step: ResultStep) -> FingerprintValue {
+    settings_fingerprint(storage, &step_settings(storage, step))
+}
+
+/// fingerprint of the rectangles and the given setting values
+fn settings_fingerprint(storage: &ProgramStorage, settings: &[(&str, String)]) -> FingerprintValue {
+    let configuration = storage.rect_configuration;
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.container(configuration);
+    for rect in configuration.available_blocks.iter().sorted_by_key(|r| r.id) {
+        fingerprint.field("id", rect.id as u64)
+            .field("height", rect.height as u64)
+            .field("width", rect.width as u64);
+        if !rect.rotatable {
+            fingerprint.field("not_rotatable", 1);
+        }
+        if let Some(pin) = configuration.pin(rect.id) {
+            fingerprint.field("pin.layer", pin.layer.map_or(u64::MAX, |l| l as u64))
+                .field("pin.position", pin.position.map_or(u64::MAX, |(x, y)| ((x as u64) << 32) | y as u64))
+                .field("pin.rotated", pin.rotated.map_or(u64::MAX, |r| r as u64));
+        }
+    }
+    for constraint in &configuration.layer_constraints {
+        fingerprint.write(format!("{constraint:?}").as_bytes());
+    }
+    for (name, value) in settings {
+        fingerprint.write(name.as_bytes());
+        fingerprint.write(value.as_bytes());
+    }
+    FingerprintValue(fingerprint.finish())
+}
+
+/// write a result file, with a header describing how it was produced
+pub(crate) fn write_result_file<I>(filepath: &str, step: ResultStep, storage: &ProgramStorage, lines: I) -> PuzzleResult<()>
+    where
+        I: IntoIterator<Item=String>
+{
+    let header = [
+        "holzpuzzle result file".to_owned(),
+        format!("version: {RESULT_FILE_VERSION}"),
+        format!("step: {}", step.name()),
+        format!("fingerprint: {}", configuration_fingerprint(storage, step)),
+    ].into_iter()
+        .chain(step_settings(storage, step).into_iter().map(|(name, value)| format!("setting {name}: {value}")))
+        .map(|l| format!("{HEADER_PREFIX}{l}"));
+    fs::write(
+        filepath,
+        header.chain(lines).join("\n"),
+    ).map_err(|e| PuzzleError::io(filepath, e))
+}
+
+/// read the body of a result file\
+/// returns `Ok(None)` if the file does not exist and an error if it was produced by another step, format or configuration
+pub(crate) fn read_result_file(filepath: &str, step: ResultStep, storage: &ProgramStorage) -> PuzzleResult<Option<Vec<String>>> {
+    read_result_file_allowing(filepath, step, storage, &[])
+}
+
+/// like `read_result_file`, but the settings in `may_differ` are taken from the header of the file
+pub(crate) fn read_result_file_allowing(filepath: &str, step: ResultStep, storage: &ProgramStorage, may_differ: &[&str]) -> PuzzleResult<Option<Vec<String>>> {
+    let content = match fs::read_to_string(filepath) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            println!("WARNING: {filepath} does not exist, starting without results of {}", step.name());
+            return Ok(None);
+        }
+        Err(e) => return Err(PuzzleError::io(filepath, e)),
+    };
+    let mismatch = |reason: String| PuzzleError::ResultFileMismatch { path: filepath.to_owned(), reason };
+    let (header, body): (Vec<&str>, Vec<&str>) = content.split('\n')
+        .filter(|l| !l.is_empty())
+        .partition(|l| l.starts_with(HEADER_PREFIX.trim_end()));
+    let header_value = |key: &str| header.iter()
+        .filter_map(|l| l.trim_start_matches('#').trim().strip_prefix(key))
+        .map(|v| v.trim_start_matches(':').trim())
+        .next();
+
+    if header.is_empty() {
+        println!("WARNING: {filepath} has no header, can not check whether it belongs to this configuration");
+    } else {
+        let version = header_value("version");
+        if version != Some(&RESULT_FILE_VERSION.to_string()) {
+            return Err(mismatch(format!("format version is {}, expected {RESULT_FILE_VERSION}", version.unwrap_or("?"))));
+        }
+        let file_step = header_value("step");
+        if file_step != Some(step.name()) {
+            return Err(mismatch(format!("produced by {}, expected {} (check the paths in the settings)", file_step.unwrap_or("?"), step.name())));
+        }
+        let expected = step_settings(storage, step).into_iter()
+            .map(|(name, value)| match may_differ.contains(&name) {
+                true => (name, header_value(&format!("setting {name}")).map_or(value, str::to_owned)),
+                false => (name, value),
+            })
+            .collect::<Vec<_>>();
+        if header_value("fingerprint").and_then(FingerprintValue::parse) != Some(settings_fingerprint(storage, &expected)) {
+            let differences = expected.into_iter()
+                .filter_map(|(name, value)| {
+                    let file_value = header_value(&format!("setting {name}"));
+                    (file_value != Some(value.as_str())).then(|| format!("{name} is {} in file, but {value} in this run", file_value.unwrap_or("?")))
+                })
+                .join(", ");
+            let reason = match differences.is_empty() {
+                true => "produced for other rectangles".to_owned(),
+                false => format!("produced for other settings ({differences})"),
+            };
+            if !storage.settings.accept_mismatched_result_files {
+                return Err(mismatch(format!("{reason}, set accept_mismatched_result_files to use it anyway")));
+            }
+            println!("WARNING: {filepath} was {reason}, using it anyway");
+        }
+    }
+    Ok(Some(body.into_iter().map(|l| l.to_owned()).collect()))
+}
+
+#[test]
+fn test_result_file_header() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::rect::Rectangle;
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 1, 3)],
+    );
+    let path = std::env::temp_dir().join("holzpuzzle_result_file_test.txt");
+    let path = path.to_str().unwrap();
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    write_result_file(path, ResultStep::FittingCandidates, &storage, vec!["1,2".to_owned()]).unwrap();
+
+    assert_eq!(read_result_file(path, ResultStep::FittingCandidates, &storage).unwrap(), Some(vec!["1,2".to_owned()]));
+    assert!(matches!(read_result_file(path, ResultStep::Candidates, &storage), Err(PuzzleError::ResultFileMismatch { .. })));
+
+    let storage = ProgramStorage::new(&rects, Settings { distance_between_rectangles: 5, ..Default::default() });
+    assert!(matches!(read_result_file(path, ResultStep::FittingCandidates, &storage), Err(PuzzleError::ResultFileMismatch { .. })));
+    let storage = ProgramStorage::new(&rects, Settings { distance_between_rectangles: 5, accept_mismatched_result_files: true, ..Default::default() });
+    assert_eq!(read_result_file(path, ResultStep::FittingCandidates, &storage).unwrap(), Some(vec!["1,2".to_owned()]));
+    let budget = crate::budget::CandidateBudget { max_permutations: Some(10), ..Default::default() };
+    let storage = ProgramStorage::new(&rects, Settings { candidate_budget: budget, ..Default::default() });
+    assert!(matches!(read_result_file(path, ResultStep::FittingCandidates, &storage), Err(PuzzleError::ResultFileMismatch { .. })));
+
+    // settings of later steps do not reject the files of earlier steps
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    write_result_file(path, ResultStep::Candidates, &storage, vec!["1,2".to_owned()]).unwrap();
+    let storage = ProgramStorage::new(&rects, Settings { distance_between_rectangles: 5, layer_solver: &crate::layer_solver::ExactSolver { max_nodes: 1 }, tie_break_seed: 3, ..Default::default() });
+    assert_eq!(read_result_file(path, ResultStep::Candidates, &storage).unwrap(), Some(vec!["1,2".to_owned()]));
+    assert!(!fs::read_to_string(path).unwrap().contains("setting distance_between_rectangles"));
+    let storage = ProgramStorage::new(&rects, Settings { max_rectangle_amount: 1, ..Default::default() });
+    assert!(matches!(read_result_file(path, ResultStep::Candidates, &storage), Err(PuzzleError::ResultFileMismatch { .. })));
+}
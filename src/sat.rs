@@ -0,0 +1,429 @@
+use std::fs;
+use std::process::Command;
+
+use itertools::Itertools;
+
+use crate::budget::BudgetTracker;
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::gcd::gcd;
+use crate::layer_solver::{LayerSolver, LayerVerdict};
+use crate::ProgramStorage;
+use crate::rect::{Combination, combination_to_string, PlacedRectangle, RecDimension, Rectangle};
+use crate::verify::verify_layout;
+
+/// a variable, negative if negated, like in DIMACS
+type Literal = i32;
+type Clause = Vec<Literal>;
+
+/// a literal, which may already be known to be true or false
+type MaybeLiteral = Result<Literal, bool>;
+
+/// one layer of a candidate as CNF\
+/// positions are order encoded: for each piece and each e there is one variable for "x <= e" and one for "y <= e"
+pub(crate) struct SatInstance {
+    /// all dimensions and positions are divided by this
+    scale: RecDimension,
+    width: RecDimension,
+    height: RecDimension,
+    pieces: Vec<SatPiece>,
+    clauses: Vec<Clause>,
+    variables: i32,
+    description: String,
+}
+
+struct SatPiece {
+    rect: Rectangle,
+    /// true if the piece is rotated, only for rotatable pieces, which are not square
+    rotated: Option<Literal>,
+    /// variable of "x <= 0", the next ones are "x <= 1"...
+    x: Literal,
+    /// variable of "y <= 0"
+    y: Literal,
+}
+
+impl SatInstance {
+    /// encode the candidate for the big rect, with blocked zones, pins and the contact policy
+    pub(crate) fn new(storage: &ProgramStorage, candidate: &Combination) -> SatInstance {
+        let configuration = storage.rect_configuration;
+        let policy = &storage.settings.contact_policy;
+        let pins = candidate.iter().filter_map(|r| configuration.pin(r.id)).collect::<Vec<_>>();
+        // the coarsest grid, on which everything is still exact
+        let scale = [configuration.big_rect.width, configuration.big_rect.height, policy.piece_clearance, policy.wall_clearance].into_iter()
+            .chain(candidate.iter().flat_map(|r| [r.width, r.height]))
+            .chain(configuration.blocked_zones.iter().flat_map(|z| [z.x, z.y, z.rect.width, z.rect.height]))
+            .chain(pins.iter().filter_map(|p| p.position).flat_map(|(x, y)| [x, y]))
+            .filter(|v| *v != 0)
+            .fold(0, |acc, v| if acc == 0 { v as u64 } else { gcd(acc, v as u64) })
+            .max(1) as RecDimension;
+        let mut instance = SatInstance {
+            scale,
+            width: configuration.big_rect.width / scale,
+            height: configuration.big_rect.height / scale,
+            pieces: vec![],
+            clauses: vec![],
+            variables: 0,
+            description: combination_to_string(candidate),
+        };
+        for rect in candidate {
+            let rotated = (rect.rotatable && rect.width != rect.height).then(|| instance.new_variables(1));
+            let x = instance.new_variables(instance.width as i32 - 1);
+            let y = instance.new_variables(instance.height as i32 - 1);
+            instance.pieces.push(SatPiece { rect: *rect, rotated, x, y });
+        }
+        let (piece_clearance, wall) = (policy.piece_clearance / scale, policy.wall_clearance / scale);
+        let zones = configuration.blocked_zones.iter()
+            .map(|z| (z.x / scale, z.y / scale, z.rect.width / scale, z.rect.height / scale))
+            .collect::<Vec<_>>();
+        for i in 0..instance.pieces.len() {
+            instance.add_piece_clauses(i, wall, &zones);
+            if let Some(pin) = configuration.pin(instance.pieces[i].rect.id) {
+                let piece = &instance.pieces[i];
+                let (rotated, x, y) = (piece.rotated, piece.x, piece.y);
+                if let (Some(rotated), Some(pinned)) = (rotated, pin.rotated) {
+                    instance.clauses.push(vec![if pinned { rotated } else { -rotated }]);
+                }
+                if let Some((px, py)) = pin.position {
+                    let (px, py) = ((px / scale) as i64, (py / scale) as i64);
+                    let (width, height) = (instance.width, instance.height);
+                    instance.add([at_most(x, width, px)]);
+                    instance.add([not(at_most(x, width, px - 1))]);
+                    instance.add([at_most(y, height, py)]);
+                    instance.add([not(at_most(y, height, py - 1))]);
+                }
+            }
+            for j in i + 1..instance.pieces.len() {
+                instance.add_pair_clauses(i, j, piece_clearance);
+            }
+        }
+        instance
+    }
+
+    fn new_variables(&mut self, count: i32) -> Literal {
+        let first = self.variables + 1;
+        self.variables += count.max(0);
+        first
+    }
+
+    /// add a clause, it is left out if it is always true
+    fn add<I: IntoIterator<Item=MaybeLiteral>>(&mut self, literals: I) {
+        let mut clause = vec![];
+        for literal in literals {
+            match literal {
+                Ok(literal) => clause.push(literal),
+                Err(true) => return,
+                Err(false) => {}
+            }
+        }
+        self.clauses.push(clause);
+    }
+
+    /// the orientations of a piece: the literal, which is true if the piece is not in this orientation, width and height
+    fn orientations(&self, i: usize) -> Vec<(MaybeLiteral, i64, i64)> {
+        let piece = &self.pieces[i];
+        let (width, height) = ((piece.rect.width / self.scale) as i64, (piece.rect.height / self.scale) as i64);
+        match piece.rotated {
+            Some(rotated) => vec![(Ok(rotated), width, height), (Ok(-rotated), height, width)],
+            None => vec![(Err(false), width, height)],
+        }
+    }
+
+    /// the order encoding, the piece inside of the walls and outside of the blocked zones
+    fn add_piece_clauses(&mut self, i: usize, wall: RecDimension, zones: &[(RecDimension, RecDimension, RecDimension, RecDimension)]) {
+        let (x, y) = (self.pieces[i].x, self.pieces[i].y);
+        let (width, height) = (self.width, self.height);
+        for e in 0..width as i64 - 2 {
+            self.add([not(at_most(x, width, e)), at_most(x, width, e + 1)]);
+        }
+        for e in 0..height as i64 - 2 {
+            self.add([not(at_most(y, height, e)), at_most(y, height, e + 1)]);
+        }
+        let wall = wall as i64;
+        self.add([not(at_most(x, width, wall - 1))]);
+        self.add([not(at_most(y, height, wall - 1))]);
+        for (other, w, h) in self.orientations(i) {
+            self.add([other, at_most(x, width, width as i64 - wall - w)]);
+            self.add([other, at_most(y, height, height as i64 - wall - h)]);
+            for &(zx, zy, zw, zh) in zones {
+                let (zx, zy, zw, zh) = (zx as i64, zy as i64, zw as i64, zh as i64);
+                self.add([
+                    other,
+                    at_most(x, width, zx - w - wall),
+                    not(at_most(x, width, zx + zw + wall - 1)),
+                    at_most(y, height, zy - h - wall),
+                    not(at_most(y, height, zy + zh + wall - 1)),
+                ]);
+            }
+        }
+    }
+
+    /// one of the pieces is left of or above the other one, at least the clearance apart
+    fn add_pair_clauses(&mut self, i: usize, j: usize, clearance: RecDimension) {
+        let sides = [(i, j, true), (j, i, true), (i, j, false), (j, i, false)].map(|side| (side, self.new_variables(1)));
+        self.clauses.push(sides.iter().map(|(_, v)| *v).collect());
+        for ((first, second, horizontal), side) in sides {
+            let (size, a, b) = match horizontal {
+                true => (self.width, self.pieces[first].x, self.pieces[second].x),
+                false => (self.height, self.pieces[first].y, self.pieces[second].y),
+            };
+            for (other, w, h) in self.orientations(first) {
+                // first + length + clearance <= second: if second <= e + distance, then first <= e
+                let distance = if horizontal { w } else { h } + clearance as i64;
+                for e in -1..size as i64 {
+                    self.add([other, Ok(-side), not(at_most(b, size, e + distance)), at_most(a, size, e)]);
+                }
+            }
+        }
+    }
+
+    /// the CNF in DIMACS format, with comments describing the variables
+    pub(crate) fn to_dimacs(&self) -> String {
+        let mut lines = vec![
+            format!("c holzpuzzle layer {}", self.description),
+            format!("c all dimensions are divided by {}, the big rect is {}x{}", self.scale, self.width, self.height),
+        ];
+        for piece in &self.pieces {
+            lines.push(format!("c piece {}: rotated {}, x <= e is {} + e, y <= e is {} + e",
+                               piece.rect.id, piece.rotated.map_or("-".to_owned(), |r| r.to_string()), piece.x, piece.y));
+        }
+        lines.push(format!("p cnf {} {}", self.variables, self.clauses.len()));
+        lines.extend(self.clauses.iter().map(|c| c.iter().map(|l| l.to_string()).chain(["0".to_owned()]).join(" ")));
+        lines.join("\n")
+    }
+
+    /// read a model in the output format of SAT solvers ("s SATISFIABLE", "v 1 -2 ... 0")\
+    /// returns `Ok(None)` if the solver found no model
+    pub(crate) fn decode(&self, model: &str) -> PuzzleResult<Option<Vec<PlacedRectangle>>> {
+        let parse_error = |line: &str, reason: String| PuzzleError::Parse { context: "SAT model".to_owned(), line: line.to_owned(), reason };
+        let mut assignment = vec![false; self.variables as usize + 1];
+        for line in model.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('c')) {
+            if line.contains("UNSAT") {
+                return Ok(None);
+            }
+            if line.starts_with('s') || line == "SAT" {
+                continue;
+            }
+            for value in line.trim_start_matches('v').split_whitespace() {
+                let literal = value.parse::<Literal>().map_err(|e| parse_error(line, format!("\"{value}\" is not a literal ({e})")))?;
+                match assignment.get_mut(literal.unsigned_abs() as usize) {
+                    Some(variable) => *variable = literal > 0,
+                    None => return Err(parse_error(line, format!("{literal} is not a variable of this instance"))),
+                }
+            }
+        }
+        let position = |first: Literal, size: RecDimension| (0..size - 1).find(|e| assignment[(first + *e as i32) as usize]).unwrap_or(size - 1);
+        Ok(Some(self.pieces.iter().map(|piece| PlacedRectangle {
+            rect: if piece.rotated.is_some_and(|r| assignment[r as usize]) { piece.rect.rotate() } else { piece.rect },
+            x: position(piece.x, self.width) * self.scale,
+            y: position(piece.y, self.height) * self.scale,
+        }).collect()))
+    }
+
+    /// solve with the built in DPLL, returns the model in the output format of SAT solvers\
+    /// or the reason, why the DPLL gave up
+    pub(crate) fn solve_dpll(&self, max_decisions: u64, budget: &BudgetTracker) -> Result<String, String> {
+        Ok(match dpll(self.variables, &self.clauses, max_decisions, budget)? {
+            Some(model) => format!("s SATISFIABLE\nv {} 0", (1..=self.variables).map(|v| if model[v as usize] { v } else { -v }).join(" ")),
+            None => "s UNSATISFIABLE".to_owned(),
+        })
+    }
+}
+
+/// "x <= e" for a coordinate with `size` possible values starting at variable `first`
+fn at_most(first: Literal, size: RecDimension, e: i64) -> MaybeLiteral {
+    match e {
+        e if e < 0 => Err(false),
+        e if e >= size as i64 - 1 => Err(true),
+        e => Ok(first + e as i32),
+    }
+}
+
+fn not(literal: MaybeLiteral) -> MaybeLiteral {
+    literal.map(|l| -l).map_err(|b| !b)
+}
+
+/// read a model for a candidate and check the layout with the verifier\
+/// returns `Ok(None)` if the solver found no model
+pub(crate) fn import_model(storage: &ProgramStorage, candidate: &Combination, filepath: &str) -> PuzzleResult<Option<Vec<PlacedRectangle>>> {
+    let model = fs::read_to_string(filepath).map_err(|e| PuzzleError::io(filepath, e))?;
+    let Some(layout) = SatInstance::new(storage, candidate).decode(&model)? else {
+        return Ok(None);
+    };
+    let problems = verify_layout(storage, candidate, &layout);
+    if !problems.is_empty() {
+        return Err(PuzzleError::InvalidLayout { context: filepath.to_owned(), problems: problems.iter().map(|p| p.to_string()).join(", ") });
+    }
+    Ok(Some(layout))
+}
+
+/// a small DPLL solver with unit propagation, good enough for tests and tiny instances\
+/// returns the value of each variable (index 0 is unused), `Err` with the reason after `max_decisions` guesses\
+/// or when the time of the budget is up
+pub(crate) fn dpll(variables: i32, clauses: &[Clause], max_decisions: u64, budget: &BudgetTracker) -> Result<Option<Vec<bool>>, String> {
+    let mut assignment: Vec<Option<bool>> = vec![None; variables as usize + 1];
+    // the set variables in order and for each guess the length of the trail before it and if it was flipped
+    let mut trail = vec![];
+    let mut guesses: Vec<(usize, Literal, bool)> = vec![];
+    let mut decisions = 0;
+    loop {
+        match propagate(clauses, &mut assignment, &mut trail) {
+            Propagation::Model => return Ok(Some(assignment.into_iter().map(|v| v.unwrap_or(false)).collect())),
+            Propagation::Branch(literal) => {
+                decisions += 1;
+                if decisions > max_decisions || !budget.within_time() {
+                    return Err(budget.exhausted().map_or_else(|| format!("gave up after {max_decisions} decisions"), str::to_owned));
+                }
+                guesses.push((trail.len(), literal, false));
+                assignment[literal.unsigned_abs() as usize] = Some(literal > 0);
+                trail.push(literal.unsigned_abs() as usize);
+            }
+            Propagation::Conflict => loop {
+                let Some((start, literal, flipped)) = guesses.pop() else {
+                    return Ok(None);
+                };
+                for variable in trail.drain(start..) {
+                    assignment[variable] = None;
+                }
+                if !flipped {
+                    guesses.push((start, literal, true));
+                    assignment[literal.unsigned_abs() as usize] = Some(literal <= 0);
+                    trail.push(literal.unsigned_abs() as usize);
+                    break;
+                }
+            },
+        }
+    }
+}
+
+enum Propagation {
+    Conflict,
+    /// an open literal of a clause, which is not decided yet
+    Branch(Literal),
+    Model,
+}
+
+/// unit propagation, until nothing changes, the set variables are added to the trail
+fn propagate(clauses: &[Clause], assignment: &mut [Option<bool>], trail: &mut Vec<usize>) -> Propagation {
+    let value = |assignment: &[Option<bool>], literal: Literal| assignment[literal.unsigned_abs() as usize].map(|v| v == (literal > 0));
+    loop {
+        let mut changed = false;
+        let mut branch = None;
+        for clause in clauses {
+            if clause.iter().any(|l| value(assignment, *l) == Some(true)) {
+                continue;
+            }
+            let mut open = clause.iter().filter(|l| value(assignment, **l).is_none());
+            match (open.next(), open.next()) {
+                (None, _) => return Propagation::Conflict,
+                (Some(l), None) => {
+                    assignment[l.unsigned_abs() as usize] = Some(*l > 0);
+                    trail.push(l.unsigned_abs() as usize);
+                    changed = true;
+                }
+                (Some(l), Some(_)) => branch = branch.or(Some(*l)),
+            }
+        }
+        if !changed {
+            return branch.map_or(Propagation::Model, Propagation::Branch);
+        }
+    }
+}
+
+/// solve the SAT encoding with an external solver, which takes the CNF file as argument and prints the model,\
+/// or with the built in DPLL, if there is no command, which gives up after `max_decisions` guesses\
+/// or when the time of the candidate budget is up
+pub(crate) struct SatSolver {
+    pub command: Option<&'static str>,
+    pub max_decisions: u64,
+}
+
+impl LayerSolver for SatSolver {
+    fn name(&self) -> String {
+        format!("sat({})", self.command.unwrap_or("dpll"))
+    }
+
+    fn solve(&self, storage: &ProgramStorage, candidate: &Combination) -> LayerVerdict {
+        let instance = SatInstance::new(storage, candidate);
+        let model = match self.command {
+            None => match instance.solve_dpll(self.max_decisions, &BudgetTracker::new(storage.settings.candidate_budget)) {
+                Ok(model) => model,
+                Err(reason) => return LayerVerdict::OutOfBudget(reason),
+            },
+            Some(command) => {
+                let path = std::env::temp_dir().join(format!("holzpuzzle_{}_{:?}.cnf", std::process::id(), std::thread::current().id()));
+                if let Err(e) = fs::write(&path, instance.to_dimacs()) {
+                    return LayerVerdict::Unknown(format!("could not write {}: {e}", path.display()));
+                }
+                let output = Command::new(command).arg(&path).output();
+                let _ = fs::remove_file(&path);
+                match output {
+                    Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+                    Err(e) => return LayerVerdict::Unknown(format!("could not run {command}: {e}")),
+                }
+            }
+        };
+        match instance.decode(&model) {
+            Ok(Some(layout)) if verify_layout(storage, candidate, &layout).is_empty() => LayerVerdict::Fits(layout, vec![]),
+            Ok(Some(_)) => LayerVerdict::Unknown("the model is not a valid layout".to_owned()),
+            Ok(None) => LayerVerdict::DoesNotFit("the SAT encoding has no model".to_owned()),
+            Err(e) => LayerVerdict::Unknown(e.to_string()),
+        }
+    }
+}
+
+#[test]
+fn test_sat_round_trip() {
+    use crate::data_configuration::{Pin, RectConfiguration};
+    use crate::rect::ContactPolicy;
+    use crate::Settings;
+
+    let configuration = || RectConfiguration::new(
+        Rectangle::new(-1, 4, 6),
+        vec![Rectangle::new(1, 4, 2), Rectangle::new(2, 2, 4), Rectangle::new(3, 2, 4)],
+    );
+    let rects = configuration();
+    let candidate: Combination = [1, 2, 3].iter().map(|id| rects.available_block_map[id]).collect();
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let instance = SatInstance::new(&storage, &candidate);
+    assert_eq!((instance.scale, instance.width, instance.height), (2, 3, 2));
+
+    // the exported CNF, solved by the DPLL, gives a valid layout
+    let cnf = instance.to_dimacs();
+    let clauses = cnf.lines()
+        .filter(|l| !l.starts_with('c') && !l.starts_with('p'))
+        .map(|l| l.split_whitespace().map(|v| v.parse::<Literal>().unwrap()).filter(|v| *v != 0).collect::<Clause>())
+        .collect::<Vec<_>>();
+    assert_eq!(clauses, instance.clauses);
+    let layout = instance.decode(&instance.solve_dpll(100_000, &BudgetTracker::unlimited()).unwrap()).unwrap().unwrap();
+    assert!(verify_layout(&storage, &candidate, &layout).is_empty(), "{layout:?}");
+    assert!(matches!(SatSolver { command: None, max_decisions: 100_000 }.solve(&storage, &candidate), LayerVerdict::Fits(..)));
+
+    // the DPLL gives up after its decisions or the time of the candidate budget
+    assert!(matches!(SatSolver { command: None, max_decisions: 0 }.solve(&storage, &candidate), LayerVerdict::OutOfBudget(reason) if reason == "gave up after 0 decisions"));
+    let budget = BudgetTracker::new(crate::budget::CandidateBudget { max_duration: Some(std::time::Duration::ZERO), ..Default::default() });
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    assert!(matches!(instance.solve_dpll(100_000, &budget), Err(reason) if reason.starts_with("time")));
+
+    // a pinned piece stays where it is
+    let pinned = configuration().with_pin(1, Pin { position: Some((4, 0)), ..Default::default() }).unwrap();
+    let storage = ProgramStorage::new(&pinned, Settings::default());
+    let layout = SatSolver { command: None, max_decisions: 100_000 }.solve(&storage, &candidate).layout().unwrap();
+    assert!(layout.iter().any(|p| p.rect.id == 1 && (p.x, p.y) == (4, 0)));
+
+    // with clearance there is no model
+    let storage = ProgramStorage::new(&rects, Settings { contact_policy: ContactPolicy { piece_clearance: 1, wall_clearance: 0 }, ..Default::default() });
+    let instance = SatInstance::new(&storage, &candidate);
+    assert_eq!(instance.scale, 1);
+    assert!(instance.decode(&instance.solve_dpll(100_000, &BudgetTracker::unlimited()).unwrap()).unwrap().is_none());
+    assert!(matches!(SatSolver { command: None, max_decisions: 100_000 }.solve(&storage, &candidate), LayerVerdict::DoesNotFit(_)));
+
+    // models, which break the rules, are found by the verifier
+    let path = std::env::temp_dir().join(format!("holzpuzzle_test_{}.model", std::process::id()));
+    let path = path.to_str().unwrap();
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let variables = SatInstance::new(&storage, &candidate).variables;
+    fs::write(path, format!("s SATISFIABLE\nv {} 0", (1..=variables).join(" "))).unwrap();
+    assert!(matches!(import_model(&storage, &candidate, path), Err(PuzzleError::InvalidLayout { .. })));
+    fs::write(path, "v 1 x 0").unwrap();
+    assert!(matches!(import_model(&storage, &candidate, path), Err(PuzzleError::Parse { .. })));
+    let _ = fs::remove_file(path);
+}
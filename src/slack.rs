@@ -0,0 +1,87 @@
+use crate::ProgramStorage;
+use crate::rect::{Combination, RecDimension, Rectangle};
+use crate::resolution::Resolution;
+
+/// how much bigger the big rect has to be for a candidate to fit, `None` if it does not fit even with much more space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Slack {
+    /// only wider
+    pub width: Option<RecDimension>,
+    /// only higher
+    pub height: Option<RecDimension>,
+    /// wider and higher by the same amount
+    pub both: Option<RecDimension>,
+}
+
+impl Slack {
+    /// the amounts in the measured unit
+    pub(crate) fn format(&self, resolution: &Resolution) -> String {
+        let f = |v: Option<RecDimension>| v.map_or("more than the pieces".to_owned(), |v| format!("{} {}", resolution.format(v), resolution.unit));
+        format!("wider by {}, higher by {}, both by {}", f(self.width), f(self.height), f(self.both))
+    }
+}
+
+/// check if the candidate fits with the layer solver of the settings, if the big rect grows by `dw` and `dh`\
+/// the blocked zones and pinned pieces keep their place at the upper left corner
+fn fits_grown(storage: &ProgramStorage, candidate: &Combination, dw: RecDimension, dh: RecDimension) -> bool {
+    let mut grown = storage.rect_configuration.clone();
+    let big_rect = grown.big_rect;
+    grown.big_rect = Rectangle::new(big_rect.id, big_rect.height + dh, big_rect.width + dw);
+    let grown_storage = ProgramStorage::new(&grown, storage.settings.clone());
+    storage.settings.layer_solver().solve(&grown_storage, candidate).layout().is_some()
+}
+
+/// the smallest growth, at which the candidate fits, with a binary search up to `limit`\
+/// this assumes, that more space never hurts, which is true for exact solvers and nearly true for heuristics
+fn smallest_growth<F: Fn(RecDimension) -> bool>(limit: RecDimension, fits: F) -> Option<RecDimension> {
+    if fits(0) {
+        return Some(0);
+    }
+    if !fits(limit) {
+        return None;
+    }
+    let (mut lower, mut upper) = (0, limit);
+    while upper - lower > 1 {
+        let middle = lower + (upper - lower) / 2;
+        match fits(middle) {
+            true => upper = middle,
+            false => lower = middle,
+        }
+    }
+    Some(upper)
+}
+
+/// by how much a candidate misses the big rect: the smallest enlargement of the big rect, so it fits\
+/// growing the big rect by some amount is the same as shrinking all pieces in a row or column by it in total
+pub(crate) fn slack(storage: &ProgramStorage, candidate: &Combination) -> Slack {
+    let clearance = storage.settings.contact_policy.piece_clearance;
+    // all pieces in one row or column always have enough space
+    let limit = candidate.iter().map(|r| r.width.max(r.height) + clearance).sum::<RecDimension>();
+    Slack {
+        width: smallest_growth(limit, |d| fits_grown(storage, candidate, d, 0)),
+        height: smallest_growth(limit, |d| fits_grown(storage, candidate, 0, d)),
+        both: smallest_growth(limit, |d| fits_grown(storage, candidate, d, d)),
+    }
+}
+
+#[test]
+fn test_slack() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::Settings;
+
+    // two 3x3 squares next to each other need a width of 6, one above the other a height of 6
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 4, 5),
+        vec![Rectangle::new(1, 3, 3), Rectangle::new(2, 3, 3), Rectangle::new(3, 4, 1)],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = [1, 2].iter().map(|id| rects.available_block_map[id]).collect();
+    assert_eq!(slack(&storage, &candidate), Slack { width: Some(1), height: Some(2), both: Some(1) });
+    assert_eq!(slack(&storage, &candidate).format(&Resolution::default()), "wider by 1 units, higher by 2 units, both by 1 units");
+
+    // a candidate, which fits, has no slack, the clearance counts as well
+    let candidate: Combination = [1, 3].iter().map(|id| rects.available_block_map[id]).collect();
+    assert_eq!(slack(&storage, &candidate), Slack { width: Some(0), height: Some(0), both: Some(0) });
+    let storage = ProgramStorage::new(&rects, Settings { contact_policy: crate::rect::ContactPolicy { piece_clearance: 2, wall_clearance: 0 }, ..Default::default() });
+    assert_eq!(slack(&storage, &candidate).width, Some(1));
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+use itertools::Itertools;
+
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::ProgramStorage;
+use crate::rect::{Combination, RecId};
+
+/// how long one of the step3 threads ran and how many candidates it checked
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ThreadStatistics {
+    pub number: u8,
+    pub duration: Duration,
+    pub candidates: u32,
+}
+
+/// timings collected while the pipeline runs, the counts are taken from the storage at the end
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RunStatistics {
+    pub steps: Vec<(&'static str, Duration)>,
+    pub threads: Vec<ThreadStatistics>,
+}
+
+/// everything of the statistics report, ready to be written as text, JSON or HTML
+pub(crate) struct Report {
+    pub steps: Vec<(&'static str, f64)>,
+    pub threads: Vec<(u8, f64, u32)>,
+    /// number of combinations with this many pieces, after each step
+    pub pieces_per_combination: Vec<(&'static str, BTreeMap<usize, usize>)>,
+    /// number of fitting layers, which cover at least this percentage of the usable area, in steps of 10
+    pub utilisation: BTreeMap<u32, usize>,
+    /// how often each piece is part of a fitting layer and of a combined solution
+    pub piece_frequency: Vec<(&'static str, BTreeMap<RecId, usize>)>,
+}
+
+/// how many combinations have how many pieces
+fn count_by_size<'a, I: IntoIterator<Item=&'a Combination>>(combinations: I) -> BTreeMap<usize, usize> {
+    combinations.into_iter().map(|c| c.len()).counts().into_iter().collect()
+}
+
+/// how often each piece appears in the combinations
+pub(crate) fn count_pieces<'a, I: IntoIterator<Item=&'a Combination>>(combinations: I) -> BTreeMap<RecId, usize> {
+    combinations.into_iter().flatten().map(|r| r.id).counts().into_iter().collect()
+}
+
+/// a JSON object with the counts, the keys are written as strings
+fn json_counts<K: std::fmt::Display>(counts: &BTreeMap<K, usize>) -> String {
+    format!("{{{}}}", counts.iter().map(|(k, v)| format!("\"{k}\": {v}")).join(", "))
+}
+
+impl Report {
+    pub(crate) fn new(storage: &ProgramStorage, statistics: &RunStatistics) -> Report {
+        let usable_area = storage.rect_configuration.usable_area().max(1) as u64;
+        let combined_layers = storage.combined_solutions.iter().flatten().collect::<Vec<_>>();
+        Report {
+            steps: statistics.steps.iter().map(|(name, d)| (*name, d.as_secs_f64())).collect(),
+            threads: statistics.threads.iter().sorted_by_key(|t| t.number).map(|t| (t.number, t.duration.as_secs_f64(), t.candidates)).collect(),
+            pieces_per_combination: vec![
+                ("step1_candidates", count_by_size(&storage.gathered_combinations)),
+                ("step2_deduplicated", count_by_size(&storage.deduplicated_combinations)),
+                ("step3_fitting", count_by_size(&storage.solutions)),
+                ("step4_combined_layers", count_by_size(combined_layers.iter().copied())),
+                ("step5_final", count_by_size(&storage.final_combinations)),
+            ],
+            utilisation: storage.solutions.iter()
+                .map(|c| (c.iter().map(|r| r.area as u64).sum::<u64>() * 100 / usable_area).min(100) as u32 / 10 * 10)
+                .map(|bucket| bucket.min(90))
+                .counts().into_iter().collect(),
+            piece_frequency: vec![
+                ("fitting_layers", count_pieces(&storage.solutions)),
+                ("combined_solutions", count_pieces(combined_layers.iter().copied())),
+            ],
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut lines = vec!["RUN STATISTICS".to_owned(), "time per step:".to_owned()];
+        lines.extend(self.steps.iter().map(|(name, seconds)| format!("  {name}: {seconds:.2} s")));
+        lines.push("time per step3 thread:".to_owned());
+        lines.extend(self.threads.iter().map(|(number, seconds, candidates)| format!("  thread {number}: {seconds:.2} s, {candidates} candidates")));
+        lines.push("combinations per number of pieces:".to_owned());
+        lines.extend(self.pieces_per_combination.iter().map(|(step, counts)|
+            format!("  {step}: {}", counts.iter().map(|(pieces, count)| format!("{pieces} pieces {count}")).join(", "))));
+        lines.push("area utilisation of fitting layers:".to_owned());
+        lines.extend(self.utilisation.iter().map(|(from, count)| format!("  {from:>3}% - {:>3}%: {count}", from + 10)));
+        lines.push("piece frequency:".to_owned());
+        lines.extend(self.piece_frequency.iter().map(|(name, counts)|
+            format!("  {name}: {}", counts.iter().map(|(id, count)| format!("{id}: {count}")).join(", "))));
+        lines.join("\n")
+    }
+
+    fn to_json(&self) -> String {
+        let object = |entries: Vec<String>| format!("{{{}}}", entries.join(", "));
+        object(vec![
+            format!("\"steps\": [{}]", self.steps.iter().map(|(name, seconds)| format!("{{\"name\": \"{name}\", \"seconds\": {seconds:.3}}}")).join(", ")),
+            format!("\"threads\": [{}]", self.threads.iter()
+                .map(|(number, seconds, candidates)| format!("{{\"thread\": {number}, \"seconds\": {seconds:.3}, \"candidates\": {candidates}}}")).join(", ")),
+            format!("\"pieces_per_combination\": {}", object(self.pieces_per_combination.iter().map(|(step, c)| format!("\"{step}\": {}", json_counts(c))).collect())),
+            format!("\"utilisation_percent\": {}", json_counts(&self.utilisation)),
+            format!("\"piece_frequency\": {}", object(self.piece_frequency.iter().map(|(name, c)| format!("\"{name}\": {}", json_counts(c))).collect())),
+        ])
+    }
+}
+
+/// print the statistics of the run and write them as text and JSON, if the paths are set
+pub(crate) fn report_statistics(storage: &ProgramStorage) -> PuzzleResult<()> {
+    let report = Report::new(storage, &storage.statistics.lock().unwrap());
+    let text = report.to_text();
+    println!("{text}");
+    if let Some(path) = storage.settings.statistics_path {
+        fs::write(path, &text).map_err(|e| PuzzleError::io(path, e))?;
+    }
+    if let Some(path) = storage.settings.statistics_json_path {
+        fs::write(path, report.to_json()).map_err(|e| PuzzleError::io(path, e))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_statistics() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::rect::Rectangle;
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 4, 5),
+        vec![Rectangle::new(1, 2, 5), Rectangle::new(2, 2, 5), Rectangle::new(3, 1, 1)],
+    );
+    let mut storage = ProgramStorage::new(&rects, Settings::default());
+    let combination = |ids: &[RecId]| ids.iter().map(|id| rects.available_block_map[id]).collect::<Combination>();
+    storage.solutions = [combination(&[1, 2]), combination(&[1, 3]), combination(&[3])].into_iter().collect();
+    storage.combined_solutions = [[combination(&[1, 2]), combination(&[3])].into_iter().collect()].into_iter().collect();
+    storage.statistics.lock().unwrap().steps.push(("step3", Duration::from_millis(1500)));
+    storage.statistics.lock().unwrap().threads.push(ThreadStatistics { number: 1, duration: Duration::from_secs(1), candidates: 3 });
+
+    let report = Report::new(&storage, &storage.statistics.lock().unwrap());
+    // 20, 11 and 1 of 20 units
+    assert_eq!(report.utilisation, BTreeMap::from([(0, 1), (50, 1), (90, 1)]));
+    let text = report.to_text();
+    assert!(text.contains("  step3: 1.50 s") && text.contains("  thread 1: 1.00 s, 3 candidates"), "{text}");
+    assert!(text.contains("  step3_fitting: 1 pieces 1, 2 pieces 2"), "{text}");
+    assert!(text.contains("  combined_solutions: 1: 1, 2: 1, 3: 1"), "{text}");
+    let json = report.to_json();
+    assert!(json.starts_with("{\"steps\": [{\"name\": \"step3\", \"seconds\": 1.500}], \"threads\": [{\"thread\": 1, \"seconds\": 1.000, \"candidates\": 3}]"), "{json}");
+    assert!(json.contains("\"piece_frequency\": {\"fitting_layers\": {\"1\": 2, \"2\": 1, \"3\": 2}"), "{json}");
+}
@@ -1,28 +1,45 @@
-use std::{fs, thread};
-use std::cmp::max;
+use std::thread;
+use std::cmp::Reverse;
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use itertools::Itertools;
 
-use crate::{ProgramStorage, Settings};
+use crate::budget::BudgetTracker;
+use crate::error::PuzzleResult;
+use crate::free_space::FreeSpace;
+use crate::layer_solver::{LayerSolver, LayerVerdict};
+use crate::near_miss::{analyze_near_miss, record_near_miss, write_near_miss_report};
+use crate::ProgramStorage;
+#[cfg(test)]
+use crate::Settings;
+#[cfg(test)]
 use crate::data_configuration::RectConfiguration;
 
-use crate::rect::{Combination, combination_from_string, combination_storage_from_file, combination_storage_to_file, combination_to_string, duplicate_combination, get_unique_combination_key, get_unique_permutation_key, PlacedRectangle, RecDimension, RecId, Rectangle, RectCombinationStorage};
+use crate::rect::{Combination, combination_from_string, combination_order_key, combination_storage_from_file, combination_storage_from_file_allowing, in_file, combination_storage_to_file, combination_to_string, duplicate_combination, get_unique_combination_key, PlacedRectangle, placement_to_string, RecDimension, Rectangle, RectCombinationStorage};
+#[cfg(test)]
+use crate::rect::RecId;
+#[cfg(test)]
 use crate::rect_image::draw_image;
+use crate::result_file::{read_result_file, ResultStep, write_result_file};
+use crate::statistics::{count_pieces, ThreadStatistics};
+use crate::text_render::render_layout;
+use crate::trace::PlacementTrace;
+use crate::verdict_cache::VerdictCache;
+use crate::verify::verify_and_report;
 
 
 /// collect all candidates, which may fit inside the big rectangle
-pub(crate) fn step1_generate_candiates(storage: &mut ProgramStorage) {
+pub(crate) fn step1_generate_candiates(storage: &mut ProgramStorage) -> PuzzleResult<()> {
     let mut gathered_combinations = RectCombinationStorage::new();
     println!("GATHERING COMBINATIONS...");
     if !storage.settings.steps[0] {
         println!("SKIPPED");
         if let Some(path) = storage.settings.candidates_path {
-            storage.gathered_combinations = combination_storage_from_file(path, storage);
+            storage.gathered_combinations = combination_storage_from_file(path, ResultStep::Candidates, storage)?;
         }
-        return;
+        return Ok(());
     }
     let start = Instant::now();
     let mut counter: u64 = 0;
@@ -31,13 +48,17 @@ pub(crate) fn step1_generate_candiates(storage: &mut ProgramStorage) {
         let mut counter2 = 0;
         for comb in storage.rect_configuration.available_blocks.iter().sorted_by_key(|r| r.id).combinations(s as usize) {
             counter += 1;
-            if counter % 1000000 == 0 {
+            if counter.is_multiple_of(1000000) {
                 println!("{s} {}, {} {}", counter, gathered_combinations.len(), start.elapsed().as_secs());
             }
             // if this combination may fit in the big rectangle, keep it
-            if comb.iter().map(|r| r.area).sum::<u32>() <= storage.rect_configuration.big_rect.area {
-                counter2 += 1;
-                gathered_combinations.insert(comb.iter().map(|r| **r).collect());
+            if comb.iter().map(|r| r.area).sum::<u32>() <= storage.rect_configuration.usable_area() {
+                let combination: Combination = comb.iter().map(|r| **r).collect();
+                // skip combinations, which contradict the pins or layer constraints
+                if storage.rect_configuration.allows_layer(&combination) {
+                    counter2 += 1;
+                    gathered_combinations.insert(combination);
+                }
             }
         }
         println!("{s} {counter2}");
@@ -52,47 +73,56 @@ pub(crate) fn step1_generate_candiates(storage: &mut ProgramStorage) {
     storage.gathered_combinations = gathered_combinations;
 
     if let Some(path) = storage.settings.candidates_path {
-        combination_storage_to_file(path, &storage.gathered_combinations);
+        combination_storage_to_file(path, ResultStep::Candidates, storage, &storage.gathered_combinations)?;
     }
 
     println!("GATHERING COMBINATIONS... DONE AFTER {} seconds, found {} combinations", start.elapsed().as_secs(), storage.gathered_combinations.len());
+    Ok(())
 }
 
 /// deduplicates all equivalent combinations\
 /// needs changes in filter_fitting_candidates to use this output as next step\
 /// needs "reduplication" before calculate_matches!
-pub(crate) fn step2_deduplication(storage: &mut ProgramStorage) {
+pub(crate) fn step2_deduplication(storage: &mut ProgramStorage) -> PuzzleResult<()> {
     let candidates = storage.gathered_combinations.iter().cloned().collect::<Vec<BTreeSet<Rectangle>>>();
 
     println!("DEDUPLICATING {} COMBINATIONS...", candidates.len());
+    // the ids decide, which of the equivalent combinations is kept, not the order of the hash set
     storage.deduplicated_combinations = candidates.into_iter()
-        .sorted_by_key(|c| c.iter().map(|r| r.id as i32).sum::<i32>())
-        .unique_by(get_unique_combination_key)
+        .sorted_by(|a, b| a.iter().map(|r| r.id as i32).sum::<i32>().cmp(&b.iter().map(|r| r.id as i32).sum::<i32>()).then_with(|| a.cmp(b)))
+        // candidates from a file may not respect the pins and layer constraints
+        .filter(|c| storage.rect_configuration.allows_layer(c))
+        // pinned or constrained pieces can not be exchanged with their duplicates, so they are part of the key
+        .unique_by(|c| (get_unique_combination_key(c), c.iter().filter(|r| storage.rect_configuration.is_distinguished(r.id)).map(|r| r.id).collect::<Vec<_>>()))
         .collect();
     if let Some(path) = storage.settings.deduplicated_combinations_path {
-        fs::write(
-            path,
-            storage.deduplicated_combinations.iter()
-                .map(combination_to_string)
-                .join("\n"),
-        ).expect("Unable to write file");
+        combination_storage_to_file(path, ResultStep::DeduplicatedCandidates, storage, &storage.deduplicated_combinations)?;
     }
     println!("We have {} deduplicated combinations!", storage.deduplicated_combinations.len());
+    Ok(())
 }
 
 /// check for each combination, if it can be arranged inside the big rectangle
-pub(crate) fn step3_filter_fitting_candidates(storage: &mut ProgramStorage) {
+pub(crate) fn step3_filter_fitting_candidates(storage: &mut ProgramStorage) -> PuzzleResult<()> {
     println!("CALCULATING SOLUTIONS (1 layer)...");
     if !storage.settings.steps[1] {
         if let Some(path) = storage.settings.fitting_candidates_path {
-            storage.solutions = combination_storage_from_file(path, storage);
+            storage.solutions = combination_storage_from_file(path, ResultStep::FittingCandidates, storage)?;
+        }
+        if let Some(path) = storage.settings.unknown_candidates_path {
+            storage.unknown_candidates = Mutex::new(combination_storage_from_file(path, ResultStep::UnknownCandidates, storage)?);
         }
         println!("SKIPPED");
-        return;
+        return Ok(());
     }
 
     let start = Instant::now();
     let output = Arc::new(Mutex::new(HashSet::new()));
+    // verdicts of earlier runs, only candidates with changed inputs are checked again
+    let cache = Arc::new(Mutex::new(match storage.settings.verdict_cache_path {
+        Some(path) => VerdictCache::from_file(path)?,
+        None => VerdictCache::default(),
+    }));
     // do all the checking threaded
     thread::scope(|s| {
         let input = Arc::new((
@@ -101,7 +131,7 @@ pub(crate) fn step3_filter_fitting_candidates(storage: &mut ProgramStorage) {
                     0,
                     // sort by area of combination
                     storage.deduplicated_combinations.iter().cloned()
-                        .sorted_by_key(|c| -(c.iter().map(|r| r.area).sum::<u32>() as i32))
+                        .sorted_by_cached_key(|c| combination_order_key(c, storage.settings.tie_break_seed))
                         .collect::<Vec<BTreeSet<Rectangle>>>()
                 )
             )
@@ -112,8 +142,9 @@ pub(crate) fn step3_filter_fitting_candidates(storage: &mut ProgramStorage) {
         for i in 1..=storage.settings.thread_count {
             let thread_input = input.clone();
             let thread_output = output.clone();
+            let thread_cache = cache.clone();
             let thread = s.spawn(move || {
-                step3_thread_procedure(i, thread_input, thread_output);
+                step3_thread_procedure(i, thread_input, thread_output, thread_cache);
             });
             threads.push(thread);
         }
@@ -125,29 +156,89 @@ pub(crate) fn step3_filter_fitting_candidates(storage: &mut ProgramStorage) {
     // save, sort, do all that stuff
     println!("All threads finished! Took us {} seconds", start.elapsed().as_secs());
     let fitting_candidates = Arc::try_unwrap(output).unwrap().into_inner().unwrap();
+    let cache = Arc::try_unwrap(cache).ok().unwrap().into_inner().unwrap();
+    println!("Verdict cache: {} hits, {} misses", cache.hits, cache.misses);
+    println!("Placed by: {}", storage.placements_by_strategy.lock().unwrap().iter().map(|(name, count)| format!("{name} {count}")).join(", "));
+    if let Some(path) = storage.settings.verdict_cache_path {
+        cache.to_file(path)?;
+    }
+    if let Some(path) = storage.settings.near_miss_report_path {
+        let near_misses = storage.near_misses.lock().unwrap();
+        println!("Closest near miss: {}", near_misses.first().map_or("none".to_owned(), |n| combination_to_string(&n.candidate)));
+        write_near_miss_report(storage, path, &near_misses)?;
+    }
     if let Some(path) = storage.settings.fitting_candidates_path {
-        combination_storage_to_file(path, &fitting_candidates);
+        combination_storage_to_file(path, ResultStep::FittingCandidates, storage, &fitting_candidates)?;
+    }
+    let undecided = storage.undecided_candidates.lock().unwrap();
+    println!("Undecided candidates (budget {}): {}", storage.settings.candidate_budget, undecided.len());
+    if let Some(path) = storage.settings.undecided_candidates_path {
+        combination_storage_to_file(path, ResultStep::UndecidedCandidates, storage, &undecided)?;
+    }
+    let unknown = storage.unknown_candidates.lock().unwrap();
+    if let Some(path) = storage.settings.unknown_candidates_path {
+        combination_storage_to_file(path, ResultStep::UnknownCandidates, storage, &unknown)?;
     }
+    println!("Verdicts: {} fit, {} proven not to fit, {} unknown, {} undecided",
+             fitting_candidates.len(), storage.deduplicated_combinations.len() - fitting_candidates.len() - unknown.len() - undecided.len(),
+             unknown.len(), undecided.len());
+    drop(unknown);
+    drop(undecided);
     storage.solutions = fitting_candidates;
     println!("CALCULATING SOLUTIONS (1 layer)... DONE AFTER {} seconds, found {} solutions", start.elapsed().as_secs(), storage.solutions.len());
+    Ok(())
 }
 
+/// decide the undecided candidates of an earlier step3 again, with another solver or the current candidate budget\
+/// the ones, which fit, are added to the fitting candidates, the ones, which are still undecided, stay in their file
+pub(crate) fn step3_retry_undecided(storage: &ProgramStorage, solver: &dyn LayerSolver) -> PuzzleResult<()> {
+    let (Some(undecided_path), Some(fitting_path)) = (storage.settings.undecided_candidates_path, storage.settings.fitting_candidates_path) else {
+        println!("WARNING: retrying needs undecided_candidates_path and fitting_candidates_path");
+        return Ok(());
+    };
+    // retrying usually follows raising the budget, so the files may come from another one
+    let undecided = combination_storage_from_file_allowing(undecided_path, ResultStep::UndecidedCandidates, storage, &["candidate_budget"])?;
+    let mut fitting = combination_storage_from_file_allowing(fitting_path, ResultStep::FittingCandidates, storage, &["candidate_budget"])?;
+    let mut still_undecided = RectCombinationStorage::new();
+    println!("RETRYING {} UNDECIDED CANDIDATES with {} (budget {})...", undecided.len(), solver.name(), storage.settings.candidate_budget);
+    for candidate in undecided.iter().sorted_by_cached_key(|c| combination_order_key(c, storage.settings.tie_break_seed)) {
+        let verdict = solver.solve(storage, candidate);
+        println!("{}: {verdict}", combination_to_string(candidate));
+        match verdict {
+            LayerVerdict::Fits(layout, _) if verify_and_report(storage, candidate, &layout) => { fitting.insert(candidate.clone()); }
+            LayerVerdict::OutOfBudget(_) => { still_undecided.insert(candidate.clone()); }
+            _ => {}
+        }
+    }
+    combination_storage_to_file(fitting_path, ResultStep::FittingCandidates, storage, &fitting)?;
+    combination_storage_to_file(undecided_path, ResultStep::UndecidedCandidates, storage, &still_undecided)?;
+    println!("RETRYING... DONE, {} fitting candidates, {} still undecided", fitting.len(), still_undecided.len());
+    Ok(())
+}
+
+/// shared input of the step3 threads: the storage and a counter with the remaining candidates
+pub(crate) type Step3Input<'a> = (&'a ProgramStorage<'a>, Mutex<(i32, Vec<BTreeSet<Rectangle>>)>);
+
 /// this function is the main function, which will be run by the threads of filter_fitting_candidates
 pub(crate) fn step3_thread_procedure(number: u8,
-                                     input: Arc<(&ProgramStorage, Mutex<(i32, Vec<BTreeSet<Rectangle>>)>)>,
+                                     input: Arc<Step3Input>,
                                      output: Arc<Mutex<RectCombinationStorage>>,
+                                     cache: Arc<Mutex<VerdictCache>>,
 ) {
     let thread_start = Instant::now();
     let storage = input.0;
     let mutex = &input.1;
+    let mut checked = 0;
     loop {
         // get combination to check
         let mut lock = mutex.lock().unwrap();
         if lock.1.is_empty() {
             println!("Thread {number} is shutting down! I was alive {} for seconds.", thread_start.elapsed().as_secs());
             drop(lock);
+            storage.statistics.lock().unwrap().threads.push(ThreadStatistics { number, duration: thread_start.elapsed(), candidates: checked });
             return;
         }
+        checked += 1;
         lock.0 += 1;
         let counter = lock.0;
         let data = lock.1.remove(0);
@@ -155,153 +246,245 @@ pub(crate) fn step3_thread_procedure(number: u8,
         if counter % 100 == 0 {
             println!("Thread {number} working counter {counter} with data {}. I am alive for {} seconds.", data.iter().map(|r| r.id).join(","), thread_start.elapsed().as_secs());
         }
-        // ic combination can be put somehow in the big rect, store it
-        if step3_check_candidate(number, counter, storage, &data).is_some() {
-            let mut lock = output.lock().unwrap();
-            lock.insert(data);
-            println!("Thread {number}: We have {} candidates so far.", lock.len());
-            drop(lock);
+        // reuse the verdict of an earlier run, if the inputs did not change
+        let cached = cache.lock().unwrap().lookup(storage, &data);
+        let verdict = match cached {
+            Some(verdict) => verdict,
+            None => {
+                let verdict = step3_decide_candidate(number, counter, storage, &data);
+                cache.lock().unwrap().insert(storage, &data, &verdict);
+                verdict
+            }
+        };
+        match verdict {
+            // ic combination can be put somehow in the big rect, store it
+            LayerVerdict::Fits(..) => {
+                let mut lock = output.lock().unwrap();
+                lock.insert(data);
+                println!("Thread {number}: We have {} candidates so far.", lock.len());
+                drop(lock);
+            }
+            // a later run with a bigger budget may decide it
+            LayerVerdict::OutOfBudget(reason) => {
+                println!("Thread {number}: {} is undecided, {reason}", combination_to_string(&data));
+                storage.undecided_candidates.lock().unwrap().insert(data);
+            }
+            verdict => {
+                // remember how close it came, this is where testing by hand with the real pieces is worth it
+                if storage.settings.near_miss_report_path.is_some() {
+                    if let Some(near_miss) = analyze_near_miss(storage, &data) {
+                        record_near_miss(&mut storage.near_misses.lock().unwrap(), near_miss, storage.settings.near_miss_count);
+                    }
+                }
+                // the heuristic did not find a layout, but that does not prove anything
+                if let LayerVerdict::Unknown(_) = verdict {
+                    storage.unknown_candidates.lock().unwrap().insert(data);
+                }
+            }
         }
     }
 }
 
-/// check if a combination fits inside the big rect
+/// check if a combination fits inside the big rect, with the layer solver of the settings
+#[cfg(test)]
 pub(crate) fn step3_check_candidate(number: u8, counter: i32,
                                     storage: &ProgramStorage,
                                     candidate: &BTreeSet<Rectangle>,
 ) -> Option<Vec<PlacedRectangle>> {
+    step3_decide_candidate(number, counter, storage, candidate).layout()
+}
+
+/// like `step3_check_candidate`, but keeps why a candidate does not fit or was not decided
+pub(crate) fn step3_decide_candidate(number: u8, counter: i32,
+                                     storage: &ProgramStorage,
+                                     candidate: &BTreeSet<Rectangle>,
+) -> LayerVerdict {
     let c_start = Instant::now();
-    // because the small rectangles can be rotated, we need to check each combination of each rotation for the input
-    for product in candidate.iter().map(|r| storage.rect_configuration.rotated_available_block_map.get(&r.id).unwrap()).multi_cartesian_product() {
-        // because I have no better idea, just check each permutation of each combination individually
-        for per in product.iter().cloned().permutations(product.len()).unique_by(get_unique_permutation_key) {
-            if let Some(sol) = step3_check_permutation(storage, per) {
-                if counter % 100 == 0 && number > 0 {
-                    println!("Thread {number} worked {counter} in {} seconds (success)", c_start.elapsed().as_secs());
-                }
-                println!("SOLUTION_DEBUG {}", sol.iter().map(|r| format!("{} {} {} {} {}", r.rect.id, r.rect.height, r.rect.width, r.x, r.y)).join("  "));
-                return Some(sol);
-            }
-        }
-    }
+    let solver = storage.settings.layer_solver();
+    let verdict = solver.solve(storage, candidate);
     if counter % 100 == 0 && number > 0 {
-        println!("Thread {number} worked {counter} in {} seconds (fail)", c_start.elapsed().as_secs());
+        println!("Thread {number} worked {counter} in {} seconds ({verdict})", c_start.elapsed().as_secs());
+    }
+    let LayerVerdict::Fits(sol, cuts) = verdict else {
+        return verdict;
+    };
+    for cut in &cuts {
+        println!("CUT {cut}");
+    }
+    println!("SOLUTION_DEBUG {}", placement_to_string(&storage.rect_configuration.resolution, &sol));
+    if let Some(width) = storage.settings.text_render_width {
+        println!("{}", render_layout(storage.rect_configuration, &sol, width));
     }
-    None
+    if !verify_and_report(storage, candidate, &sol) {
+        return LayerVerdict::Unknown("the layout of the solver is invalid".to_owned());
+    }
+    LayerVerdict::Fits(sol, cuts)
+}
+
+/// check if a specific set of rectangles fits inside the big rect, without rotating or rearranging them\
+/// `fixed` are rectangles, which are already placed and must not be moved
+#[cfg(test)]
+pub(crate) fn step3_check_permutation(storage: &ProgramStorage, candidate: Vec<&Rectangle>, fixed: &[PlacedRectangle]) -> Option<Vec<PlacedRectangle>> {
+    step3_check_permutation_traced(storage, candidate, fixed, &BudgetTracker::unlimited(), None)
 }
 
-/// check if a specific set of rectangles fits inside the big rect, without rotating or rearranging them
-pub(crate) fn step3_check_permutation(storage: &ProgramStorage, candidate: Vec<&Rectangle>) -> Option<Vec<PlacedRectangle>> {
+/// like `step3_check_permutation`, records each intermediate state into `trace`\
+/// gives up, if `budget` has no compaction steps left
+pub(crate) fn step3_check_permutation_traced(storage: &ProgramStorage,
+                                             candidate: Vec<&Rectangle>,
+                                             fixed: &[PlacedRectangle],
+                                             budget: &BudgetTracker,
+                                             mut trace: Option<&mut PlacementTrace>,
+) -> Option<Vec<PlacedRectangle>> {
     /*
     idea:
     put all rectangles inside the big rectangle in order
-    if rect has e.g. width 23 and x is 0 at the moment, it will occupy 0, 1, 2... 22, not 23!
     leave space horizontally and vertically to accommodate for slightly smaller or bigger ones
-    if width is filled, go to "higher" row
-    move rectangles closer together without collisions (touching is also not allowed)
+    if width is filled, go to "higher" row, each rect goes as low as possible (also into holes below earlier rows)
+    move rectangles closer together, as close as the contact policy allows (with clearance 0 they may touch)
      */
+    let policy = &storage.settings.contact_policy;
+    // the first row and column start this far from the walls
+    let wall = policy.wall_clearance;
     // x value to put next rectangle, with "spaces"
-    let mut x_with_spaces: RecDimension = 0;
+    let mut x_with_spaces: RecDimension = wall;
     // x without spaces to check for "full row"
     let mut x_normal: RecDimension = 0;
-    // how big the "spaces" should be
-    let distance = storage.settings.distance_between_rectangles;
+    // how big the "spaces" should be, at least the clearance between the rectangles
+    let distance = storage.settings.distance_between_rectangles.max(policy.piece_clearance);
     // store placed rects
     let mut placed_rects: Vec<PlacedRectangle> = vec![];
-    // make everything more cache efficient (width and height with spaces put in)
-    let big_width = storage.rect_configuration.big_rect.width;
-    // store the height of each column
-    let x_size = big_width + storage.settings.max_rectangle_amount as RecDimension * distance;
-    let mut taken_with_spaces = vec![0 as RecDimension; x_size as usize];
-    let mut taken = vec![0 as RecDimension; x_size as usize];
+    // blocked zones and fixed rects can not be moved, the other rects have to go around them
+    let obstacles = storage.rect_configuration.blocked_zones.iter().chain(fixed).copied().collect::<Vec<_>>();
+    let big_rect = storage.rect_configuration.big_rect;
+    // the free space with "spaces" is bigger than the big rect, all spaces have to fit in
+    let spaces = candidate.len() as RecDimension * distance;
+    let mut free_with_spaces = FreeSpace::new(wall + big_rect.width + spaces, wall + big_rect.height + spaces);
+    free_with_spaces.occupy((0, 0, wall, wall + big_rect.height + spaces));
+    free_with_spaces.occupy((0, 0, wall + big_rect.width + spaces, wall));
+    for o in &obstacles {
+        // rects have to stay clear of obstacles and go at least one space above them
+        let clearance = policy.clearance_to(o);
+        let (x, y) = (o.x.saturating_sub(clearance), o.y.saturating_sub(clearance));
+        free_with_spaces.occupy((x, y, o.x + o.rect.width + clearance - x, o.y + o.rect.height + distance.max(clearance) - y));
+    }
+    // the height of each placed rect without the spaces below it, to check for "full height"
+    let mut tops: Vec<RecDimension> = vec![];
     for rect in candidate {
         // if the new rect will collide with the border of the big rect
-        if x_normal + rect.width > big_width {
+        if x_normal + rect.width > big_rect.width {
             // go back to x 0 for next row
             x_normal = 0;
-            x_with_spaces = 0;
-            if taken.iter().max().unwrap() > &storage.rect_configuration.big_rect.height {
-                return None;
-            }
-        }
-        // the new rect can not be lower than the highest point within its "area"
-        let mut ymax_with_spaces: RecDimension = 0;
-        for i in x_with_spaces..x_with_spaces + rect.width {
-            ymax_with_spaces = max(ymax_with_spaces, taken_with_spaces[i as usize]);
-        }
-        let mut ymax: RecDimension = 0;
-        for i in x_with_spaces..x_with_spaces + rect.width {
-            ymax = max(ymax, taken[i as usize]);
+            x_with_spaces = wall;
         }
-        // if this is not the first row, add space to height
-        if ymax_with_spaces > 0 {
-            ymax_with_spaces += distance;
+        // the lowest free spot in this column, rects keep one space to the right and above
+        let Some(y_with_spaces) = free_with_spaces.lowest_at(x_with_spaces, rect.width, rect.height) else {
+            if let Some(trace) = trace.as_mut() {
+                trace.record(format!("no space for {} in this column", rect.label()), &[&placed_rects[..], fixed].concat(), None);
+            }
+            return None;
+        };
+        free_with_spaces.occupy((x_with_spaces, y_with_spaces, rect.width + distance, rect.height + distance));
+        // without the spaces, the rect would lie directly on the highest rect or obstacle below it
+        let below = |p: &PlacedRectangle| p.y < y_with_spaces && p.x < x_with_spaces + rect.width && x_with_spaces < p.x + p.rect.width;
+        let bottom = placed_rects.iter().zip(&tops)
+            .filter(|(p, _)| below(p))
+            .map(|(_, top)| *top)
+            .chain(obstacles.iter().filter(|o| below(o)).map(|o| o.y + o.rect.height + policy.clearance_to(o)))
+            .max()
+            .unwrap_or(wall);
+        if bottom + rect.height + wall > big_rect.height {
+            if let Some(trace) = trace.as_mut() {
+                trace.record(format!("{} is too high", rect.label()), &[&placed_rects[..], fixed].concat(), None);
+            }
+            return None;
         }
+        tops.push(bottom + rect.height);
         // add new rect to placed rects, at this position
         placed_rects.push(PlacedRectangle {
             rect: *rect,
             x: x_with_spaces,
-            y: ymax_with_spaces,
+            y: y_with_spaces,
         });
-        // calculate new height to avoid colliding with this rectangle when setting the next row
-        ymax_with_spaces += rect.height;
-        ymax += rect.height;
-        // store ymax for all "columns" the new rect occupies
-        for i in x_with_spaces..x_with_spaces + rect.width {
-            taken_with_spaces[i as usize] = ymax_with_spaces;
-        }
-        for i in x_with_spaces..x_with_spaces + rect.width {
-            taken[i as usize] = ymax;
+        if let Some(trace) = trace.as_mut() {
+            trace.record(format!("place {} at {}, {}", rect.label(), x_with_spaces, y_with_spaces), &[&placed_rects[..], fixed].concat(), Some(rect.id));
         }
         // calculate new x values for next rect
         x_with_spaces += rect.width + distance;
         x_normal += rect.width;
     }
-    if taken.iter().max().unwrap() > &storage.rect_configuration.big_rect.height {
-        return None;
-    }
     let mut compacted = true;
     let mut p;
-    // move the placed rects closer together, without touching or colliding
+    // move the placed rects closer together, as close as the contact policy allows
     while compacted {
         compacted = false;
         for i in 0..placed_rects.len() {
             p = *placed_rects.get(i).unwrap();
             // move this rect as far as possible
-            if p.compact(&placed_rects) {
+            if p.compact(&placed_rects, &obstacles, policy) {
+                if !budget.compaction_step() {
+                    return None;
+                }
                 compacted = true;
+                let old = placed_rects[i];
                 placed_rects[i] = p;
+                if let Some(trace) = trace.as_mut() {
+                    trace.record(format!("compact {}: {}, {} -> {}, {}", p.rect.label(), old.x, old.y, p.x, p.y), &[&placed_rects[..], fixed].concat(), Some(p.rect.id));
+                }
                 break;
             }
         }
     }
+    placed_rects.extend_from_slice(fixed);
     // check if all rects are inside the big rect now
-    if placed_rects.iter().all(|p| p.check_bounds(storage)) {
+    let outside = placed_rects.iter().filter(|p| !p.check_bounds(storage)).map(|p| p.rect.label()).collect::<Vec<_>>();
+    if let Some(trace) = trace.as_mut() {
+        let result = match outside.is_empty() {
+            true => "all inside".to_owned(),
+            false => format!("{} outside", outside.join(", ")),
+        };
+        trace.record(format!("bounds check: {result}"), &placed_rects, None);
+    }
+    if outside.is_empty() {
         Some(placed_rects)
     } else {
         None
     }
 }
 
+/// marks a line of the combined solutions, which has a layer that is only unknown to fit
+pub(crate) const UNPROVEN_MARKER: &str = " unproven";
+
 /// take three disjunctive combinations of the combinations, which fit inside the big rect\
 /// these three combinations represent the three layers inside the big rect
-pub(crate) fn step4_calculate_matches(storage: &mut ProgramStorage) {
+pub(crate) fn step4_calculate_matches(storage: &mut ProgramStorage) -> PuzzleResult<()> {
     if !storage.settings.steps[2] {
         if let Some(path) = storage.settings.solutions_filepath {
-            storage.combined_solutions = fs::read_to_string(path).unwrap_or_else(|_| "".to_owned())
-                .split('\n')
-                .filter(|line| !line.is_empty())
-                .map(|line|
-                    line.split(' ')
-                        .map(|c| combination_from_string(storage, c)).collect()
-                ).collect::<HashSet<BTreeSet<Combination>>>();
+            for line in read_result_file(path, ResultStep::CombinedSolutions, storage)?.unwrap_or_default() {
+                let (layers, unproven) = match line.strip_suffix(UNPROVEN_MARKER) {
+                    Some(layers) => (layers, true),
+                    None => (line.as_str(), false),
+                };
+                let solution = layers.split(' ')
+                    .map(|c| combination_from_string(storage, c).map_err(|e| in_file(e, path)))
+                    .collect::<PuzzleResult<BTreeSet<Combination>>>()?;
+                if unproven {
+                    storage.unproven_solutions.insert(solution.clone());
+                }
+                storage.combined_solutions.insert(solution);
+            }
         }
         println!("CALCULATING COMBINED SOLUTIONS (3 layers)...\nSKIPPED");
-        return;
+        return Ok(());
     }
-    // sort the candidates by area
-    let candidates = storage.solutions.iter()
-        .sorted_by_key(|c| -(c.iter().map(|r| r.area).sum::<u32>() as i32))
+    // layers without a layout, but without a proof either, may be part of a solution
+    let unknown = match storage.settings.unknown_layers_as_possible {
+        true => storage.unknown_candidates.get_mut().unwrap().clone(),
+        false => RectCombinationStorage::new(),
+    };
+    // sort the candidates by area, the order decides which duplicates end up in the combined solutions
+    let candidates = storage.solutions.iter().chain(unknown.difference(&storage.solutions))
+        .sorted_by_cached_key(|c| combination_order_key(c, storage.settings.tie_break_seed))
         .collect::<Vec<&BTreeSet<Rectangle>>>();
     let start = Instant::now();
 
@@ -315,36 +498,39 @@ pub(crate) fn step4_calculate_matches(storage: &mut ProgramStorage) {
                 let ks = *candidates.get(k).unwrap();
                 let amount = is.len() + js.len() + ks.len();
                 if amount >= storage.rect_configuration.available_blocks.len() {
-                    let vec: Vec<&Rectangle> = is.iter()
-                        .map(|r| storage.rect_configuration.duplication_map.get(&r.id).unwrap().first().unwrap())
-                        .chain(
-                            js.iter().map(|r| storage.rect_configuration.duplication_map.get(&r.id).unwrap().first().unwrap())
-                        )
-                        .chain(
-                            ks.iter().map(|r| storage.rect_configuration.duplication_map.get(&r.id).unwrap().first().unwrap())
-                        ).collect();
+                    // the representative of each rectangle, the first one with the same dimensions
+                    let vec: Vec<&Rectangle> = is.iter().chain(js.iter()).chain(ks.iter())
+                        .map(|r| storage.rect_configuration.duplicates_of(r.id).map(|d| d.first().unwrap_or(r)))
+                        .collect::<PuzzleResult<_>>()?;
                     if storage.rect_configuration.duplication_map.iter().all(
                         |(id, rects)| vec.iter().filter(|r| &r.id == id).count() <= rects.len()
                     ) {
                         println!("{} {} {}", i, j, k);
                         let mut found = false;
-                        for is2 in duplicate_combination(&mut is.clone(), &storage.rect_configuration.duplication_map, &BTreeSet::new()) {
+                        for is2 in duplicate_combination(&mut is.clone(), &storage.rect_configuration.duplication_map, &BTreeSet::new())? {
                             if found {
                                 break;
                             }
-                            for js2 in duplicate_combination(&mut js.clone(), &storage.rect_configuration.duplication_map, &is2) {
+                            for js2 in duplicate_combination(&mut js.clone(), &storage.rect_configuration.duplication_map, &is2)? {
                                 if found {
                                     break;
                                 }
                                 let union = &is2 | &js2;
                                 if union.len() >= is2.len() + js2.len() {
-                                    for ks2 in duplicate_combination(&mut ks.clone(), &storage.rect_configuration.duplication_map, &union) {
-                                        if (&union | &ks2).len() >= storage.rect_configuration.available_blocks.len() {
+                                    for ks2 in duplicate_combination(&mut ks.clone(), &storage.rect_configuration.duplication_map, &union)? {
+                                        if (&union | &ks2).len() >= storage.rect_configuration.available_blocks.len() &&
+                                            storage.rect_configuration.allows_layers(&[&is2, &js2, &ks2]) {
                                             let mut solution = BTreeSet::new();
                                             solution.insert(is2.to_owned().clone());
                                             solution.insert(js2.to_owned().clone());
                                             solution.insert(ks2.to_owned().clone());
-                                            println!("Found {}", solution.iter().map(combination_to_string).join(" "));
+                                            // the unknown candidates are the ones, which are not in the solutions of step3
+                                            if [is, js, ks].iter().any(|c| !storage.solutions.contains(*c)) {
+                                                println!("Found {} (unproven, with an unknown layer)", solution.iter().map(combination_to_string).join(" "));
+                                                storage.unproven_solutions.insert(solution.clone());
+                                            } else {
+                                                println!("Found {}", solution.iter().map(combination_to_string).join(" "));
+                                            }
                                             storage.combined_solutions.insert(solution);
                                             found = true;
                                             break;
@@ -360,22 +546,29 @@ pub(crate) fn step4_calculate_matches(storage: &mut ProgramStorage) {
     }
 
     if let Some(path) = storage.settings.solutions_filepath {
-        fs::write(
+        write_result_file(
             path,
+            ResultStep::CombinedSolutions,
+            storage,
             storage.combined_solutions.iter()
-                .map(|l| l.iter().map(combination_to_string).join(" "))
-                .join("\n"),
-        ).expect("Unable to write file");
+                .sorted()
+                .map(|l| match storage.unproven_solutions.contains(l) {
+                    true => format!("{}{UNPROVEN_MARKER}", l.iter().map(combination_to_string).join(" ")),
+                    false => l.iter().map(combination_to_string).join(" "),
+                }),
+        )?;
     }
-    println!("CALCULATING COMBINED SOLUTIONS (3 layers)... DONE AFTER {} seconds, found {} combined solutions", start.elapsed().as_secs(), storage.combined_solutions.len());
+    println!("CALCULATING COMBINED SOLUTIONS (3 layers)... DONE AFTER {} seconds, found {} combined solutions, {} of them unproven",
+             start.elapsed().as_secs(), storage.combined_solutions.len(), storage.unproven_solutions.len());
+    Ok(())
 }
 
 /// take the possible solutions for three layers and split them in single layer combinations\
 /// sort by how often each combination appears within the possible solutions
-pub(crate) fn step5_sort_final_combinations(storage: &mut ProgramStorage) {
+pub(crate) fn step5_sort_final_combinations(storage: &mut ProgramStorage) -> PuzzleResult<()> {
     if !storage.settings.steps[3] {
         println!("SORTING FINAL COMBINATIONS...\nSKIPPED");
-        return;
+        return Ok(());
     }
     let mut combination_counter_map = HashMap::new();
     let mut dedup_string_combination_map = HashMap::new();
@@ -383,23 +576,30 @@ pub(crate) fn step5_sort_final_combinations(storage: &mut ProgramStorage) {
 
     println!("SORTING FINAL COMBINATIONS with {} solutions...", storage.combined_solutions.len());
 
-    storage.combined_solutions.iter().for_each(|solution| solution.iter().for_each(|c| {
+    // in a fixed order, so the same combination represents its duplicates in every run
+    storage.combined_solutions.iter().sorted().for_each(|solution| solution.iter().for_each(|c| {
         let dedup = get_unique_combination_key(c);
         dedup_string_combination_map.entry(dedup.clone()).or_insert(c);
         *combination_counter_map.entry(dedup).or_insert(0) += 1;
     }));
     let final_combinations: Vec<Combination> = combination_counter_map.keys()
-        .sorted_by_key(|s| combination_counter_map.get(*s).unwrap())
         .map(|s| (*dedup_string_combination_map.get(s).unwrap()).clone())
+        // the most frequent last, with the same count the biggest area last
+        .sorted_by_cached_key(|c| (combination_counter_map[&get_unique_combination_key(c)], Reverse(combination_order_key(c, storage.settings.tie_break_seed))))
         .collect();
 
     if let Some(path) = storage.settings.final_combinations_path {
-        fs::write(path,
-                  final_combinations.iter().map(|c| c.iter().map(|r| r.id).join(" ")).join("\n"),
-        ).expect("Unable to write file");
+        write_result_file(
+            path,
+            ResultStep::FinalCombinations,
+            storage,
+            final_combinations.iter().map(|c| c.iter().map(|r| r.id).join(" ")),
+        )?;
     }
     storage.final_combinations = final_combinations;
+    println!("Pieces in combined solutions: {}", count_pieces(storage.combined_solutions.iter().flatten()).iter().map(|(id, count)| format!("{id}: {count}")).join(", "));
     println!("CALCULATING FINAL COMBINATIONS... DONE AFTER {} seconds, found {} final combinations", start.elapsed().as_secs(), storage.final_combinations.len());
+    Ok(())
 }
 
 #[test]
@@ -494,7 +694,7 @@ fn test_single_combination2() {
         if let Some(data) = step3_check_candidate(100, 0, &storage, &c) {
             println!("Solution is: {}", data.iter().map(|r| format!("{} {} {}", r.rect.id, r.rect.height, r.rect.width)).join("  "));
             println!("Area is: {}", data.iter().map(|r| r.rect.area).sum::<u32>());
-            draw_image("./out.png", &storage, &data);
+            draw_image(std::env::temp_dir().join("out.png").to_str().unwrap(), &storage, &data).unwrap();
         } else {
             println!("Not a solution!");
         }
@@ -570,10 +770,106 @@ fn test_single_permutation() {
         for id in s1.trim().split(',') {
             c.push(storage.rect_configuration.available_block_map.get(&id.trim().parse::<RecId>().unwrap()).unwrap());
         }
-        assert!(step3_check_permutation(&storage, c).is_none());
+        assert!(step3_check_permutation(&storage, c, &[]).is_none());
     }
 }
 
+#[test]
+fn test_blocked_zones() {
+    use crate::data_configuration::blocked_zone;
+    use crate::verify::verify_layout;
+
+    // L shaped container, the lower right corner is blocked
+    let rects = RectConfiguration::from_polygon(
+        &[(0, 0), (6, 0), (6, 2), (3, 2), (3, 4), (0, 4)],
+        vec![Rectangle::new(1, 2, 3), Rectangle::new(2, 2, 3), Rectangle::new(3, 2, 3)],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = rects.available_blocks.iter().copied().collect();
+    let layout = step3_check_candidate(0, 0, &storage, &candidate).unwrap();
+    assert!(verify_layout(&storage, &candidate, &layout).is_empty());
+
+    // the first rectangle has to go below the blocked zone
+    let rects = RectConfiguration::new(Rectangle::new(-1, 4, 6), vec![Rectangle::new(1, 2, 3), Rectangle::new(2, 2, 3)])
+        .with_blocked_zones(vec![blocked_zone(0, 0, 2, 3)]).unwrap();
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let layout = step3_check_permutation(&storage, rects.available_blocks.iter().collect(), &[]).unwrap();
+    assert_eq!(layout.iter().map(|p| (p.rect.id, p.x, p.y)).collect::<Vec<_>>(), vec![(1, 0, 2), (2, 3, 0)]);
+}
+
+#[test]
+fn test_pins() {
+    use crate::data_configuration::Pin;
+    use crate::verify::verify_layout;
+
+    let rects = || RectConfiguration::new(
+        Rectangle::new(-1, 4, 6),
+        vec![Rectangle::new(1, 2, 3), Rectangle::new(2, 2, 3), Rectangle::new(3, 2, 3), Rectangle::new(4, 2, 2)],
+    );
+    assert!(rects().with_pin(1, Pin { layer: Some(3), ..Default::default() }).is_err());
+    assert!(rects().with_pin(1, Pin { position: Some((4, 0)), rotated: Some(false), ..Default::default() }).is_err());
+    assert!(rects().with_pin(5, Pin::default()).is_err());
+
+    let rects = rects()
+        .with_pin(3, Pin { layer: Some(0), position: Some((3, 2)), rotated: Some(false) }).unwrap()
+        .with_pin(4, Pin { layer: Some(1), ..Default::default() }).unwrap();
+    assert_eq!(rects.duplicates_of(1).unwrap().iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(rects.duplicates_of(3).unwrap().iter().map(|r| r.id).collect::<Vec<_>>(), vec![3]);
+    let combination = |ids: &[RecId]| ids.iter().map(|id| rects.available_block_map[id]).collect::<Combination>();
+    assert!(!rects.allows_layer(&combination(&[3, 4])));
+    assert!(rects.allows_layers(&[&combination(&[1, 4]), &combination(&[3])]));
+    assert!(!rects.allows_layers(&[&combination(&[1, 4]), &combination(&[3, 4])]));
+
+    let mut storage = ProgramStorage::new(&rects, Settings::default());
+    // the pinned piece is placed where it is pinned, the others around it
+    let candidate = combination(&[1, 2, 3]);
+    let layout = step3_check_candidate(0, 0, &storage, &candidate).unwrap();
+    assert!(verify_layout(&storage, &candidate, &layout).is_empty());
+    assert!(layout.iter().any(|p| p.rect.id == 3 && (p.x, p.y) == (3, 2)));
+
+    // the pinned piece is not exchangeable with its duplicates
+    storage.gathered_combinations = [combination(&[1]), combination(&[2]), combination(&[3])].into_iter().collect();
+    step2_deduplication(&mut storage).unwrap();
+    assert_eq!(storage.deduplicated_combinations.len(), 2);
+}
+
+#[test]
+fn test_layer_constraints() {
+    use crate::data_configuration::{LayerConstraint, Pin};
+
+    let rects = || RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 1, 2), Rectangle::new(2, 1, 2), Rectangle::new(3, 1, 2), Rectangle::new(4, 1, 2), Rectangle::new(5, 2, 2)],
+    );
+    assert!(rects().with_layer_constraint(LayerConstraint::Apart(vec![1, 2, 3, 4])).is_err());
+    assert!(rects().with_layer_constraint(LayerConstraint::Together(vec![1, 6])).is_err());
+    assert!(rects().with_pin(5, Pin { layer: Some(0), ..Default::default() }).unwrap()
+        .with_layer_constraint(LayerConstraint::InLayer(vec![5], 1)).is_err());
+
+    let rects = rects()
+        .with_layer_constraint(LayerConstraint::Together(vec![1, 2])).unwrap()
+        .with_layer_constraint(LayerConstraint::Apart(vec![1, 3])).unwrap()
+        .with_layer_constraint(LayerConstraint::InLayer(vec![5], 2)).unwrap();
+    assert_eq!(rects.pin(5).and_then(|p| p.layer), Some(2));
+    assert_eq!(rects.duplicates_of(4).unwrap().iter().map(|r| r.id).collect::<Vec<_>>(), vec![4]);
+
+    let settings = Settings {
+        steps: [true, false, false, false],
+        min_rectangle_amount: 1,
+        max_rectangle_amount: 5,
+        ..Default::default()
+    };
+    let mut storage = ProgramStorage::new(&rects, settings);
+    step1_generate_candiates(&mut storage).unwrap();
+    let has = |c: &Combination, id: RecId| c.iter().any(|r| r.id == id);
+    assert!(storage.gathered_combinations.iter().all(|c| has(c, 1) == has(c, 2) && !(has(c, 1) && has(c, 3))));
+    assert!(storage.gathered_combinations.iter().any(|c| has(c, 1) && has(c, 2) && has(c, 4)));
+
+    let combination = |ids: &[RecId]| ids.iter().map(|id| rects.available_block_map[id]).collect::<Combination>();
+    assert!(rects.allows_layers(&[&combination(&[1, 2, 4]), &combination(&[3]), &combination(&[5])]));
+    assert!(!rects.allows_layers(&[&combination(&[1, 4]), &combination(&[2, 3]), &combination(&[5])]));
+}
+
 #[test]
 fn test_multiple_layers() {
     let start = Instant::now();
@@ -603,6 +899,9 @@ fn test_multiple_layers() {
         ],
     );
 
+    let path = |name: &str| -> &'static str {
+        Box::leak(std::env::temp_dir().join(format!("holzpuzzle_layers_{}_{name}.txt", std::process::id())).to_string_lossy().into_owned().into_boxed_str())
+    };
     let settings = Settings {
         thread_count: 14,
         steps: [false, false, true, true],
@@ -610,11 +909,11 @@ fn test_multiple_layers() {
         min_rectangle_amount: 5,
         max_rectangle_amount: 9,
         min_solution_area: 30,
-        candidates_path: Some("./tests/candidates.txt"),
-        deduplicated_combinations_path: Some("./tests/dedup_comb.txt"),
-        fitting_candidates_path: Some("./tests/fitting_cand.txt"),
-        solutions_filepath: Some("./tests/solutions.txt"),
-        final_combinations_path: Some("./tests/final_solutions.txt"),
+        candidates_path: Some(path("candidates")),
+        deduplicated_combinations_path: Some(path("dedup_comb")),
+        fitting_candidates_path: Some(path("fitting_cand")),
+        solutions_filepath: Some(path("solutions")),
+        final_combinations_path: Some(path("final_solutions")),
         ..Default::default()
     };
     let mut storage = ProgramStorage::new(&rects, settings);
@@ -622,11 +921,11 @@ fn test_multiple_layers() {
     println!("Using blocks:\n{}\n", storage.rect_configuration.available_blocks.iter().map(|b| format!("ID: {}, area: {}", b.id, b.area)).join("\n"));
     println!("Big rect area = {}\nSmall react area sum = {}\n", 3 * rects.big_rect.area, storage.rect_configuration.available_blocks.iter().map(|b| b.area).sum::<u32>());
 
-    step1_generate_candiates(&mut storage);
-    step2_deduplication(&mut storage);
-    step3_filter_fitting_candidates(&mut storage);
-    step4_calculate_matches(&mut storage);
-    step5_sort_final_combinations(&mut storage);
+    step1_generate_candiates(&mut storage).unwrap();
+    step2_deduplication(&mut storage).unwrap();
+    step3_filter_fitting_candidates(&mut storage).unwrap();
+    step4_calculate_matches(&mut storage).unwrap();
+    step5_sort_final_combinations(&mut storage).unwrap();
 
     println!("The whole run took us {} seconds!", start.elapsed().as_secs());
     println!("{}", storage.solutions.len());
@@ -634,7 +933,6 @@ fn test_multiple_layers() {
 
 #[test]
 fn test_multiple_layers2() {
-    let start = Instant::now();
     let rects = RectConfiguration::new(
         Rectangle::new(-1, 4, 8),
         vec![
@@ -670,7 +968,7 @@ fn test_multiple_layers2() {
         min_solution_area: 30,
         ..Default::default()
     };
-    let mut storage = ProgramStorage::new(&rects, settings);
+    let storage = ProgramStorage::new(&rects, settings);
 
     // check all permutations for solution
     let s = "1,2,4,6,7,11,14,18,19
@@ -686,7 +984,7 @@ fn test_multiple_layers2() {
         if let Some(data) = step3_check_candidate(100, 0, &storage, &c) {
             println!("Solution is: {}", data.iter().map(|r| format!("{} {} {}", r.rect.id, r.rect.height, r.rect.width)).join("  "));
             println!("Area is: {}", data.iter().map(|r| r.rect.area).sum::<u32>());
-            draw_image(&format!("./out{i}.png"), &storage, &data);
+            draw_image(std::env::temp_dir().join(format!("out{i}.png")).to_str().unwrap(), &storage, &data).unwrap();
         } else {
             println!("Not a solution!");
         }
@@ -731,7 +1029,7 @@ let rects = RectConfiguration::new(
         if let Some(data) = step3_check_candidate(100, 0, &storage, &c) {
             println!("Solution is: {}", data.iter().map(|r| format!("{} {} {}", r.rect.id, r.rect.height, r.rect.width)).join("  "));
             println!("Area is: {}", data.iter().map(|r| r.rect.area).sum::<u32>());
-            draw_image("./out.png", &storage, &data);
+            draw_image(std::env::temp_dir().join("out.png").to_str().unwrap(), &storage, &data).unwrap();
         } else {
             println!("Not a solution!");
         }
@@ -739,7 +1037,7 @@ let rects = RectConfiguration::new(
     let mut x: Vec<Combination> = redup_comb_iter(storage.solutions.iter().filter(
         |c| c.iter().map(|r| r.area).sum::<u32>() >= 300000
     )
-                                                      .clone(), &storage).collect();
+                                                      .clone(), &storage).collect::<PuzzleResult<_>>().unwrap();
     for i in 0..x.len() {
         let is = x.get(i).unwrap();
         println!("{}", is.iter().map(|r| r.id).join(","));
@@ -757,10 +1055,181 @@ let rects = RectConfiguration::new(
                 if let Some(data) = step3_check_candidate(0, 0, &storage, &c) {
                     println!("Solution is: {}", data.iter().map(|r| format!("{} {} {}", r.rect.id, r.rect.height, r.rect.width)).join("  "));
                     println!("Area is: {}", data.iter().map(|r| r.rect.area).sum::<u32>());
-                    draw_image("./out.png", &storage, &data);
+                    draw_image(std::env::temp_dir().join("out.png").to_str().unwrap(), &storage, &data).unwrap();
                 } else {
                     println!("Not a solution!");
                 }
             }
         }
     }*/
+
+#[test]
+fn test_contact_policy() {
+    use crate::rect::ContactPolicy;
+    use crate::verify::{LayoutProblem, verify_layout};
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 4, 8),
+        vec![Rectangle::new(1, 2, 3).not_rotatable(), Rectangle::new(2, 2, 3).not_rotatable()],
+    );
+    let candidate: Combination = [1, 2].iter().map(|id| rects.available_block_map[id]).collect();
+    let check = |policy: ContactPolicy, guillotine_only| {
+        let storage = ProgramStorage::new(&rects, Settings { contact_policy: policy, guillotine_only, ..Default::default() });
+        let layout = step3_check_candidate(0, 0, &storage, &candidate);
+        if let Some(layout) = &layout {
+            assert!(verify_layout(&storage, &candidate, layout).is_empty());
+        }
+        layout.is_some()
+    };
+    for guillotine_only in [false, true] {
+        assert!(check(ContactPolicy::default(), guillotine_only));
+        assert!(check(ContactPolicy { piece_clearance: 2, wall_clearance: 0 }, guillotine_only));
+        assert!(!check(ContactPolicy { piece_clearance: 3, wall_clearance: 0 }, guillotine_only));
+        assert!(check(ContactPolicy { piece_clearance: 0, wall_clearance: 1 }, guillotine_only));
+        assert!(!check(ContactPolicy { piece_clearance: 1, wall_clearance: 1 }, guillotine_only));
+    }
+
+    let storage = ProgramStorage::new(&rects, Settings { contact_policy: ContactPolicy { piece_clearance: 1, wall_clearance: 1 }, ..Default::default() });
+    let placed = |id: RecId, x, y| PlacedRectangle { rect: rects.available_block_map[&id], x, y };
+    assert_eq!(verify_layout(&storage, &candidate, &[placed(1, 1, 1), placed(2, 4, 1)]), vec![LayoutProblem::TooClose(1, 2)]);
+    assert_eq!(verify_layout(&storage, &candidate, &[placed(1, 0, 1), placed(2, 4, 1)]), vec![LayoutProblem::TooCloseToWall(1)]);
+    assert_eq!(verify_layout(&storage, &candidate, &[placed(1, 1, 0), placed(2, 6, 1)]), vec![LayoutProblem::TooCloseToWall(1), LayoutProblem::OutOfBounds(2)]);
+}
+
+#[test]
+fn test_deterministic_order() {
+    use std::fs;
+    use crate::rect::combination_order_key;
+
+    // many equivalent pieces, so the hash sets decide a lot, if the order is not fixed
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![
+            Rectangle::new(1, 1, 2), Rectangle::new(2, 1, 2), Rectangle::new(3, 1, 2), Rectangle::new(4, 1, 2),
+            Rectangle::new(5, 2, 2), Rectangle::new(6, 2, 2), Rectangle::new(7, 2, 4),
+        ],
+    );
+    let path = |run: usize, name: &str| -> &'static str {
+        Box::leak(std::env::temp_dir().join(format!("holzpuzzle_order_{}_{run}_{name}.txt", std::process::id())).to_string_lossy().into_owned().into_boxed_str())
+    };
+    let names = ["candidates", "dedup", "fitting", "solutions", "final"];
+    // the bytes of all written result files, not only the stored sets, have to be the same in each run
+    let run = |run: usize| {
+        let settings = Settings {
+            steps: [true, true, true, true],
+            thread_count: 3,
+            candidates_path: Some(path(run, names[0])),
+            deduplicated_combinations_path: Some(path(run, names[1])),
+            fitting_candidates_path: Some(path(run, names[2])),
+            solutions_filepath: Some(path(run, names[3])),
+            final_combinations_path: Some(path(run, names[4])),
+            ..Default::default()
+        };
+        let mut storage = ProgramStorage::new(&rects, settings);
+        step1_generate_candiates(&mut storage).unwrap();
+        step2_deduplication(&mut storage).unwrap();
+        step3_filter_fitting_candidates(&mut storage).unwrap();
+        step4_calculate_matches(&mut storage).unwrap();
+        step5_sort_final_combinations(&mut storage).unwrap();
+        assert!(!storage.combined_solutions.is_empty());
+        names.map(|name| {
+            let bytes = fs::read(path(run, name)).unwrap();
+            let _ = fs::remove_file(path(run, name));
+            bytes
+        })
+    };
+    let first = run(0);
+    for i in 1..4 {
+        assert!(run(i) == first, "the result files of run {i} differ from the first run");
+    }
+
+    // the seed only changes the order of ties
+    let combinations = rects.available_blocks.iter().combinations(2).map(|c| c.into_iter().copied().collect::<Combination>()).collect::<Vec<_>>();
+    let order = |seed: u64| combinations.iter().sorted_by_cached_key(|c| combination_order_key(c, seed)).collect::<Vec<_>>();
+    assert!((1..10).any(|seed| order(seed) != order(0)));
+    assert!(order(0).windows(2).all(|w| w[0].iter().map(|r| r.area).sum::<u32>() >= w[1].iter().map(|r| r.area).sum::<u32>()));
+}
+
+#[test]
+fn test_undecided_candidates() {
+    use crate::budget::CandidateBudget;
+    use crate::error::PuzzleError;
+    use crate::layer_solver::ExactSolver;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 4, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 2, 2), Rectangle::new(3, 2, 4), Rectangle::new(4, 1, 3)],
+    );
+    let path = |name: &str| -> &'static str {
+        Box::leak(std::env::temp_dir().join(format!("holzpuzzle_test_{}_{name}.txt", std::process::id())).to_string_lossy().into_owned().into_boxed_str())
+    };
+    let settings = Settings {
+        steps: [true, true, false, false],
+        // one order is enough for single pieces, but not for all of the bigger candidates
+        candidate_budget: CandidateBudget { max_permutations: Some(1), ..Default::default() },
+        fitting_candidates_path: Some(path("fitting")),
+        undecided_candidates_path: Some(path("undecided")),
+        ..Default::default()
+    };
+    let mut storage = ProgramStorage::new(&rects, settings.clone());
+    step1_generate_candiates(&mut storage).unwrap();
+    step2_deduplication(&mut storage).unwrap();
+    step3_filter_fitting_candidates(&mut storage).unwrap();
+    let undecided = storage.undecided_candidates.lock().unwrap().clone();
+    assert!(!undecided.is_empty());
+    assert!(undecided.iter().all(|c| !storage.solutions.contains(c)));
+    assert_eq!(combination_storage_from_file(path("undecided"), ResultStep::UndecidedCandidates, &storage).unwrap(), undecided);
+
+    // the files of another budget are only accepted for retrying
+    let retry_storage = ProgramStorage::new(&rects, Settings { candidate_budget: CandidateBudget::default(), ..settings });
+    assert!(matches!(combination_storage_from_file(path("undecided"), ResultStep::UndecidedCandidates, &retry_storage), Err(PuzzleError::ResultFileMismatch { .. })));
+    // the exact solver decides all of them
+    step3_retry_undecided(&retry_storage, &ExactSolver { max_nodes: 100_000 }).unwrap();
+    assert!(combination_storage_from_file(path("undecided"), ResultStep::UndecidedCandidates, &retry_storage).unwrap().is_empty());
+    let fitting = combination_storage_from_file(path("fitting"), ResultStep::FittingCandidates, &retry_storage).unwrap();
+    assert!(fitting.len() > storage.solutions.len() && storage.solutions.is_subset(&fitting));
+    let _ = std::fs::remove_file(path("fitting"));
+    let _ = std::fs::remove_file(path("undecided"));
+}
+
+#[test]
+fn test_unknown_layers() {
+    // the heuristic does not find the pinwheel of the first four pieces around the small one
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 5, 5),
+        vec![
+            Rectangle::new(1, 2, 3), Rectangle::new(2, 2, 3), Rectangle::new(3, 2, 3), Rectangle::new(4, 2, 3), Rectangle::new(5, 1, 1),
+            Rectangle::new(6, 5, 5), Rectangle::new(7, 4, 5), Rectangle::new(8, 1, 5),
+        ],
+    );
+    let path = std::env::temp_dir().join(format!("holzpuzzle_test_{}_unknown_solutions.txt", std::process::id()));
+    let path: &'static str = Box::leak(path.to_string_lossy().into_owned().into_boxed_str());
+    let run = |unknown_layers_as_possible: bool| {
+        let settings = Settings { steps: [true, true, true, false], unknown_layers_as_possible, solutions_filepath: Some(path), ..Default::default() };
+        let mut storage = ProgramStorage::new(&rects, settings);
+        step1_generate_candiates(&mut storage).unwrap();
+        step2_deduplication(&mut storage).unwrap();
+        step3_filter_fitting_candidates(&mut storage).unwrap();
+        step4_calculate_matches(&mut storage).unwrap();
+        storage
+    };
+    let pinwheel = [1, 2, 3, 4, 5].iter().map(|id| rects.available_block_map[id]).collect::<Combination>();
+    let mut storage = run(false);
+    assert!(!storage.solutions.contains(&pinwheel));
+    assert!(storage.unknown_candidates.get_mut().unwrap().contains(&pinwheel));
+    assert!(storage.combined_solutions.is_empty());
+
+    let storage = run(true);
+    assert_eq!(storage.combined_solutions.len(), 1);
+    assert!(storage.combined_solutions.iter().all(|s| s.contains(&pinwheel)));
+    // the solution is marked, also after loading it again
+    assert_eq!(storage.unproven_solutions, storage.combined_solutions);
+    assert!(std::fs::read_to_string(path).unwrap().ends_with(UNPROVEN_MARKER));
+    let mut loaded = ProgramStorage::new(&rects, Settings { steps: [false, false, false, false], ..storage.settings.clone() });
+    step4_calculate_matches(&mut loaded).unwrap();
+    assert_eq!((&loaded.combined_solutions, &loaded.unproven_solutions), (&storage.combined_solutions, &storage.unproven_solutions));
+    // the file of unproven layers is not taken as a strict result
+    let mut strict = ProgramStorage::new(&rects, Settings { steps: [false, false, false, false], unknown_layers_as_possible: false, ..storage.settings.clone() });
+    assert!(step4_calculate_matches(&mut strict).is_err());
+    let _ = std::fs::remove_file(path);
+}
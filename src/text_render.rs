@@ -0,0 +1,125 @@
+use crate::data_configuration::RectConfiguration;
+use crate::rect::{PlacedRectangle, RecDimension};
+
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
+/// box drawing character for a point, which is connected in the given directions
+fn box_char(connections: u8) -> char {
+    match connections {
+        0 => ' ',
+        c if c == LEFT | RIGHT || c == LEFT || c == RIGHT => '─',
+        c if c == UP | DOWN || c == UP || c == DOWN => '│',
+        c if c == DOWN | RIGHT => '┌',
+        c if c == DOWN | LEFT => '┐',
+        c if c == UP | RIGHT => '└',
+        c if c == UP | LEFT => '┘',
+        c if c == UP | DOWN | RIGHT => '├',
+        c if c == UP | DOWN | LEFT => '┤',
+        c if c == LEFT | RIGHT | DOWN => '┬',
+        c if c == LEFT | RIGHT | UP => '┴',
+        _ => '┼',
+    }
+}
+
+/// draw a layout inside the big rect with box drawing characters, at most `width` characters wide\
+/// y grows downwards, like in the images of `draw_image`\
+/// rectangles are labelled with their id (and an arrow if rotated), space which is not covered by any rectangle is shaded, blocked zones are filled
+pub(crate) fn render_layout(configuration: &RectConfiguration, data: &[PlacedRectangle], width: usize) -> String {
+    let big_rect = configuration.big_rect;
+    // terminal characters are about twice as high as wide
+    let columns = width.max(3) - 1;
+    let rows = ((columns as f64 * big_rect.height as f64 / big_rect.width as f64 / 2.0).round() as usize).max(2);
+    let to_column = |x: RecDimension| ((x as f64 * columns as f64 / big_rect.width as f64).round() as usize).min(columns);
+    let to_row = |y: RecDimension| ((y as f64 * rows as f64 / big_rect.height as f64).round() as usize).min(rows);
+
+    let mut connections = vec![vec![0u8; columns + 1]; rows + 1];
+    let mut outline = |x0: usize, y0: usize, x1: usize, y1: usize| {
+        for row in [y0, y1] {
+            for column in x0..x1 {
+                connections[row][column] |= RIGHT;
+                connections[row][column + 1] |= LEFT;
+            }
+        }
+        for column in [x0, x1] {
+            for row in y0..y1 {
+                connections[row][column] |= DOWN;
+                connections[row + 1][column] |= UP;
+            }
+        }
+    };
+    outline(0, 0, columns, rows);
+    let boxes = data.iter()
+        .map(|p| (p, to_column(p.x), to_row(p.y), to_column(p.x + p.rect.width), to_row(p.y + p.rect.height)))
+        .collect::<Vec<_>>();
+    for (_, x0, y0, x1, y1) in &boxes {
+        outline(*x0, *y0, *x1, *y1);
+    }
+    for z in &configuration.blocked_zones {
+        outline(to_column(z.x), to_row(z.y), to_column(z.x + z.rect.width), to_row(z.y + z.rect.height));
+    }
+
+    let mut grid: Vec<Vec<char>> = connections.iter().map(|row| row.iter().map(|c| box_char(*c)).collect()).collect();
+    // shade leftover space, the center of a character decides whether it is covered
+    for (row, line) in grid.iter_mut().enumerate().take(rows).skip(1) {
+        for (column, c) in line.iter_mut().enumerate().take(columns).skip(1) {
+            let x = column as f64 * big_rect.width as f64 / columns as f64;
+            let y = row as f64 * big_rect.height as f64 / rows as f64;
+            let inside = |p: &PlacedRectangle|
+                p.x as f64 <= x && x <= (p.x + p.rect.width) as f64 && p.y as f64 <= y && y <= (p.y + p.rect.height) as f64;
+            if *c != ' ' {
+                continue;
+            }
+            if configuration.blocked_zones.iter().any(inside) {
+                *c = '▓';
+            } else if !data.iter().any(inside) {
+                *c = '░';
+            }
+        }
+    }
+    // put the id in the middle of each rectangle, if there is room for it
+    for (p, x0, y0, x1, y1) in &boxes {
+        let label = p.rect.label().chars().collect::<Vec<char>>();
+        if y1 - y0 < 2 || x1 - x0 <= label.len() {
+            continue;
+        }
+        let row = (y0 + y1) / 2;
+        let start = (x0 + x1 + 1 - label.len()) / 2;
+        for (i, c) in label.iter().enumerate() {
+            grid[row][start + i] = *c;
+        }
+    }
+    grid.iter().map(|row| row.iter().collect::<String>()).collect::<Vec<String>>().join("\n")
+}
+
+#[test]
+fn test_render_layout() {
+    use crate::data_configuration::blocked_zone;
+    use crate::rect::Rectangle;
+
+    let configuration = RectConfiguration::new(Rectangle::new(-1, 4, 8), vec![]);
+    let data = vec![
+        PlacedRectangle { rect: Rectangle::new(1, 4, 4), x: 0, y: 0 },
+        PlacedRectangle { rect: Rectangle::new(2, 2, 2), x: 4, y: 0 },
+    ];
+    let expected = "\
+┌─────────┬────┬────┐
+│         │ 2  │░░░░│
+│    1    │    │░░░░│
+│         ├────┘░░░░│
+│         │░░░░░░░░░│
+└─────────┴─────────┘";
+    assert_eq!(render_layout(&configuration, &data, 21), expected);
+
+    let configuration = configuration.with_blocked_zones(vec![blocked_zone(6, 2, 2, 2)]).unwrap();
+    let expected = "\
+┌─────────┬────┬────┐
+│         │ 2  │░░░░│
+│    1    │    │░░░░│
+│         ├────┼────┤
+│         │░░░░│▓▓▓▓│
+└─────────┴────┴────┘";
+    assert_eq!(render_layout(&configuration, &data, 21), expected);
+}
@@ -0,0 +1,144 @@
+use std::fs::{self, File};
+use std::path::Path;
+
+use image::{Delay, Frame, Rgb, RgbImage};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::buffer::ConvertBuffer;
+use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut, draw_text_mut};
+use imageproc::rect::Rect;
+use rusttype::{Font, Scale};
+
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::ProgramStorage;
+use crate::rect::{PlacedRectangle, RecDimension, RecId};
+
+/// default for the longer side of a rendered frame in pixels, without the margin
+const FRAME_SIZE: f64 = 600.0;
+const MARGIN: i32 = 10;
+const CAPTION_HEIGHT: u32 = 30;
+/// how long each frame of the gif is shown
+const FRAME_DELAY_MS: u32 = 700;
+
+/// one intermediate state of `step3_check_permutation`
+#[derive(Debug, Clone)]
+pub(crate) struct TraceFrame {
+    pub label: String,
+    pub layout: Vec<PlacedRectangle>,
+    /// the rectangle, which was just placed or moved
+    pub moved: Option<RecId>,
+}
+
+/// all intermediate states of placing one order of rectangles: the shelf placement, each compaction move and the bounds check
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PlacementTrace {
+    pub frames: Vec<TraceFrame>,
+}
+
+impl PlacementTrace {
+    pub(crate) fn record(&mut self, label: String, layout: &[PlacedRectangle], moved: Option<RecId>) {
+        self.frames.push(TraceFrame { label, layout: layout.to_vec(), moved });
+    }
+
+    /// write the frames as animated gif, if the path ends with ".gif", otherwise as numbered png files into this directory
+    pub(crate) fn save(&self, storage: &ProgramStorage, path: &str) -> PuzzleResult<()> {
+        self.save_sized(storage, path, FRAME_SIZE)
+    }
+
+    /// like `save` with `frame_size` pixels for the longer side of each frame\
+    /// the frames are rendered one after another while writing, so only one of them is in memory
+    pub(crate) fn save_sized(&self, storage: &ProgramStorage, path: &str, frame_size: f64) -> PuzzleResult<()> {
+        let render_error = |e: image::ImageError| PuzzleError::Render { path: path.to_owned(), reason: e.to_string() };
+        let images = self.render(storage, path, frame_size)?;
+        if path.ends_with(".gif") {
+            let file = File::create(path).map_err(|e| PuzzleError::io(path, e))?;
+            let mut encoder = GifEncoder::new(file);
+            encoder.set_repeat(Repeat::Infinite).map_err(render_error)?;
+            return encoder.encode_frames(images
+                .map(|image| Frame::from_parts(image.convert(), 0, 0, Delay::from_numer_denom_ms(FRAME_DELAY_MS, 1))))
+                .map_err(render_error);
+        }
+        fs::create_dir_all(path).map_err(|e| PuzzleError::io(path, e))?;
+        for (i, image) in images.enumerate() {
+            let frame_path = Path::new(path).join(format!("frame_{:04}.png", i + 1));
+            image.save(&frame_path).map_err(|e| PuzzleError::Render { path: frame_path.display().to_string(), reason: e.to_string() })?;
+        }
+        Ok(())
+    }
+
+    /// draw each frame at the same scale, big enough for the rectangles outside of the big rect
+    fn render<'s>(&'s self, storage: &'s ProgramStorage, path: &str, frame_size: f64) -> PuzzleResult<impl Iterator<Item=RgbImage> + 's> {
+        let big_rect = storage.rect_configuration.big_rect;
+        let placed = self.frames.iter().flat_map(|f| &f.layout);
+        let width = placed.clone().map(|p| p.x + p.rect.width).fold(big_rect.width, RecDimension::max);
+        let height = placed.map(|p| p.y + p.rect.height).fold(big_rect.height, RecDimension::max);
+        let scale = frame_size / width.max(height).max(1) as f64;
+        let pixels = move |v: RecDimension| (v as f64 * scale).round() as i32;
+        let area = move |p: &PlacedRectangle| Rect::at(pixels(p.x) + MARGIN, pixels(p.y) + MARGIN)
+            .of_size(pixels(p.rect.width).max(1) as u32, pixels(p.rect.height).max(1) as u32);
+        let font = Font::try_from_vec(Vec::from(include_bytes!("../DejaVuSans.ttf") as &[u8]))
+            .ok_or_else(|| PuzzleError::Render { path: path.to_owned(), reason: "the embedded font is broken".to_owned() })?;
+        let text_scale = Scale::uniform(16.0);
+        let black = Rgb([0u8, 0u8, 0u8]);
+
+        Ok(self.frames.iter().map(move |frame| {
+            let size = |v: RecDimension| (pixels(v) + 2 * MARGIN) as u32;
+            let mut image = RgbImage::from_pixel(size(width), size(height) + CAPTION_HEIGHT, Rgb([255u8, 255u8, 255u8]));
+            let container = PlacedRectangle { rect: big_rect, x: 0, y: 0 };
+            draw_filled_rect_mut(&mut image, area(&container), Rgb([220u8, 230u8, 255u8]));
+            draw_hollow_rect_mut(&mut image, area(&container), Rgb([0u8, 0u8, 255u8]));
+            for zone in &storage.rect_configuration.blocked_zones {
+                draw_filled_rect_mut(&mut image, area(zone), Rgb([96u8, 96u8, 96u8]));
+            }
+            for p in &frame.layout {
+                let fill = match frame.moved == Some(p.rect.id) {
+                    true => Rgb([255u8, 170u8, 60u8]),
+                    false => Rgb([250u8, 240u8, 200u8]),
+                };
+                draw_filled_rect_mut(&mut image, area(p), fill);
+                let outline = match p.check_bounds(storage) {
+                    true => black,
+                    false => Rgb([220u8, 0u8, 0u8]),
+                };
+                draw_hollow_rect_mut(&mut image, area(p), outline);
+                let r = area(p);
+                draw_text_mut(&mut image, black, r.left() + r.width() as i32 / 2 - 6, r.top() + r.height() as i32 / 2 - 8, text_scale, &font, &p.rect.label());
+            }
+            draw_text_mut(&mut image, black, MARGIN, size(height) as i32 + 4, text_scale, &font, &frame.label);
+            image
+        }))
+    }
+}
+
+#[test]
+fn test_placement_trace() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::rect::Rectangle;
+    use crate::budget::BudgetTracker;
+    use crate::steps::{step3_check_permutation, step3_check_permutation_traced};
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 4, 6),
+        vec![Rectangle::new(1, 4, 2), Rectangle::new(2, 2, 4), Rectangle::new(3, 2, 4)],
+    );
+    let storage = ProgramStorage::new(&rects, Settings { distance_between_rectangles: 1, ..Default::default() });
+    let order = [2, 3, 1].iter().map(|id| &rects.available_block_map[id]).collect::<Vec<_>>();
+    let mut trace = PlacementTrace::default();
+    let layout = step3_check_permutation_traced(&storage, order.clone(), &[], &BudgetTracker::unlimited(), Some(&mut trace));
+    // tracing does not change the result
+    assert_eq!(format!("{layout:?}"), format!("{:?}", step3_check_permutation(&storage, order, &[])));
+    let labels = trace.frames.iter().map(|f| f.label.as_str()).collect::<Vec<_>>();
+    assert!(labels[..3].iter().all(|l| l.starts_with("place ")), "{labels:?}");
+    assert!(labels.iter().any(|l| l.starts_with("compact ")), "{labels:?}");
+    assert_eq!(labels.last(), Some(&"bounds check: all inside"));
+    assert_eq!(trace.frames.last().unwrap().layout.len(), 3);
+
+    let directory = std::env::temp_dir().join(format!("holzpuzzle_test_{}_frames", std::process::id()));
+    // small frames, encoding full sized ones into the gif takes half a minute in a debug build
+    trace.save_sized(&storage, directory.to_str().unwrap(), 60.0).unwrap();
+    assert_eq!(fs::read_dir(&directory).unwrap().count(), trace.frames.len());
+    let gif = directory.join("trace.gif");
+    trace.save_sized(&storage, gif.to_str().unwrap(), 60.0).unwrap();
+    assert!(fs::read(&gif).unwrap().starts_with(b"GIF89a"));
+    let _ = fs::remove_dir_all(directory);
+}
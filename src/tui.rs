@@ -0,0 +1,429 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+use itertools::Itertools;
+
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::ProgramStorage;
+use crate::rect::{PlacedRectangle, placement_from_string, placement_to_string, RecDimension, Rectangle};
+use crate::verify::find_problems;
+
+/// a key press, as far as the editor cares about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Tab,
+    Char(char),
+    Other,
+}
+
+/// interactive editor for a single layer\
+/// y grows downwards, like in the images of `draw_image`
+pub(crate) struct LayoutEditor<'a> {
+    storage: &'a ProgramStorage<'a>,
+    pub placed: Vec<PlacedRectangle>,
+    /// index into `placed`
+    selected: Option<usize>,
+    /// index into the list of unplaced rectangles
+    palette: usize,
+    history: Vec<Vec<PlacedRectangle>>,
+    /// units per character cell
+    scale: RecDimension,
+    /// units per arrow key press
+    step: RecDimension,
+    /// the last arrow key, snapping moves in this direction
+    direction: Key,
+    path: String,
+    pub message: String,
+}
+
+impl<'a> LayoutEditor<'a> {
+    pub(crate) fn new(storage: &'a ProgramStorage<'a>, path: &str, scale: Option<RecDimension>) -> LayoutEditor<'a> {
+        let big_rect = storage.rect_configuration.big_rect;
+        // by default, show the big rect about 60 cells wide
+        let scale = scale.unwrap_or_else(|| big_rect.width.div_ceil(60)).max(1);
+        LayoutEditor {
+            storage,
+            placed: vec![],
+            selected: None,
+            palette: 0,
+            history: vec![],
+            scale,
+            step: scale,
+            direction: Key::Left,
+            path: path.to_owned(),
+            message: String::new(),
+        }
+    }
+
+    /// all rectangles, which are not placed yet
+    fn unplaced(&self) -> Vec<Rectangle> {
+        self.storage.rect_configuration.available_blocks.iter()
+            .filter(|r| !self.placed.iter().any(|p| p.rect.id == r.id))
+            .copied()
+            .sorted_by_key(|r| r.id)
+            .collect()
+    }
+
+    /// all collisions and rectangles out of bounds
+    pub(crate) fn problems(&self) -> Vec<String> {
+        find_problems(self.storage, &self.placed).iter().map(|p| p.to_string()).collect()
+    }
+
+    fn is_problematic(&self, p: &PlacedRectangle) -> bool {
+        let clearance = self.storage.settings.contact_policy.piece_clearance;
+        !p.check_bounds(self.storage) || self.placed.iter().any(|o| o.check_clearance(p, clearance))
+    }
+
+    /// remember the current layout for undo
+    fn checkpoint(&mut self) {
+        self.history.push(self.placed.clone());
+    }
+
+    fn with_selected<F: FnOnce(&mut PlacedRectangle, &[PlacedRectangle])>(&mut self, f: F) {
+        let Some(i) = self.selected else {
+            self.message = "nothing selected, add a rectangle with 'a' or select one with Tab".to_owned();
+            return;
+        };
+        self.checkpoint();
+        let others = self.placed.clone();
+        f(&mut self.placed[i], &others);
+    }
+
+    /// handle a key press, returns false if the editor should be closed
+    pub(crate) fn handle_key(&mut self, key: Key) -> bool {
+        self.message.clear();
+        let storage = self.storage;
+        let big_rect = storage.rect_configuration.big_rect;
+        let step = self.step;
+        match key {
+            Key::Char('q') => return false,
+            Key::Tab if !self.placed.is_empty() => {
+                self.selected = Some(self.selected.map_or(0, |i| (i + 1) % self.placed.len()));
+            }
+            Key::Char(']') => self.palette = self.palette.saturating_add(1),
+            Key::Char('[') => self.palette = self.palette.saturating_sub(1),
+            Key::Char('a') => {
+                let unplaced = self.unplaced();
+                if let Some(rect) = unplaced.get(self.palette.min(unplaced.len().saturating_sub(1))) {
+                    self.checkpoint();
+                    self.placed.push(PlacedRectangle { rect: *rect, x: 0, y: 0 });
+                    self.selected = Some(self.placed.len() - 1);
+                } else {
+                    self.message = "all rectangles are placed".to_owned();
+                }
+            }
+            Key::Char('x') => {
+                if let Some(i) = self.selected {
+                    self.checkpoint();
+                    self.placed.remove(i);
+                    self.selected = None;
+                }
+            }
+            Key::Left | Key::Right | Key::Up | Key::Down => {
+                self.direction = key;
+                self.with_selected(|p, _| match key {
+                    Key::Left => p.x = p.x.saturating_sub(step),
+                    Key::Right => p.x = (p.x + step).min(big_rect.width),
+                    Key::Up => p.y = p.y.saturating_sub(step),
+                    _ => p.y = (p.y + step).min(big_rect.height),
+                });
+            }
+            Key::Char('r') => match self.selected.map(|i| self.placed[i].rect) {
+                Some(rect) if !rect.rotatable => self.message = format!("{} has to keep its orientation", rect.id),
+                _ => self.with_selected(|p, _| p.rect = p.rect.rotate()),
+            },
+            Key::Char('s') => {
+                let direction = self.direction;
+                self.with_selected(|p, others| *p = snapped(storage, p, others, direction));
+            }
+            Key::Char('+') => self.step = self.step.saturating_mul(10),
+            Key::Char('-') => self.step = (self.step / 10).max(1),
+            Key::Char('u') => match self.history.pop() {
+                Some(placed) => {
+                    self.placed = placed;
+                    self.selected = self.selected.filter(|i| *i < self.placed.len());
+                }
+                None => self.message = "nothing to undo".to_owned(),
+            },
+            Key::Char('w') => self.message = match self.save() {
+                Ok(()) => format!("saved to {}", self.path),
+                Err(e) => e.to_string(),
+            },
+            Key::Char('o') => self.message = match self.load() {
+                Ok(()) => format!("loaded {}", self.path),
+                Err(e) => e.to_string(),
+            },
+            _ => {}
+        }
+        true
+    }
+
+    /// store the layout in the solution format
+    pub(crate) fn save(&self) -> PuzzleResult<()> {
+        fs::write(&self.path, placement_to_string(&self.storage.rect_configuration.resolution, &self.placed) + "\n").map_err(|e| PuzzleError::io(&self.path, e))
+    }
+
+    /// load the layout from the solution format
+    pub(crate) fn load(&mut self) -> PuzzleResult<()> {
+        let content = fs::read_to_string(&self.path).map_err(|e| PuzzleError::io(&self.path, e))?;
+        let line = content.split('\n').find(|l| !l.trim().is_empty() && !l.starts_with('#')).unwrap_or("");
+        let placed = placement_from_string(self.storage, line)?;
+        self.checkpoint();
+        self.placed = placed;
+        self.selected = None;
+        Ok(())
+    }
+
+    /// draw the big rect and all placed rectangles, `colors` enables ANSI colors
+    pub(crate) fn render(&self, colors: bool) -> String {
+        let big_rect = self.storage.rect_configuration.big_rect;
+        let scale = self.scale;
+        // show rectangles, which stick out of the big rect, too
+        let width = self.placed.iter().map(|p| p.x + p.rect.width).fold(big_rect.width, RecDimension::max);
+        let height = self.placed.iter().map(|p| p.y + p.rect.height).fold(big_rect.height, RecDimension::max);
+        let mut out = String::new();
+        for row in 0..height.div_ceil(scale) {
+            for column in 0..width.div_ceil(scale) {
+                // the center of the cell decides what is shown
+                let (x, y) = (column * scale + scale / 2, row * scale + scale / 2);
+                let covering = self.placed.iter().enumerate()
+                    .filter(|(_, p)| p.x <= x && x < p.x + p.rect.width && p.y <= y && y < p.y + p.rect.height)
+                    .collect::<Vec<_>>();
+                let inside = x < big_rect.width && y < big_rect.height;
+                let blocked = self.storage.rect_configuration.blocked_zones.iter()
+                    .any(|z| z.x <= x && x < z.x + z.rect.width && z.y <= y && y < z.y + z.rect.height);
+                let (text, color) = match covering.as_slice() {
+                    [] if blocked => ("##".to_owned(), ""),
+                    [] if inside => (" .".to_owned(), ""),
+                    [] => ("  ".to_owned(), ""),
+                    [(i, p)] => (
+                        format!("{:>2}", p.rect.id),
+                        if self.is_problematic(p) { "\x1b[41m" } else if self.selected == Some(*i) { "\x1b[7m" } else { "\x1b[44m" },
+                    ),
+                    _ => (" X".to_owned(), "\x1b[41m"),
+                };
+                if colors && !color.is_empty() {
+                    out += &format!("{color}{text}\x1b[0m");
+                } else {
+                    out += &text;
+                }
+            }
+            out += "\n";
+        }
+        let placed_area = self.placed.iter().map(|p| p.rect.area).sum::<u32>();
+        let usable_area = self.storage.rect_configuration.usable_area();
+        let resolution = &self.storage.rect_configuration.resolution;
+        let f = |v: RecDimension| resolution.format(v);
+        out += &format!("\nplaced area {} of {} ({:.1} %), step {}, 1 cell = {} {}\n",
+                        resolution.format_area(placed_area), resolution.format_area(usable_area),
+                        100.0 * placed_area as f64 / usable_area as f64, f(self.step), f(scale), resolution.unit);
+        if let Some(p) = self.selected.and_then(|i| self.placed.get(i)) {
+            out += &format!("selected: {} ({}x{}{}) at {} {}\n", p.rect.id, f(p.rect.height), f(p.rect.width),
+                            if p.rect.rotated { ", rotated" } else { "" }, f(p.x), f(p.y));
+        }
+        let unplaced = self.unplaced();
+        let palette = self.palette.min(unplaced.len().saturating_sub(1));
+        out += &format!("unplaced: {}\n", unplaced.iter().enumerate()
+            .map(|(i, r)| if i == palette { format!("[{}]", r.id) } else { r.id.to_string() })
+            .join(" "));
+        for problem in self.problems() {
+            out += &format!("! {problem}\n");
+        }
+        out += &format!("{}\n", self.message);
+        out += "arrows move  +/- step  r rotate  s snap  a add  x remove  [ ] pick  Tab select  u undo  w save  o load  q quit\n";
+        out
+    }
+}
+
+/// `p` moved in `direction` until it reaches the clearance to the nearest neighbour, blocked zone or wall in its way\
+/// pieces, which already overlap it, are not in the way
+fn snapped(storage: &ProgramStorage, p: &PlacedRectangle, others: &[PlacedRectangle], direction: Key) -> PlacedRectangle {
+    let policy = &storage.settings.contact_policy;
+    let big_rect = storage.rect_configuration.big_rect;
+    let horizontal = matches!(direction, Key::Left | Key::Right);
+    // (start, length) along the direction and across it
+    let span = |r: &PlacedRectangle| match horizontal {
+        true => ((r.x, r.rect.width), (r.y, r.rect.height)),
+        false => ((r.y, r.rect.height), (r.x, r.rect.width)),
+    };
+    let ((start, length), (cross, cross_length)) = span(p);
+    let size = if horizontal { big_rect.width } else { big_rect.height };
+    let obstacles = others.iter().filter(|o| o.rect.id != p.rect.id)
+        .chain(&storage.rect_configuration.blocked_zones)
+        .map(|o| (span(o), policy.clearance_to(o)))
+        .filter(|((_, (o_cross, o_cross_length)), clearance)| *o_cross < cross + cross_length + clearance && cross < o_cross + o_cross_length + clearance);
+    let position = match direction {
+        Key::Left | Key::Up => obstacles.map(|(((s, l), _), clearance)| s + l + clearance)
+            .chain([policy.wall_clearance])
+            .filter(|stop| *stop <= start)
+            .max(),
+        _ => obstacles.filter_map(|(((s, _), _), clearance)| s.checked_sub(clearance + length))
+            .chain(size.checked_sub(policy.wall_clearance + length))
+            .filter(|stop| *stop >= start)
+            .min(),
+    }.unwrap_or(start);
+    let mut moved = *p;
+    match horizontal {
+        true => moved.x = position,
+        false => moved.y = position,
+    }
+    moved
+}
+
+/// switches the terminal into raw mode and restores it when dropped
+struct RawTerminal {
+    saved: String,
+}
+
+impl RawTerminal {
+    fn enable() -> PuzzleResult<RawTerminal> {
+        let stty = |args: &[&str]| Command::new("stty").args(args).stdin(Stdio::inherit()).output()
+            .map_err(|e| PuzzleError::Terminal { reason: format!("could not run stty ({e})") });
+        let saved = String::from_utf8_lossy(&stty(&["-g"])?.stdout).trim().to_owned();
+        if saved.is_empty() {
+            return Err(PuzzleError::Terminal { reason: "stdin is not a terminal".to_owned() });
+        }
+        // reads return after 0.1 s without input, so a lone Esc is not mistaken for the start of an arrow key
+        stty(&["-icanon", "-echo", "min", "0", "time", "1"])?;
+        Ok(RawTerminal { saved })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = Command::new("stty").arg(&self.saved).stdin(Stdio::inherit()).status();
+    }
+}
+
+/// `None` if no key was pressed before the read timed out
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let mut buffer = [0u8; 1];
+    if input.read(&mut buffer)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(match buffer[0] {
+        b'\t' => Key::Tab,
+        0x1b => {
+            let mut sequence = [0u8; 2];
+            let mut length = 0;
+            while length < sequence.len() {
+                match input.read(&mut sequence[length..])? {
+                    0 => break,
+                    n => length += n,
+                }
+            }
+            match sequence[..length] {
+                [b'[', b'A'] => Key::Up,
+                [b'[', b'B'] => Key::Down,
+                [b'[', b'C'] => Key::Right,
+                [b'[', b'D'] => Key::Left,
+                _ => Key::Other,
+            }
+        }
+        c => Key::Char(c as char),
+    }))
+}
+
+/// run the editor until 'q' is pressed, the layout is loaded from and saved to `path`
+pub(crate) fn run_tui(storage: &ProgramStorage, path: &str, scale: Option<RecDimension>) -> PuzzleResult<()> {
+    let mut editor = LayoutEditor::new(storage, path, scale);
+    if fs::metadata(path).is_ok() {
+        editor.load()?;
+    }
+    let _terminal = RawTerminal::enable()?;
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    loop {
+        write!(stdout, "\x1b[2J\x1b[H{}", editor.render(true)).and_then(|_| stdout.flush())
+            .map_err(|e| PuzzleError::io("stdout", e))?;
+        let key = loop {
+            if let Some(key) = read_key(&mut stdin).map_err(|e| PuzzleError::io("stdin", e))? {
+                break key;
+            }
+        };
+        if !editor.handle_key(key) {
+            return Ok(());
+        }
+    }
+}
+
+#[test]
+fn test_layout_editor() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 2, 2)],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let path = std::env::temp_dir().join("holzpuzzle_tui_test.txt");
+    let mut editor = LayoutEditor::new(&storage, path.to_str().unwrap(), Some(1));
+
+    // both rectangles at (0, 0) collide
+    editor.handle_key(Key::Char('a'));
+    editor.handle_key(Key::Char('a'));
+    assert_eq!(editor.problems(), vec!["1 collides with 2"]);
+    // move the second one to the right, until it is out of bounds
+    for _ in 0..3 {
+        editor.handle_key(Key::Right);
+    }
+    assert_eq!(editor.problems(), vec!["2 is out of bounds"]);
+    editor.handle_key(Key::Char('u'));
+    assert!(editor.problems().is_empty());
+    assert_eq!(editor.render(false).lines().take(2).collect::<Vec<_>>(), vec![" 1 1 2 2", " 1 1 2 2"]);
+
+    editor.handle_key(Key::Char('w'));
+    editor.handle_key(Key::Char('x'));
+    assert_eq!(editor.placed.len(), 1);
+    editor.handle_key(Key::Char('o'));
+    assert_eq!(placement_to_string(&rects.resolution, &editor.placed), "1 2 2 0 0  2 2 2 2 0");
+}
+
+#[test]
+fn test_snap() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::rect::ContactPolicy;
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 6, 10),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 2, 2), Rectangle::new(3, 1, 1)],
+    );
+    let settings = Settings { contact_policy: ContactPolicy { piece_clearance: 1, wall_clearance: 0 }, ..Default::default() };
+    let storage = ProgramStorage::new(&rects, settings);
+    let mut editor = LayoutEditor::new(&storage, "", Some(1));
+    let place = |id, x, y| PlacedRectangle { rect: rects.available_block_map[&id], x, y };
+    editor.placed = vec![place(1, 7, 2), place(2, 1, 2), place(3, 4, 0)];
+    editor.selected = Some(1);
+    let position = |editor: &LayoutEditor| (editor.placed[1].x, editor.placed[1].y);
+
+    // to the right up to the clearance of the neighbour, not towards the upper left corner
+    editor.handle_key(Key::Right);
+    editor.handle_key(Key::Char('s'));
+    assert_eq!(position(&editor), (4, 2));
+    // down to the wall, the small piece above is not in the way
+    editor.handle_key(Key::Down);
+    editor.handle_key(Key::Char('s'));
+    assert_eq!(position(&editor), (4, 4));
+    editor.handle_key(Key::Up);
+    editor.handle_key(Key::Char('s'));
+    assert_eq!(position(&editor), (4, 2));
+    editor.handle_key(Key::Left);
+    editor.handle_key(Key::Char('s'));
+    assert_eq!(position(&editor), (0, 2));
+    assert!(editor.problems().is_empty());
+}
+
+#[test]
+fn test_read_key() {
+    // a lone Esc at the end must not wait for two more bytes
+    let mut input: &[u8] = b"\x1b[Ax\t\x1b";
+    let keys: Vec<Key> = std::iter::from_fn(|| read_key(&mut input).unwrap()).collect();
+    assert_eq!(keys, vec![Key::Up, Key::Char('x'), Key::Tab, Key::Other]);
+}
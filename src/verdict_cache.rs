@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+
+use itertools::Itertools;
+
+use crate::error::{PuzzleError, PuzzleResult};
+use crate::fingerprint::{Fingerprint, FingerprintValue};
+use crate::layer_solver::{LayerVerdict, LayoutKind};
+use crate::ProgramStorage;
+use crate::rect::{Combination, ContactPolicy, PlacedRectangle, RecDimension, Rectangle};
+
+/// a layout stored without ids: (height, width, x, y) for each rectangle
+pub(crate) type DimensionLayout = Vec<(RecDimension, RecDimension, RecDimension, RecDimension)>;
+
+/// verdicts of step3, stored on disk to be reused by later runs\
+/// a found layout and "proven infeasible" hold for every solver, which searches the same kind of layouts,\
+/// so they are keyed by the geometry (pieces and big rect) and the layout kind of the solver\
+/// "unknown" is just the result of the heuristic, so it is keyed by the geometry and the solver options\
+/// candidates, which ran out of budget, are not stored, a bigger budget may decide them
+#[derive(Default)]
+pub(crate) struct VerdictCache {
+    fits: BTreeMap<FingerprintValue, DimensionLayout>,
+    /// with the reason or bound, which proved it
+    infeasible: BTreeMap<FingerprintValue, String>,
+    /// with the reason, why the solver could not decide
+    unknown: BTreeMap<FingerprintValue, String>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl VerdictCache {
+    /// load cache from file, a missing file is an empty cache
+    pub(crate) fn from_file(filepath: &str) -> PuzzleResult<VerdictCache> {
+        let mut cache = VerdictCache::default();
+        let content = match fs::read_to_string(filepath) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(PuzzleError::io(filepath, e)),
+        };
+        for line in content.split('\n').filter(|l| !l.is_empty()) {
+            let mut parts = line.split(' ');
+            let kind = parts.next();
+            let key = parts.next().and_then(FingerprintValue::parse);
+            let reason = || line.splitn(3, ' ').nth(2).unwrap_or_default().to_owned();
+            match (kind, key) {
+                (Some("fits"), Some(key)) => {
+                    let layout: Option<DimensionLayout> = parts
+                        .map(|p| p.split(',').map(|v| v.parse::<RecDimension>().ok()).collect::<Option<Vec<_>>>())
+                        .map(|v| v.and_then(|v| if v.len() == 4 { Some((v[0], v[1], v[2], v[3])) } else { None }))
+                        .collect();
+                    match layout {
+                        Some(layout) => { cache.fits.insert(key, layout); }
+                        None => println!("Ignoring broken line in verdict cache {filepath}: {line}"),
+                    }
+                }
+                (Some("infeasible"), Some(key)) => { cache.infeasible.insert(key, reason()); }
+                (Some("unknown"), Some(key)) => { cache.unknown.insert(key, reason()); }
+                // older caches did not tell a proof from a heuristic miss, both had the key of the solver
+                (Some("nofit"), Some(key)) => { cache.unknown.insert(key, "does not fit (older cache)".to_owned()); }
+                _ => println!("Ignoring broken line in verdict cache {filepath}: {line}"),
+            }
+        }
+        Ok(cache)
+    }
+
+    /// store cache to file, sorted by key
+    pub(crate) fn to_file(&self, filepath: &str) -> PuzzleResult<()> {
+        fs::write(
+            filepath,
+            self.fits.iter()
+                .map(|(k, layout)| format!("fits {k} {}", layout.iter().map(|(h, w, x, y)| format!("{h},{w},{x},{y}")).join(" ")))
+                .chain(self.infeasible.iter().map(|(k, reason)| format!("infeasible {k} {reason}")))
+                .chain(self.unknown.iter().map(|(k, reason)| format!("unknown {k} {reason}")))
+                .join("\n"),
+        ).map_err(|e| PuzzleError::io(filepath, e))
+    }
+
+    /// look up a candidate, `None` if there is no stored verdict for it
+    pub(crate) fn lookup(&mut self, storage: &ProgramStorage, candidate: &Combination) -> Option<LayerVerdict> {
+        let result = if let Some(kind) = cacheable_kind(storage, candidate) {
+            let key = layout_key(storage, candidate, kind);
+            if let Some(layout) = self.fits.get(&key) {
+                layout_for_candidate(candidate, layout).map(|layout| LayerVerdict::Fits(layout, vec![]))
+            } else if let Some(reason) = self.infeasible.get(&key) {
+                Some(LayerVerdict::DoesNotFit(reason.clone()))
+            } else {
+                self.unknown.get(&solver_key(storage, candidate)).map(|reason| LayerVerdict::Unknown(reason.clone()))
+            }
+        } else {
+            None
+        };
+        match result {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        result
+    }
+
+    /// store the verdict for a candidate
+    pub(crate) fn insert(&mut self, storage: &ProgramStorage, candidate: &Combination, verdict: &LayerVerdict) {
+        let Some(kind) = cacheable_kind(storage, candidate) else {
+            return;
+        };
+        match verdict {
+            LayerVerdict::Fits(layout, _) => {
+                self.fits.insert(
+                    layout_key(storage, candidate, kind),
+                    layout.iter().map(|p| (p.rect.height, p.rect.width, p.x, p.y)).collect(),
+                );
+            }
+            LayerVerdict::DoesNotFit(reason) => { self.infeasible.insert(layout_key(storage, candidate, kind), one_line(reason)); }
+            LayerVerdict::Unknown(reason) => { self.unknown.insert(solver_key(storage, candidate), one_line(reason)); }
+            LayerVerdict::OutOfBudget(_) => {}
+        }
+    }
+}
+
+/// reasons are stored at the end of a line
+fn one_line(reason: &str) -> String {
+    reason.replace('\n', " ")
+}
+
+/// the layout kind of the solver, `None` if the verdict can not be cached\
+/// layouts are stored without ids, which does not work for pieces pinned to a position or orientation
+fn cacheable_kind(storage: &ProgramStorage, candidate: &Combination) -> Option<LayoutKind> {
+    match candidate.iter().all(|r| storage.rect_configuration.pin(r.id).is_none_or(|p| p.position.is_none() && p.rotated.is_none())) {
+        true => storage.settings.layer_solver().layout_kind(storage),
+        false => None,
+    }
+}
+
+/// key of the exact dimensions of a candidate and the container, ignoring ids and the order of the rectangles\
+/// a layout is only valid for the contact policy it was found with, so it is part of the geometry
+pub(crate) fn geometry_key(storage: &ProgramStorage, candidate: &Combination) -> FingerprintValue {
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.container(storage.rect_configuration);
+    let policy = &storage.settings.contact_policy;
+    if *policy != ContactPolicy::default() {
+        fingerprint.field("piece_clearance", policy.piece_clearance as u64)
+            .field("wall_clearance", policy.wall_clearance as u64);
+    }
+    for (a, b) in candidate.iter().filter(|r| r.rotatable).map(orientation_free_dimensions).sorted() {
+        fingerprint.field("rect", ((a as u64) << 32) | b as u64);
+    }
+    for (a, b) in candidate.iter().filter(|r| !r.rotatable).map(|r| (r.height, r.width)).sorted() {
+        fingerprint.field("fixed rect", ((a as u64) << 32) | b as u64);
+    }
+    FingerprintValue(std::hash::Hasher::finish(&fingerprint))
+}
+
+/// key of the geometry and the kind of layouts, a layout or a proof of infeasibility is valid for\
+/// guillotine layouts also depend on the kerf of the saw
+pub(crate) fn layout_key(storage: &ProgramStorage, candidate: &Combination, kind: LayoutKind) -> FingerprintValue {
+    let geometry = geometry_key(storage, candidate);
+    let LayoutKind::Guillotine(kerf) = kind else {
+        return geometry;
+    };
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.field("geometry", geometry.0)
+        .field("guillotine_kerf", kerf as u64);
+    FingerprintValue(std::hash::Hasher::finish(&fingerprint))
+}
+
+/// key of the geometry and all settings which change the result of the heuristic
+pub(crate) fn solver_key(storage: &ProgramStorage, candidate: &Combination) -> FingerprintValue {
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.field("geometry", geometry_key(storage, candidate).0)
+        .field("distance_between_rectangles", storage.settings.distance_between_rectangles as u64)
+        .field("guillotine_only", storage.settings.guillotine_only as u64);
+    for strategy in &storage.settings.placement_strategies {
+        std::hash::Hasher::write(&mut fingerprint, strategy.name().as_bytes());
+    }
+    std::hash::Hasher::write(&mut fingerprint, storage.settings.layer_solver().name().as_bytes());
+    FingerprintValue(std::hash::Hasher::finish(&fingerprint))
+}
+
+fn orientation_free_dimensions(rect: &Rectangle) -> (RecDimension, RecDimension) {
+    if rect.height >= rect.width { (rect.height, rect.width) } else { (rect.width, rect.height) }
+}
+
+/// map a stored layout back onto the rectangles of a candidate with the same dimensions\
+/// rectangles, which have to keep their orientation, get the places with exactly their dimensions first
+fn layout_for_candidate(candidate: &Combination, layout: &DimensionLayout) -> Option<Vec<PlacedRectangle>> {
+    let mut unused: Vec<&Rectangle> = candidate.iter().sorted_by_key(|r| r.rotatable).collect();
+    let mut placed = vec![];
+    for (height, width, x, y) in layout {
+        let position = unused.iter().position(|r| match r.rotatable {
+            true => orientation_free_dimensions(r) == orientation_free_dimensions(&Rectangle::new(0, *height, *width)),
+            false => (r.height, r.width) == (*height, *width),
+        })?;
+        let rect = unused.remove(position);
+        placed.push(PlacedRectangle { rect: if rect.height == *height { *rect } else { rect.rotate() }, x: *x, y: *y });
+    }
+    if unused.is_empty() { Some(placed) } else { None }
+}
+
+#[test]
+fn test_verdict_cache_roundtrip() {
+    use crate::data_configuration::RectConfiguration;
+    use crate::layer_solver::{ChainedSolver, GuillotineSolver, HeuristicSolver};
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![
+            Rectangle::new(1, 2, 2),
+            Rectangle::new(2, 2, 2),
+            Rectangle::new(3, 1, 3),
+        ],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let fitting: Combination = [1, 2].iter().map(|id| rects.available_block_map[id]).collect();
+    let not_fitting: Combination = [1, 3].iter().map(|id| rects.available_block_map[id]).collect();
+    let infeasible: Combination = [1, 2, 3].iter().map(|id| rects.available_block_map[id]).collect();
+    let layout = vec![
+        PlacedRectangle { rect: rects.available_block_map[&1], x: 0, y: 0 },
+        PlacedRectangle { rect: rects.available_block_map[&2], x: 2, y: 0 },
+    ];
+
+    let mut cache = VerdictCache::default();
+    cache.insert(&storage, &fitting, &LayerVerdict::Fits(layout, vec![]));
+    cache.insert(&storage, &not_fitting, &LayerVerdict::Unknown("no placement strategy found a layout".to_owned()));
+    cache.insert(&storage, &infeasible, &LayerVerdict::DoesNotFit("area 11 > 8".to_owned()));
+    let path = std::env::temp_dir().join("holzpuzzle_verdict_cache_test.txt");
+    cache.to_file(path.to_str().unwrap()).unwrap();
+    let mut cache = VerdictCache::from_file(path.to_str().unwrap()).unwrap();
+
+    let found = cache.lookup(&storage, &fitting).unwrap().layout().unwrap();
+    assert_eq!(found.iter().map(|p| (p.rect.id, p.x)).collect::<Vec<_>>(), vec![(1, 0), (2, 2)]);
+    assert!(matches!(cache.lookup(&storage, &not_fitting), Some(LayerVerdict::Unknown(reason)) if reason == "no placement strategy found a layout"));
+    assert!(matches!(cache.lookup(&storage, &infeasible), Some(LayerVerdict::DoesNotFit(reason)) if reason == "area 11 > 8"));
+    // nothing is stored for candidates, which ran out of budget
+    cache.insert(&storage, &not_fitting, &LayerVerdict::OutOfBudget("time limit of 1 s reached".to_owned()));
+    assert!(matches!(cache.lookup(&storage, &not_fitting), Some(LayerVerdict::Unknown(_))));
+
+    // another distance invalidates "unknown", but not the found layout and the proof
+    let storage = ProgramStorage::new(&rects, Settings { distance_between_rectangles: 3, ..Default::default() });
+    assert!(cache.lookup(&storage, &fitting).unwrap().layout().is_some());
+    assert!(cache.lookup(&storage, &not_fitting).is_none());
+    assert!(matches!(cache.lookup(&storage, &infeasible), Some(LayerVerdict::DoesNotFit(_))));
+    // for guillotine layouts the distance is the kerf, so the proof depends on it
+    let storage = ProgramStorage::new(&rects, Settings { guillotine_only: true, ..Default::default() });
+    assert!(cache.lookup(&storage, &infeasible).is_none());
+
+    // layouts and proofs of guillotine solvers do not answer for other solvers, and the reverse
+    let heuristic = ProgramStorage::new(&rects, Settings::default());
+    let guillotine = ProgramStorage::new(&rects, Settings { layer_solver: &GuillotineSolver, ..Default::default() });
+    let guillotine_only = ProgramStorage::new(&rects, Settings { guillotine_only: true, ..Default::default() });
+    let layout = cache.lookup(&heuristic, &fitting).unwrap().layout().unwrap();
+    let mut cache = VerdictCache::default();
+    cache.insert(&guillotine_only, &fitting, &LayerVerdict::Fits(layout.clone(), vec![]));
+    cache.insert(&guillotine, &not_fitting, &LayerVerdict::DoesNotFit("no guillotine packing exists".to_owned()));
+    assert!(cache.lookup(&heuristic, &fitting).is_none());
+    assert!(cache.lookup(&heuristic, &not_fitting).is_none());
+    assert!(cache.lookup(&guillotine, &fitting).unwrap().layout().is_some());
+    assert!(matches!(cache.lookup(&guillotine_only, &not_fitting), Some(LayerVerdict::DoesNotFit(_))));
+    let mut cache = VerdictCache::default();
+    cache.insert(&heuristic, &fitting, &LayerVerdict::Fits(layout, vec![]));
+    cache.insert(&heuristic, &infeasible, &LayerVerdict::DoesNotFit("area 11 > 8".to_owned()));
+    assert!(cache.lookup(&guillotine_only, &fitting).is_none());
+    assert!(cache.lookup(&guillotine, &infeasible).is_none());
+    // a chain of solvers of different kinds is not cached
+    let mixed = ProgramStorage::new(&rects, Settings { layer_solver: &ChainedSolver(&[&HeuristicSolver, &GuillotineSolver]), ..Default::default() });
+    assert!(cache.lookup(&mixed, &fitting).is_none());
+
+    // "nofit" of older caches is not a proof
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    fs::write(&path, format!("nofit {}", solver_key(&storage, &infeasible))).unwrap();
+    let mut cache = VerdictCache::from_file(path.to_str().unwrap()).unwrap();
+    assert!(matches!(cache.lookup(&storage, &infeasible), Some(LayerVerdict::Unknown(_))));
+}
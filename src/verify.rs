@@ -0,0 +1,144 @@
+use std::fmt::{Display, Formatter};
+
+use crate::ProgramStorage;
+use crate::rect::{Combination, PlacedRectangle, RecId};
+use crate::text_render::render_layout;
+
+/// width of the layouts printed by the verifier
+const REPORT_WIDTH: usize = 80;
+
+/// something that makes a layout invalid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LayoutProblem {
+    Collision(RecId, RecId),
+    /// not overlapping, but closer than the piece clearance of the contact policy
+    TooClose(RecId, RecId),
+    OutOfBounds(RecId),
+    /// inside, but closer to a wall or blocked zone than the wall clearance of the contact policy
+    TooCloseToWall(RecId),
+    /// part of the candidate, but not placed
+    Missing(RecId),
+    /// placed, but not part of the candidate (or placed twice)
+    Unexpected(RecId),
+    /// placed with dimensions, that differ from the rectangle with this id
+    WrongSize(RecId),
+    /// rotated, but the rectangle has to keep its orientation
+    Rotated(RecId),
+    /// not at the position or in the orientation of its pin
+    PinViolated(RecId),
+}
+
+impl Display for LayoutProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutProblem::Collision(a, b) => write!(f, "{a} collides with {b}"),
+            LayoutProblem::TooClose(a, b) => write!(f, "{a} is too close to {b}"),
+            LayoutProblem::OutOfBounds(id) => write!(f, "{id} is out of bounds"),
+            LayoutProblem::TooCloseToWall(id) => write!(f, "{id} is too close to a wall"),
+            LayoutProblem::Missing(id) => write!(f, "{id} is missing"),
+            LayoutProblem::Unexpected(id) => write!(f, "{id} is not part of the candidate"),
+            LayoutProblem::WrongSize(id) => write!(f, "{id} has the wrong size"),
+            LayoutProblem::Rotated(id) => write!(f, "{id} must not be rotated"),
+            LayoutProblem::PinViolated(id) => write!(f, "{id} is not placed as pinned"),
+        }
+    }
+}
+
+/// find collisions, rectangles outside of the big rect, violations of the contact policy and of the pins
+pub(crate) fn find_problems(storage: &ProgramStorage, layout: &[PlacedRectangle]) -> Vec<LayoutProblem> {
+    let configuration = storage.rect_configuration;
+    let policy = &storage.settings.contact_policy;
+    let mut problems = vec![];
+    for (i, p) in layout.iter().enumerate() {
+        for other in layout.iter().skip(i + 1) {
+            if p.check_collision(other) {
+                problems.push(LayoutProblem::Collision(p.rect.id, other.rect.id));
+            } else if p.check_clearance(other, policy.piece_clearance) {
+                problems.push(LayoutProblem::TooClose(p.rect.id, other.rect.id));
+            }
+        }
+        if !p.check_bounds_with_clearance(storage, 0) {
+            problems.push(LayoutProblem::OutOfBounds(p.rect.id));
+        } else if !p.check_bounds(storage) {
+            problems.push(LayoutProblem::TooCloseToWall(p.rect.id));
+        }
+        if let (Some(pin), Some(r)) = (configuration.pin(p.rect.id), configuration.available_block_map.get(&p.rect.id)) {
+            // a square piece is in both orientations
+            let as_given = p.rect.height == r.height && p.rect.width == r.width;
+            let rotated = p.rect.height == r.width && p.rect.width == r.height;
+            if pin.position.is_some_and(|position| position != (p.x, p.y)) || pin.rotated.is_some_and(|pinned| if pinned { !rotated } else { !as_given }) {
+                problems.push(LayoutProblem::PinViolated(p.rect.id));
+            }
+        }
+    }
+    problems
+}
+
+/// check that a layout places exactly the rectangles of the candidate, without collisions and inside the big rect
+pub(crate) fn verify_layout(storage: &ProgramStorage, candidate: &Combination, layout: &[PlacedRectangle]) -> Vec<LayoutProblem> {
+    let mut problems = find_problems(storage, layout);
+    for (i, p) in layout.iter().enumerate() {
+        match candidate.iter().find(|r| r.id == p.rect.id) {
+            Some(_) if layout.iter().take(i).any(|o| o.rect.id == p.rect.id) => problems.push(LayoutProblem::Unexpected(p.rect.id)),
+            Some(r) if !(r.height == p.rect.height && r.width == p.rect.width || r.height == p.rect.width && r.width == p.rect.height) =>
+                problems.push(LayoutProblem::WrongSize(p.rect.id)),
+            Some(r) if !r.rotatable && r.height != p.rect.height => problems.push(LayoutProblem::Rotated(p.rect.id)),
+            Some(_) => {}
+            None => problems.push(LayoutProblem::Unexpected(p.rect.id)),
+        }
+    }
+    for r in candidate {
+        if !layout.iter().any(|p| p.rect.id == r.id) {
+            problems.push(LayoutProblem::Missing(r.id));
+        }
+    }
+    problems
+}
+
+/// verify a layout and print the problems together with a drawing of the layout\
+/// returns true if the layout is valid
+pub(crate) fn verify_and_report(storage: &ProgramStorage, candidate: &Combination, layout: &[PlacedRectangle]) -> bool {
+    let problems = verify_layout(storage, candidate, layout);
+    if problems.is_empty() {
+        return true;
+    }
+    println!("INVALID LAYOUT: {}", problems.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(", "));
+    println!("{}", render_layout(storage.rect_configuration, layout, REPORT_WIDTH));
+    false
+}
+
+#[test]
+fn test_verify_layout() {
+    use crate::data_configuration::{Pin, RectConfiguration};
+    use crate::rect::Rectangle;
+    use crate::Settings;
+
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 2, 2), Rectangle::new(3, 1, 1)],
+    );
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = [1, 2].iter().map(|id| rects.available_block_map[id]).collect();
+    let placed = |id: RecId, x, y| PlacedRectangle { rect: rects.available_block_map[&id], x, y };
+
+    assert!(verify_layout(&storage, &candidate, &[placed(1, 0, 0), placed(2, 2, 0)]).is_empty());
+    assert_eq!(verify_layout(&storage, &candidate, &[placed(1, 0, 0), placed(2, 1, 0)]), vec![LayoutProblem::Collision(1, 2)]);
+    assert_eq!(verify_layout(&storage, &candidate, &[placed(1, 0, 0), placed(2, 3, 0)]), vec![LayoutProblem::OutOfBounds(2)]);
+    assert_eq!(verify_layout(&storage, &candidate, &[placed(1, 0, 0), placed(3, 2, 0)]), vec![LayoutProblem::Unexpected(3), LayoutProblem::Missing(2)]);
+
+    // a piece pinned to the right, another one pinned to stay lying
+    let rects = RectConfiguration::new(
+        Rectangle::new(-1, 2, 4),
+        vec![Rectangle::new(1, 2, 2), Rectangle::new(2, 1, 2)],
+    ).with_pin(1, Pin { position: Some((2, 0)), ..Default::default() }).unwrap()
+        .with_pin(2, Pin { rotated: Some(false), ..Default::default() }).unwrap();
+    let storage = ProgramStorage::new(&rects, Settings::default());
+    let candidate: Combination = rects.available_blocks.iter().copied().collect();
+    let placed = |id: RecId, x, y, rotated: bool| {
+        let rect = rects.available_block_map[&id];
+        PlacedRectangle { rect: if rotated { rect.rotate() } else { rect }, x, y }
+    };
+    assert!(verify_layout(&storage, &candidate, &[placed(1, 2, 0, false), placed(2, 0, 0, false)]).is_empty());
+    assert_eq!(verify_layout(&storage, &candidate, &[placed(1, 0, 0, false), placed(2, 2, 0, false)]), vec![LayoutProblem::PinViolated(1)]);
+    assert_eq!(verify_layout(&storage, &candidate, &[placed(1, 2, 0, false), placed(2, 0, 0, true)]), vec![LayoutProblem::PinViolated(2)]);
+}